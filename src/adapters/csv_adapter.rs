@@ -1,24 +1,38 @@
-use std::{error::Error, fs::File, io::BufReader};
+use std::io::{self, BufRead, Seek, SeekFrom};
+use std::sync::Arc;
 
-use csv::StringRecord;
+use csv::{ByteRecord, Position, StringRecord};
 use serde_json::{Map, Value};
 
-use crate::Readable;
+use crate::{
+    adapters::utils::{
+        column_utils::select_columns,
+        file_utils::{open_input, Input},
+    },
+    count_records,
+    index::{find_checkpoint, IndexCache},
+    last_records, Checkpoint, CheckpointIter, Readable, ReaderError, RecordIter, Source,
+};
 
 #[derive(Debug)]
-pub struct CsvAdapter {}
+pub struct CsvAdapter {
+    pub(crate) index_cache: Arc<IndexCache>,
+}
 
 impl Readable for CsvAdapter {
-    fn read(
+    fn stream<'a>(
         &self,
-        file_path: &String,
-        config: &crate::Config,
-        from: Option<u64>,
-        len: u64,
-    ) -> Result<Vec<Map<String, Value>>, Box<dyn Error>> {
-        // Create file reader
-        let file = File::open(file_path)?;
-        let buf_reader = BufReader::new(file);
+        source: Source,
+        config: &'a crate::Config,
+        from: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let checkpoint = find_checkpoint(self, &self.index_cache, &source, config, from)?;
+
+        // Checkpoints are only found for paths, which can be opened again
+        let header_source = checkpoint.and_then(|_| source.try_clone());
+
+        // Open source, decompressed if needed
+        let buf_reader = open_input(source, config.file_compression)?;
 
         // Create csv reader
         let mut reader = csv::Reader::from_reader(buf_reader);
@@ -29,29 +43,156 @@ impl Readable for CsvAdapter {
             // Set headers to default
             // This marks first entry as data rather than header
             reader.set_headers(StringRecord::from(config.default_columns.clone()));
+        } else if let Some(header_source) = header_source {
+            // Reader starts at checkpoint, so headers are read from start of file
+            let buf_reader = open_input(header_source, config.file_compression)?;
+            let headers = csv::Reader::from_reader(buf_reader).headers()?.clone();
+
+            reader.set_headers(headers);
         }
 
-        let columns = reader.headers()?.clone();
+        let columns = select_columns(reader.headers()?.iter().map(String::from).collect(), config)?;
+
+        // Start at closest indexed record before from
+        let skip = match checkpoint {
+            Some(checkpoint) => {
+                let mut position = Position::new();
+                position
+                    .set_byte(checkpoint.offset)
+                    .set_line(checkpoint.inner)
+                    .set_record(checkpoint.record + u64::from(!config.use_default_columns));
+
+                reader.seek(position)?;
+
+                from - checkpoint.record
+            }
+            None => from,
+        };
+
+        // Iter through records after from and build values lazily
+        let records = reader.into_records().skip(skip as usize);
+
+        Ok(Box::new(
+            records.map(move |record| Ok(to_map(&columns, &record?))),
+        ))
+    }
+
+    fn stream_last<'a>(
+        &self,
+        source: Source,
+        config: &'a crate::Config,
+        n: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let mut buf_reader = open_input(source, config.file_compression)?;
 
-        // Set from and to
-        // min ensures it is within bounds
-        let from = from.unwrap_or(0);
+        // Quoted fields may span lines and blank lines are not records
+        // Such tails are read from start instead
+        let offset = match buf_reader.seek_last_lines(n)? {
+            Some(offset) if is_plain_tail(&mut buf_reader)? => Some(offset),
+            _ => None,
+        };
 
-        let mut data = vec![];
+        buf_reader.seek(SeekFrom::Start(0))?;
 
-        // Iter through slice of data and collect values
-        for record in reader.records().skip(from as usize).take(len as usize) {
-            let record = record?;
+        let mut reader = csv::Reader::from_reader(buf_reader);
 
-            let mut hashmap = Map::new();
+        if config.use_default_columns {
+            reader.set_headers(StringRecord::from(config.default_columns.clone()));
+        }
 
-            for (key, value) in columns.iter().zip(record.iter()) {
-                hashmap.insert(key.to_string(), Value::from(value));
+        let columns = select_columns(reader.headers()?.iter().map(String::from).collect(), config)?;
+        let header_end = match config.use_default_columns {
+            true => 0,
+            false => reader.position().byte(),
+        };
+
+        match offset {
+            // Lines are found from end of file, their records are counted from there
+            Some(offset) if offset >= header_end => {
+                let mut position = Position::new();
+                position.set_byte(offset);
+
+                reader.seek(position)?;
+
+                Ok(Box::new(
+                    reader
+                        .into_records()
+                        .map(move |record| Ok(to_map(&columns, &record?))),
+                ))
             }
+            _ => Ok(last_records(
+                Box::new(
+                    reader
+                        .into_records()
+                        .map(move |record| Ok(to_map(&columns, &record?))),
+                ),
+                n,
+            )),
+        }
+    }
 
-            data.push(hashmap);
+    fn count(&self, source: Source, config: &crate::Config) -> Result<u64, ReaderError> {
+        // Records are walked without decoding their values
+        count_records(self.scan(source, config)?.into_iter().flatten())
+    }
+
+    fn scan<'a>(
+        &self,
+        source: Source,
+        config: &'a crate::Config,
+    ) -> Result<Option<CheckpointIter<'a>>, ReaderError> {
+        let mut reader = csv::Reader::from_reader(open_input(source, config.file_compression)?);
+
+        // First entry is data when default columns are used
+        if config.use_default_columns {
+            reader.set_headers(StringRecord::from(config.default_columns.clone()));
+        }
+
+        let mut record = ByteRecord::new();
+        let mut index = 0;
+
+        Ok(Some(Box::new(std::iter::from_fn(move || {
+            let read = reader.read_byte_record(&mut record);
+
+            let checkpoint = match read {
+                Ok(false) => return None,
+                Ok(true) => Ok(Checkpoint {
+                    record: index,
+                    offset: record.position().map_or(0, Position::byte),
+                    inner: record.position().map_or(0, Position::line),
+                }),
+                Err(e) => Err(e.into()),
+            };
+
+            index += 1;
+
+            Some(checkpoint)
+        }))))
+    }
+}
+
+/// Pairs values of record with selected columns
+fn to_map(columns: &[Option<String>], record: &StringRecord) -> Map<String, Value> {
+    let mut hashmap = Map::new();
+
+    for (key, value) in columns.iter().zip(record.iter()) {
+        if let Some(key) = key {
+            hashmap.insert(key.clone(), Value::from(value));
         }
+    }
 
-        Ok(data)
+    hashmap
+}
+
+/// True if every line after current position is a record of it's own
+fn is_plain_tail(buf_reader: &mut Input) -> io::Result<bool> {
+    for line in buf_reader.split(b'\n') {
+        let line = line?;
+
+        if line.contains(&b'"') || line.strip_suffix(b"\r").unwrap_or(&line).is_empty() {
+            return Ok(false);
+        }
     }
+
+    Ok(true)
 }
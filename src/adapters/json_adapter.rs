@@ -1,35 +1,80 @@
-use std::{error::Error, fs::File, io::BufReader};
+use std::io::{Seek, SeekFrom};
+use std::sync::Arc;
 
+use serde::de::IgnoredAny;
 use serde_json::{Map, Value};
 
-use crate::Readable;
+use crate::{
+    adapters::utils::{
+        column_utils::Projection,
+        file_utils::open_input,
+        json_utils::{element_checkpoints, JsonArrayIter, ObjectRecord},
+    },
+    count_records,
+    index::{find_checkpoint, IndexCache},
+    CheckpointIter, Location, Readable, ReaderError, RecordIter, Source,
+};
 
 #[derive(Debug)]
-pub struct JsonAdapter {}
+pub struct JsonAdapter {
+    pub(crate) index_cache: Arc<IndexCache>,
+}
 
 impl Readable for JsonAdapter {
-    fn read(
+    fn stream<'a>(
         &self,
-        file_path: &String,
-        _config: &crate::Config,
-        from: Option<u64>,
-        len: u64,
-    ) -> Result<Vec<Map<String, Value>>, Box<dyn Error>> {
-        // Create file reader
-        let file = File::open(file_path)?;
-        let buf_reader = BufReader::new(file);
-
-        // Create key value pair from json data
-        let data: Vec<Map<String, Value>> = serde_json::from_reader(buf_reader)?;
-
-        // Set from and to
-        // min ensures it is within bounds
-        let length = data.len() as u64;
-        let from = from.unwrap_or(0).min(length);
-        let to = (from + len).min(length);
-
-        let data = data[from as usize..to as usize].iter().map(|i| i.clone()).collect::<Vec<_>>();
-
-        Ok(data)
+        source: Source,
+        config: &'a crate::Config,
+        from: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let checkpoint = find_checkpoint(self, &self.index_cache, &source, config, from)?;
+
+        // Open source, decompressed if needed
+        let mut buf_reader = open_input(source, config.file_compression)?;
+
+        // Decode objects of json array one at a time
+        // Starting at closest indexed element before from
+        let mut values = match checkpoint {
+            Some(checkpoint) => {
+                buf_reader.seek(SeekFrom::Start(checkpoint.offset))?;
+
+                JsonArrayIter::<_, Map<String, Value>>::resume(
+                    buf_reader,
+                    Location {
+                        offset: checkpoint.offset,
+                        position: checkpoint.record,
+                    },
+                )
+            }
+            None => JsonArrayIter::new(buf_reader),
+        };
+
+        // Skip till from without building values
+        values.skip_elements(from - values.location().position)?;
+
+        // Values of unselected keys are not built
+        let projection = Projection::new(config);
+
+        Ok(Box::new(std::iter::from_fn(move || {
+            values.next_seed(ObjectRecord(projection.as_ref()))
+        })))
+    }
+
+    fn count(&self, source: Source, config: &crate::Config) -> Result<u64, ReaderError> {
+        // Records are walked without decoding their values
+        count_records(self.scan(source, config)?.into_iter().flatten())
+    }
+
+    fn scan<'a>(
+        &self,
+        source: Source,
+        config: &'a crate::Config,
+    ) -> Result<Option<CheckpointIter<'a>>, ReaderError> {
+        let buf_reader = open_input(source, config.file_compression)?;
+
+        Ok(Some(element_checkpoints(
+            JsonArrayIter::<_, IgnoredAny>::new(buf_reader),
+            0,
+        )))
     }
 }
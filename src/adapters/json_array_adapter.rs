@@ -1,65 +1,127 @@
-use std::{error::Error, fs::File, io::BufReader};
+use std::io::{BufRead, Seek, SeekFrom};
+use std::sync::Arc;
 
-use serde_json::{Map, Value};
+use serde::de::IgnoredAny;
+use serde_json::Value;
 
-use crate::Readable;
+use crate::{
+    adapters::utils::{
+        column_utils::select_columns,
+        file_utils::open_input,
+        json_utils::{element_checkpoints, ArrayRecord, JsonArrayIter},
+    },
+    count_records,
+    index::{find_checkpoint, IndexCache},
+    CheckpointIter, Config, Location, Readable, ReaderError, RecordIter, Source,
+};
 
 #[derive(Debug)]
-pub struct JsonArrayAdapter {}
+pub struct JsonArrayAdapter {
+    pub(crate) index_cache: Arc<IndexCache>,
+}
 
 impl Readable for JsonArrayAdapter {
-    fn read(
+    fn stream<'a>(
         &self,
-        file_path: &String,
-        config: &crate::Config,
-        from: Option<u64>,
-        len: u64,
-    ) -> Result<Vec<Map<String, Value>>, Box<dyn Error>> {
-        // Create file reader
-        let file = File::open(file_path)?;
-        let buf_reader = BufReader::new(file);
-
-        // Read data using buffer reader
-        let values: Vec<Vec<Value>> = serde_json::from_reader(buf_reader)?;
-
-        // Set from and to
-        // min ensures it is within bounds
-        let length = values.len() as u64;
-        let mut from = from.unwrap_or(0).min(length);
-        let mut to = (from + len).min(length);
-
-        // Get first object
-        let columns: Vec<String> = if config.use_default_columns {
-            config.default_columns.clone()
-        } else {
-            // Increment from & to because first val is header
-            from += 1;
-            from = from.min(length);
-
-            to += 1;
-            to = to.min(length);
-
-            values
-                .get(0)
-                .ok_or("Empty data")?
-                .iter()
-                .map(|i| i.as_str().unwrap().to_string())
-                .collect()
-        };
+        source: Source,
+        config: &'a crate::Config,
+        from: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let checkpoint = find_checkpoint(self, &self.index_cache, &source, config, from)?;
+
+        // First array is header unless default columns are used
+        let header_rows = u64::from(!config.use_default_columns);
+
+        let (columns, mut values) = match (checkpoint, source.try_clone()) {
+            // Checkpoints are only found for paths, which can be opened again
+            // Header is read from start of file
+            (Some(checkpoint), Some(header_source)) => {
+                let buf_reader = open_input(header_source, config.file_compression)?;
+                let columns = read_columns(&mut JsonArrayIter::new(buf_reader), config)?;
+
+                let mut buf_reader = open_input(source, config.file_compression)?;
+                buf_reader.seek(SeekFrom::Start(checkpoint.offset))?;
+
+                let values = JsonArrayIter::resume(
+                    buf_reader,
+                    Location {
+                        offset: checkpoint.offset,
+                        position: checkpoint.record + header_rows,
+                    },
+                );
 
-        let mut data = vec![];
+                (columns, values)
+            }
+            _ => {
+                let buf_reader = open_input(source, config.file_compression)?;
 
-        // Collect data from slice
-        values[from as usize..to as usize].into_iter().for_each(|val| {
-            let mut hashmap = Map::new();
+                // Decode arrays of json array one at a time
+                let mut values = JsonArrayIter::new(buf_reader);
 
-            for i in 0..val.len() {
-                hashmap.insert(columns[i].clone(), Value::from(val[i].clone()));
+                (read_columns(&mut values, config)?, values)
             }
+        };
+
+        // Skip till from without building values
+        values.skip_elements(from + header_rows - values.location().position)?;
+
+        // Values of unselected columns are not built
+        let columns = select_columns(columns, config)?;
+
+        // Collect data for each array
+        Ok(Box::new(std::iter::from_fn(move || {
+            values.next_seed(ArrayRecord(&columns))
+        })))
+    }
 
-            data.push(hashmap);
-        });
+    fn count(&self, source: Source, config: &Config) -> Result<u64, ReaderError> {
+        // Records are walked without decoding their values
+        count_records(self.scan(source, config)?.into_iter().flatten())
+    }
+
+    fn scan<'a>(
+        &self,
+        source: Source,
+        config: &'a Config,
+    ) -> Result<Option<CheckpointIter<'a>>, ReaderError> {
+        let buf_reader = open_input(source, config.file_compression)?;
+
+        let mut values = JsonArrayIter::<_, IgnoredAny>::new(buf_reader);
+
+        // Header is not a record
+        let header_rows = u64::from(!config.use_default_columns);
+
+        values.skip_elements(header_rows)?;
+
+        Ok(Some(element_checkpoints(values, header_rows)))
+    }
+}
+
+/// Columns from config or first array of file
+fn read_columns<R: BufRead>(
+    values: &mut JsonArrayIter<R, Vec<Value>>,
+    config: &Config,
+) -> Result<Vec<String>, ReaderError> {
+    if config.use_default_columns {
+        return Ok(config.default_columns.clone());
+    }
 
-        Ok(data)
+    // First val is header
+    match values.next() {
+        Some(header) => header?
+            .iter()
+            .map(|i| {
+                i.as_str()
+                    .map(|i| i.to_string())
+                    .ok_or_else(|| ReaderError::InvalidRecord {
+                        message: format!("Invalid column name {i}"),
+                        at: Default::default(),
+                    })
+            })
+            .collect(),
+        None => Err(ReaderError::InvalidRecord {
+            message: "Empty data".to_string(),
+            at: Default::default(),
+        }),
     }
 }
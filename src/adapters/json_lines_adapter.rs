@@ -1,54 +1,227 @@
-use std::{
-    error::Error,
-    fs::File,
-    io::{BufRead, BufReader, Seek, SeekFrom},
-};
+use std::io::{BufRead, Seek, SeekFrom};
+use std::sync::Arc;
 
 use serde_json::{Map, Value};
 
-use crate::Readable;
+use crate::{
+    adapters::utils::{
+        column_utils::Projection,
+        file_utils::{open_input, Input},
+        json_utils::ObjectRecord,
+    },
+    index::{find_checkpoint, IndexCache},
+    last_records, Checkpoint, CheckpointIter, Location, Readable, ReaderError, RecordIter, Source,
+};
 
 #[derive(Debug)]
-pub struct JsonLineAdapter {}
+pub struct JsonLineAdapter {
+    pub(crate) index_cache: Arc<IndexCache>,
+}
 
 impl Readable for JsonLineAdapter {
-    fn read(
+    fn stream<'a>(
+        &self,
+        source: Source,
+        config: &'a crate::Config,
+        from: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let checkpoint = find_checkpoint(self, &self.index_cache, &source, config, from)?;
+
+        // Open source, decompressed if needed
+        let mut buf_reader = open_input(source, config.file_compression)?;
+
+        // Start at closest indexed line before from
+        let checkpoint = match checkpoint {
+            Some(checkpoint) => {
+                buf_reader.seek(SeekFrom::Start(checkpoint.offset))?;
+                checkpoint
+            }
+            None => Checkpoint::default(),
+        };
+
+        let mut lines = JsonLines {
+            buf_reader,
+            offset: checkpoint.offset,
+            position: checkpoint.record,
+            projection: Projection::new(config),
+        };
+
+        // Skip till from, remaining lines are decoded when iterated
+        for _ in checkpoint.record..from {
+            if lines.next_line()?.is_none() {
+                break;
+            }
+        }
+
+        Ok(Box::new(lines))
+    }
+
+    fn stream_last<'a>(
+        &self,
+        source: Source,
+        config: &'a crate::Config,
+        n: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let mut buf_reader = open_input(source, config.file_compression)?;
+
+        // Lines are found from end of file, their records are counted from there
+        // Blank lines are not records, such tails are read from start instead
+        let offset = match buf_reader.seek_last_lines(n)? {
+            Some(offset) if !has_blank_line(&mut buf_reader)? => Some(offset),
+            _ => None,
+        };
+
+        match offset {
+            Some(offset) => {
+                buf_reader.seek(SeekFrom::Start(offset))?;
+
+                Ok(Box::new(JsonLines {
+                    buf_reader,
+                    offset,
+                    position: 0,
+                    projection: Projection::new(config),
+                }))
+            }
+            None => {
+                buf_reader.seek(SeekFrom::Start(0))?;
+
+                Ok(last_records(
+                    Box::new(JsonLines {
+                        buf_reader,
+                        offset: 0,
+                        position: 0,
+                        projection: Projection::new(config),
+                    }),
+                    n,
+                ))
+            }
+        }
+    }
+
+    fn count(&self, source: Source, config: &crate::Config) -> Result<u64, ReaderError> {
+        let mut buf_reader = open_input(source, config.file_compression)?;
+
+        // Every line with content is a record, last one may not end with a newline
+        let mut count = 0;
+        let mut content = false;
+
+        loop {
+            let buf = buf_reader.fill_buf()?;
+
+            if buf.is_empty() {
+                break;
+            }
+
+            for byte in buf {
+                if *byte == b'\n' {
+                    count += u64::from(content);
+                    content = false;
+                } else if !byte.is_ascii_whitespace() {
+                    content = true;
+                }
+            }
+
+            let n = buf.len();
+            buf_reader.consume(n);
+        }
+
+        Ok(count + u64::from(content))
+    }
+
+    fn scan<'a>(
         &self,
-        file_path: &String,
-        _config: &crate::Config,
-        from: Option<u64>,
-        len: u64,
-    ) -> Result<Vec<Map<String, Value>>, Box<dyn Error>> {
-        // Create file reader with BufReader
-        let file = File::open(&file_path)?;
-        let mut buf_reader = BufReader::new(file);
+        source: Source,
+        config: &'a crate::Config,
+    ) -> Result<Option<CheckpointIter<'a>>, ReaderError> {
+        let mut lines = JsonLines {
+            buf_reader: open_input(source, config.file_compression)?,
+            offset: 0,
+            position: 0,
+            projection: None,
+        };
+
+        Ok(Some(Box::new(std::iter::from_fn(move || {
+            let line = lines.next_line().transpose()?;
+
+            Some(line.map(|(_, at)| Checkpoint {
+                record: at.position,
+                offset: at.offset,
+                inner: 0,
+            }))
+        }))))
+    }
+}
 
-        // Create buffer reader lines iterator
-        let mut buf = String::new();
-        buf_reader.read_line(&mut buf)?;
+/// Decodes one json object per line
+/// Keeps track of line start for errors
+struct JsonLines {
+    buf_reader: Input,
+    offset: u64,
+    position: u64,
+    /// Values of unselected keys are not built
+    projection: Option<Projection>,
+}
 
-        // Reset reader to 0 after reading first line
-        buf_reader.seek(SeekFrom::Start(0))?;
+impl JsonLines {
+    /// Returns next line with it's location, blank lines are skipped
+    fn next_line(&mut self) -> Result<Option<(String, Location)>, ReaderError> {
+        let mut line = String::new();
 
-        // Set from and to
-        let from = from.unwrap_or(0);
+        loop {
+            let at = Location {
+                offset: self.offset,
+                position: self.position,
+            };
 
-        // Create iter of lines
-        let lines = buf_reader.lines();
+            let n = self.buf_reader.read_line(&mut line)?;
 
-        // Iterate through lines and collect data
-        // Skip from lines and take (to - from) lines
-        let mut data = vec![];
+            if n == 0 {
+                return Ok(None);
+            }
 
-        for line in lines.skip(from as usize).take(len as usize) {
-            if let Ok(line) = line {
-                // Decode each line as json
-                let json_obj = serde_json::from_str::<Map<String, Value>>(&line)?;
+            self.offset += n as u64;
 
-                data.push(json_obj);
+            if line.bytes().all(|byte| byte.is_ascii_whitespace()) {
+                line.clear();
+                continue;
             }
+
+            self.position += 1;
+
+            return Ok(Some((line, at)));
         }
+    }
+}
+
+/// True if any line after current position is blank
+fn has_blank_line(buf_reader: &mut Input) -> std::io::Result<bool> {
+    for line in buf_reader.split(b'\n') {
+        if line?.iter().all(u8::is_ascii_whitespace) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+impl Iterator for JsonLines {
+    type Item = Result<Map<String, Value>, ReaderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line, at) = match self.next_line() {
+            Ok(Some(line)) => line,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
 
-        Ok(data)
+        // Decode each line as json
+        Some(
+            ObjectRecord(self.projection.as_ref())
+                .parse(&line)
+                .map_err(|e| ReaderError::InvalidRecord {
+                    message: e.to_string(),
+                    at,
+                }),
+        )
     }
 }
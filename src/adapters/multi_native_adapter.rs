@@ -1,246 +1,1247 @@
 use std::{
-    error::Error,
-    fs::File,
-    io::{BufReader, Read},
-    vec,
+    collections::HashMap,
+    io::{Seek, SeekFrom},
+    mem,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
 };
 
 use crate::{
     adapters::utils::{
-        byte_utils::{col_from_buf, get_buffer_slice},
-        column_utils::get_len_from_columns,
+        byte_utils::{col_from_buf, column_at, get_buffer_slice, read_columns, read_full, Layout},
+        column_utils::{expand_switches, get_len_from_columns, validate_columns, Projection},
+        file_utils::{open_binary_input, Input},
+        value_utils::{flatten_record, value_key},
     },
-    BufferValue, CompressionType, Readable,
+    decompress::Decompressors,
+    index::{find_checkpoint, IndexCache},
+    last_records, BufferValue, Checkpoint, CheckpointIter, Config, Decompressor, Location,
+    NativeSettings, OnUnknownIdentifier, PacketColumns, Readable, ReaderError, RecordIter, Source,
 };
 use serde_json::{Map, Value};
 
 #[derive(Debug)]
-pub struct MultiNative {}
+pub struct MultiNative {
+    pub(crate) index_cache: Arc<IndexCache>,
+    /// Codecs registered with Reader::register_decompressor
+    pub(crate) decompressors: Decompressors,
+    /// Counts of Reader::skipped_packets, set by every stream when it is dropped
+    pub(crate) skipped_packets: Arc<SkippedPackets>,
+}
 
 impl Readable for MultiNative {
-    fn read(
+    fn stream<'a>(
+        &self,
+        source: Source,
+        config: &'a Config,
+        from: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let checkpoint = find_checkpoint(self, &self.index_cache, &source, config, from)?;
+
+        let mut iter = MultiNativeIter::new(source, config, self, from)?;
+
+        // Counting passes do not report skipped packets, streams of records do
+        iter.skipped_packets = Some(self.skipped_packets.clone());
+
+        // Start at udp packet of closest indexed record before from
+        // Inner packets before it are walked over
+        if let Some(checkpoint) = checkpoint {
+            iter.seek_to(checkpoint)?;
+        }
+
+        Ok(Box::new(iter))
+    }
+
+    fn stream_last<'a>(
         &self,
-        file_path: &String,
-        config: &crate::Config,
-        from: Option<u64>,
-        len: u64,
-    ) -> Result<Vec<Map<String, Value>>, Box<dyn Error>> {
-        let from = from.unwrap_or(0);
+        source: Source,
+        config: &'a Config,
+        n: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        // Records are counted in a first pass, so source has to be read twice
+        let Some(count_source) = source.try_clone() else {
+            return Ok(last_records(self.stream(source, config, 0)?, n));
+        };
 
-        // Create file reader and BufReader
-        let file = File::open(file_path)?;
-        let mut buf_reader = BufReader::new(file);
+        let checkpoint = find_checkpoint(self, &self.index_cache, &source, config, u64::MAX)?;
 
-        let mut values = vec![];
-        let mut pos = 0;
+        let mut iter = MultiNativeIter::new(count_source, config, self, 0)?;
+
+        // Only udp packets after last indexed record are counted
+        if let Some(checkpoint) = checkpoint {
+            iter.seek_to(checkpoint)?;
+        }
+
+        let total = iter.pos + iter.count_remaining()?;
+
+        self.stream(source, config, total.saturating_sub(n))
+    }
+
+    fn count(&self, source: Source, config: &Config) -> Result<u64, ReaderError> {
+        MultiNativeIter::new(source, config, self, 0)?.count_remaining()
+    }
+
+    fn scan<'a>(
+        &self,
+        source: Source,
+        config: &'a Config,
+    ) -> Result<Option<CheckpointIter<'a>>, ReaderError> {
+        let mut iter = MultiNativeIter::new(source, config, self, 0)?;
 
-        // Get all headers
+        Ok(Some(Box::new(std::iter::from_fn(move || {
+            let packet = iter.next_packet(true)?;
+
+            Some(packet.map(|(checkpoint, _)| checkpoint))
+        }))))
+    }
+}
+
+impl<'a> MultiNativeIter<'a> {
+    fn new(
+        source: Source,
+        config: &'a Config,
+        adapter: &MultiNative,
+        from: u64,
+    ) -> Result<Self, ReaderError> {
         let packet_header = &config.native.packet_header;
         let packet_info = &config.native.packet_info;
-        let packing = config.native.packing;
+
+        validate_columns(
+            [
+                &packet_header.timestamp,
+                &packet_header.packet_size,
+                &packet_info.no_of_packets,
+                &packet_info.compressed_packet_size,
+                &packet_info.packet_size,
+                &packet_info.packet_identifier,
+            ],
+            &config.native,
+        )?;
+        validate_columns(
+            packet_info
+                .column_details
+                .values()
+                .flat_map(|details| &details.columns),
+            &config.native,
+        )?;
+
+        // Records of every packet type have timestamp of their udp packet
+        let projection = Projection::new(config)
+            .map(|projection| {
+                let columns = packet_info
+                    .column_details
+                    .values()
+                    .map(|details| &details.columns[..]);
+
+                projection.for_native(columns, &config.native, &["timestamp"])
+            })
+            .transpose()?;
+
+        // Filter can reject packets by identifier before their columns are decoded
+        let identifier_names = identifier_columns(&config.native);
+
+        // Unknown packets can only be skipped over when all packets skip the same bytes
+        let mut skip_bytes = packet_info
+            .column_details
+            .values()
+            .map(|details| details.skip_bytes);
+        let shared_skip_bytes = skip_bytes
+            .next()
+            .filter(|first| skip_bytes.all(|skip_bytes| skip_bytes == *first));
+
+        let compression = match &packet_info.compression_indicator {
+            Some(indicator) => {
+                validate_columns([&indicator.column], &config.native)?;
+
+                if indicator.codecs.is_empty() {
+                    return Err(ReaderError::Config(
+                        "Compression indicator has no codecs".to_string(),
+                    ));
+                }
+
+                let codecs = indicator
+                    .codecs
+                    .iter()
+                    .map(|(value, codec)| {
+                        let decompressor = codec
+                            .as_ref()
+                            .map(|codec| adapter.decompressors.get(codec))
+                            .transpose()?;
+
+                        Ok((value.clone(), decompressor))
+                    })
+                    .collect::<Result<_, ReaderError>>()?;
+
+                Compression::ByIndicator {
+                    column: &indicator.column,
+                    codecs,
+                }
+            }
+            None => Compression::BySize(adapter.decompressors.get(&packet_info.compression_type)?),
+        };
+
+        // Open source, decompressed if needed
+        let buf_reader = open_binary_input(source, config.file_compression)?;
 
         let header_size =
             get_len_from_columns(vec![&packet_header.timestamp, &packet_header.packet_size]);
 
-        // Loop for each buffer in file
-        'outer: loop {
-            // Init a buffer
-            let mut buf: [u8; 1024] = [0; 1024];
-            let mut offset = 0;
+        let max_packet_size = config.native.max_packet_size();
+
+        if header_size > max_packet_size {
+            return Err(ReaderError::Config(format!(
+                "Packet header of {header_size} bytes is larger than {max_packet_size}"
+            )));
+        }
+
+        Ok(MultiNativeIter {
+            buf_reader,
+            config,
+            header_size,
+            buf: Vec::new(),
+            decompress_buf: Vec::new(),
+            compression,
+            projection,
+            identifier_names,
+            skipped: HashMap::new(),
+            skipped_packets: None,
+            shared_skip_bytes,
+            file_offset: 0,
+            next_file_offset: 0,
+            packet_size: 0,
+            packets_start: 0,
+            base: 0,
+            remaining: 0,
+            timestamp: Value::Null,
+            index: 0,
+            pos: 0,
+            from,
+            done: false,
+        })
+    }
+}
+
+/// Names of columns which hold packet identifier, by identifier of packet type
+/// It is packet_identifier.name, or the column found at offset of identifier
+/// Left out when column is converted, as filter sees converted values
+fn identifier_columns<'a>(native: &'a NativeSettings) -> HashMap<u64, &'a str> {
+    let identifier = &native.packet_info.packet_identifier;
+    let layout = Layout::new(native);
+
+    let column_name = |details: &'a PacketColumns| -> Option<&'a str> {
+        if !identifier.name.is_empty() {
+            return Some(identifier.name.as_str());
+        }
+
+        let target = identifier
+            .offset
+            .unwrap_or(0)
+            .checked_sub(details.skip_bytes as usize)?;
+
+        let column = column_at(&details.columns, &layout, target)?;
+
+        // Column must hold the same bytes as identifier
+        let endian = column.endian.or(details.endian).unwrap_or(native.endian);
+        let same = column.dtype == identifier.dtype
+            && column.length == identifier.length
+            && column.count.is_none()
+            && column.count_from.is_none()
+            && endian == identifier.endian.unwrap_or(native.endian);
+
+        same.then_some(column.name.as_str())
+    };
+
+    native
+        .packet_info
+        .column_details
+        .iter()
+        .filter_map(|(&key, details)| {
+            let name = column_name(details)?;
+
+            let converted = expand_switches(&details.columns).into_iter().any(|column| {
+                column.name == name
+                    && (column.value_map.is_some()
+                        || column.decimal.is_some()
+                        || column.timestamp.is_some())
+            });
+
+            (!converted).then_some((key, name))
+        })
+        .collect()
+}
+
+/// Count of skipped packets by identifier
+#[derive(Debug, Default)]
+pub(crate) struct SkippedPackets(Mutex<HashMap<String, u64>>);
+
+impl SkippedPackets {
+    fn set(&self, counts: HashMap<String, u64>) {
+        *self.lock() = counts;
+    }
+
+    pub(crate) fn get(&self) -> HashMap<String, u64> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, u64>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// How compressed sub packets are detected
+enum Compression<'a> {
+    /// Sub packets with compressed_packet_size > 0 are compressed
+    BySize(Arc<dyn Decompressor>),
+    /// Codec is looked up by value of indicator column
+    /// None for uncompressed sub packets
+    ByIndicator {
+        column: &'a BufferValue,
+        codecs: HashMap<String, Option<Arc<dyn Decompressor>>>,
+    },
+}
+
+/// Walks udp packets of file and yields one record per packet inside them
+struct MultiNativeIter<'a> {
+    buf_reader: Input,
+    config: &'a Config,
+    header_size: usize,
+
+    /// Current udp packet, sized by it's header
+    buf: Vec<u8>,
+    /// Allocated on first compressed packet
+    decompress_buf: Vec<u8>,
+    compression: Compression<'a>,
+    projection: Option<Projection>,
+    /// Column names of packet identifier by packet type, for early rejection by filter
+    identifier_names: HashMap<u64, &'a str>,
+    /// Skipped packets of this stream, by identifier
+    skipped: HashMap<String, u64>,
+    /// Reader counts, which skipped replaces once stream is dropped
+    skipped_packets: Option<Arc<SkippedPackets>>,
+    /// Skip bytes of all packet types, if they are the same
+    shared_skip_bytes: Option<u32>,
+
+    /// Offset in file where current udp packet starts
+    file_offset: u64,
+    next_file_offset: u64,
+    /// Size of current udp packet without header
+    packet_size: usize,
+    /// Offset in buf where inner packets start
+    packets_start: usize,
+    /// Offset of next inner packet from packets_start
+    base: usize,
+    /// Inner packets left in current udp packet
+    remaining: u64,
+    /// Timestamp from header of current udp packet
+    timestamp: Value,
+    /// Index of next inner packet in current udp packet
+    index: u64,
 
-            // Check if header data is available in file or EOF
-            if buf_reader.read_exact(&mut buf[0..header_size]).is_err() {
-                println!("Reached EOF at {pos}");
+    pos: u64,
+    from: u64,
+    done: bool,
+}
+
+impl Iterator for MultiNativeIter<'_> {
+    type Item = Result<Map<String, Value>, ReaderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Packets before from are walked over without decoding columns
+            let skip = self.pos < self.from;
+
+            match self.next_packet(skip)? {
+                Ok((_, Some(hashmap))) => return Some(Ok(hashmap)),
+                Ok((_, None)) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl Drop for MultiNativeIter<'_> {
+    fn drop(&mut self) {
+        if let Some(skipped_packets) = &self.skipped_packets {
+            skipped_packets.set(mem::take(&mut self.skipped));
+        }
+    }
+}
+
+impl MultiNativeIter<'_> {
+    /// Moves to udp packet of checkpoint
+    /// Inner packets before it are walked over
+    fn seek_to(&mut self, checkpoint: Checkpoint) -> Result<(), ReaderError> {
+        self.buf_reader.seek(SeekFrom::Start(checkpoint.offset))?;
+        self.next_file_offset = checkpoint.offset;
+        self.pos = checkpoint.record - checkpoint.inner;
+
+        Ok(())
+    }
+
+    /// Reads next inner packet, loading next udp packet once all are read
+    /// Returns where packet starts and it's record, None for skipped packets
+    #[allow(clippy::type_complexity)]
+    fn next_packet(
+        &mut self,
+        skip: bool,
+    ) -> Option<Result<(Checkpoint, Option<Map<String, Value>>), ReaderError>> {
+        loop {
+            if self.done {
+                return None;
+            }
+
+            // Load next udp packet once all inner packets are read
+            if self.remaining > 0 {
                 break;
             }
 
-            // Get timestamp and packet size from header
-            let timestamp =
-                col_from_buf(&packet_header.timestamp, &buf, &mut offset, &mut 0, packing)?;
-            let packet_size = col_from_buf(
-                &packet_header.packet_size,
-                &buf,
-                &mut offset,
-                &mut 0,
-                packing,
-            )?;
-
-            // println!("timestamp {}", timestamp);
-            // println!("packet size {}", packet_size);
-
-            // Read buffer
-            buf = [0; 1024];
-            buf_reader
-                .read_exact(&mut buf[0..packet_size.as_u64().unwrap() as usize])
-                .unwrap();
-            let mut offset = 0;
-
-            // get no of packets
-            let no_of_packets = col_from_buf(
-                &packet_info.no_of_packets,
-                &buf,
-                &mut offset,
-                &mut 0,
-                packing,
-            )?;
-            // println!("no_of_packets {}", no_of_packets);
-
-            // Read buffer
-            let buf = &buf[offset..];
-            let mut base = 0;
-
-            // For each packet inside udp packet
-            for _ in 0..no_of_packets.as_u64().unwrap_or(1) {
-                // After reading buffer
-                // Skip packets before from
-                if pos < from {
-                    pos += 1;
-                    continue;
+            match self.read_buffer() {
+                Ok(true) => continue,
+                Ok(false) => {
+                    self.done = true;
+
+                    return None;
                 }
+                Err(e) => {
+                    // Udp packet boundaries are unknown after a bad header
+                    self.done = true;
 
-                // load buffer from base
-                let mut offset = 0;
-                let mut buf = &buf[base..];
-                let mut decompress_buf = [0; 2048];
-
-                // Get compressed packet size
-                let compressed_packet_size = col_from_buf(
-                    &packet_info.compressed_packet_size,
-                    &buf,
-                    &mut offset,
-                    &mut 0,
-                    packing,
-                )
-                .unwrap();
-
-                // println!("compressed_packet_size {}", compressed_packet_size);
-
-                // Check if packet is compressed
-                if compressed_packet_size.is_u64() && compressed_packet_size.as_u64().unwrap() > 0 {
-                    let mut temp_offset = offset;
-                    let compressed_buf = get_buffer_slice(
-                        &buf,
-                        compressed_packet_size.as_u64().unwrap() as usize,
-                        &mut temp_offset,
-                    );
-
-                    match packet_info.compresseion_type {
-                        CompressionType::Lzo => {
-                            mylzo::decompress(compressed_buf, &mut decompress_buf).unwrap()
-                        }
-                    };
-
-                    buf = &decompress_buf;
-
-                    // Add compressed packet size to base
-                    base += compressed_packet_size.as_u64().unwrap() as usize;
-                } else {
-                    buf = &buf[offset..];
+                    return Some(Err(e.at(self.location(0))));
                 }
+            }
+        }
 
-                base += offset;
+        self.remaining -= 1;
 
-                offset = 0;
+        let checkpoint = Checkpoint {
+            record: self.pos,
+            offset: self.file_offset,
+            inner: self.index,
+        };
 
-                // Packet size and identifier
-                let packet_identifier = col_from_buf(
-                    &packet_info.packet_identifier,
-                    &buf,
-                    &mut offset,
-                    &mut 0,
-                    packing,
-                )
-                .unwrap();
-                let packet_size =
-                    col_from_buf(&packet_info.packet_size, &buf, &mut offset, &mut 0, packing)
-                        .unwrap();
+        self.index += 1;
 
-                // Set offset to 0 when starting to read form 0
-                offset = 0;
+        let at = self.location(self.header_size + self.packets_start + self.base);
 
-                // println!("packet_size {}", packet_size);
-                // println!("packet_identifier {}", packet_identifier);
+        let packet = self.read_packet(skip);
 
-                // Get column details or default
-                let column_details = packet_info
-                    .column_details
-                    .get(&packet_identifier.as_u64().unwrap())
-                    .unwrap_or_else(|| {
-                        packet_info.column_details.get(&0).expect(
-                            format!(
-                                "Unable to find columns for {} at pos {pos}",
-                                packet_identifier
-                            )
-                            .as_str(),
-                        )
-                    });
-
-                // Calculate base for next packet
-                // add packet size and skip bytes
-                // Only calculate this for non-compressed packets
-                // Because length changes after decompression
-                if !(compressed_packet_size.is_u64()
-                    && compressed_packet_size.as_u64().unwrap() > 0)
-                {
-                    base +=
-                        packet_size.as_u64().unwrap() as usize + column_details.skip_bytes as usize;
+        self.pos += 1;
+
+        match packet {
+            Ok(packet) => Some(Ok((checkpoint, packet))),
+            Err(e) => {
+                // Base of next packet is unknown after a bad packet
+                // Continue from next udp packet
+                self.remaining = 0;
+
+                Some(Err(e.at(at)))
+            }
+        }
+    }
+
+    /// Sums number of inner packets from headers of remaining udp packets
+    /// Inner packets are not decompressed or decoded
+    fn count_remaining(&mut self) -> Result<u64, ReaderError> {
+        let mut count = 0;
+
+        loop {
+            match self.read_buffer() {
+                Ok(true) => count += self.remaining,
+                Ok(false) => break,
+                Err(ReaderError::Io(e)) => return Err(ReaderError::Io(e)),
+                // Records end at a bad udp packet
+                Err(_) => break,
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Location of current record at offset from start of udp packet
+    fn location(&self, offset: usize) -> Location {
+        Location {
+            offset: self.file_offset + offset as u64,
+            position: self.pos,
+        }
+    }
+
+    /// Reads header and buffer of next udp packet
+    /// Returns false on EOF
+    fn read_buffer(&mut self) -> Result<bool, ReaderError> {
+        let packet_header = &self.config.native.packet_header;
+        let packet_info = &self.config.native.packet_info;
+        let packing = self.config.native.packing;
+        let endian = self.config.native.endian;
+
+        let mut offset = 0;
+
+        // Move file offset past previous udp packet
+        self.file_offset = self.next_file_offset;
+        self.packet_size = 0;
+        self.packets_start = 0;
+        self.base = 0;
+        self.index = 0;
+
+        // Check if header data is available in file or EOF
+        self.buf.clear();
+        self.buf.resize(self.header_size, 0);
+
+        let n = read_full(&mut self.buf_reader, &mut self.buf)?;
+
+        if n == 0 {
+            return Ok(false);
+        } else if n < self.header_size {
+            return Err(ReaderError::TruncatedPacket {
+                expected: self.header_size,
+                available: n,
+                at: Default::default(),
+            });
+        }
+
+        // Get timestamp and packet size from header
+        let timestamp = col_from_buf(
+            &packet_header.timestamp,
+            &self.buf,
+            &mut offset,
+            &mut 0,
+            packing,
+            endian,
+        )?;
+        let packet_size = col_from_buf(
+            &packet_header.packet_size,
+            &self.buf,
+            &mut offset,
+            &mut 0,
+            packing,
+            endian,
+        )?;
+
+        let packet_size = packet_size
+            .as_u64()
+            .ok_or_else(|| ReaderError::InvalidValue {
+                column: "packet_size".to_string(),
+                message: format!("Invalid packet size {packet_size}"),
+                at: Default::default(),
+            })? as usize;
+
+        let max_packet_size = self.config.native.max_packet_size();
+
+        if packet_size > max_packet_size {
+            return Err(ReaderError::PacketTooLarge {
+                size: packet_size,
+                max: max_packet_size,
+                at: Default::default(),
+            });
+        }
+
+        // Read buffer
+        self.buf.clear();
+        self.buf.resize(packet_size, 0);
+        self.packet_size = packet_size;
+
+        let n = read_full(&mut self.buf_reader, &mut self.buf)?;
+
+        self.next_file_offset = self.file_offset + (self.header_size + n) as u64;
+
+        if n < packet_size {
+            return Err(ReaderError::TruncatedPacket {
+                expected: packet_size,
+                available: n,
+                at: Default::default(),
+            });
+        }
+
+        let mut offset = 0;
+
+        // get no of packets
+        let no_of_packets = col_from_buf(
+            &packet_info.no_of_packets,
+            &self.buf,
+            &mut offset,
+            &mut 0,
+            packing,
+            endian,
+        )?;
+
+        self.timestamp = timestamp;
+        self.packets_start = offset;
+        self.remaining = no_of_packets.as_u64().unwrap_or(1);
+
+        Ok(true)
+    }
+
+    /// Reads inner packet at base and moves base to next packet
+    /// Returns None for skipped packets
+    fn read_packet(&mut self, skip: bool) -> Result<Option<Map<String, Value>>, ReaderError> {
+        let packet_info = &self.config.native.packet_info;
+        let packing = self.config.native.packing;
+        let endian = self.config.native.endian;
+
+        // load buffer from base
+        let mut offset = 0;
+        let buf = self
+            .buf
+            .get(self.packets_start + self.base..self.packet_size)
+            .ok_or(ReaderError::TruncatedPacket {
+                expected: self.packets_start + self.base,
+                available: self.packet_size,
+                at: Default::default(),
+            })?;
+
+        // Get compressed packet size
+        let compressed_packet_size = col_from_buf(
+            &packet_info.compressed_packet_size,
+            buf,
+            &mut offset,
+            &mut 0,
+            packing,
+            endian,
+        )?;
+
+        // Check if packet is compressed and find it's codec
+        let compressed = match &self.compression {
+            Compression::BySize(decompressor) => compressed_packet_size
+                .as_u64()
+                .filter(|size| *size > 0)
+                .map(|size| (decompressor.clone(), size)),
+            Compression::ByIndicator { column, codecs } => {
+                // Indicator may be placed before or after compressed size
+                let mut indicator_end = 0;
+
+                let indicator =
+                    col_from_buf(column, buf, &mut indicator_end, &mut 0, packing, endian)?;
+
+                offset = offset.max(indicator_end);
+
+                let codec = codecs.get(&value_key(&indicator)).ok_or_else(|| {
+                    ReaderError::InvalidValue {
+                        column: "compression_indicator".to_string(),
+                        message: format!("No codec for compression indicator {indicator}"),
+                        at: Default::default(),
+                    }
+                })?;
+
+                match codec {
+                    Some(decompressor) => {
+                        let size = compressed_packet_size
+                            .as_u64()
+                            .filter(|size| *size > 0)
+                            .ok_or_else(|| ReaderError::InvalidValue {
+                                column: "compressed_packet_size".to_string(),
+                                message: format!(
+                                    "Invalid compressed packet size {compressed_packet_size}"
+                                ),
+                                at: Default::default(),
+                            })?;
+
+                        Some((decompressor.clone(), size))
+                    }
+                    None => None,
                 }
+            }
+        };
+
+        let is_compressed = compressed.is_some();
+
+        let buf = if let Some((decompressor, compressed_size)) = compressed {
+            let mut temp_offset = offset;
+            let compressed_buf = get_buffer_slice(buf, compressed_size as usize, &mut temp_offset)?;
+
+            // Add compressed packet size to base
+            self.base += compressed_size as usize + offset;
 
-                // Read values from packet buf
-                let mut hashmap = Map::new();
+            // Skipped packets need not be decompressed
+            if skip {
+                return Ok(None);
+            }
+
+            let max_decompressed_size = self.config.native.max_decompressed_size();
+
+            if self.decompress_buf.len() != max_decompressed_size {
+                self.decompress_buf.resize(max_decompressed_size, 0);
+            }
 
-                hashmap.insert("timestamp".to_string(), timestamp.clone());
+            let n = decompressor
+                .decompress(compressed_buf, &mut self.decompress_buf)
+                .map_err(|message| ReaderError::Decompression {
+                    message: format!(
+                        "{message}, output is limited to {max_decompressed_size} bytes"
+                    ),
+                    at: Default::default(),
+                })?;
 
-                read_uncompressed(
-                    &column_details.columns,
-                    packing,
-                    &buf[column_details.skip_bytes as usize..],
-                    &mut offset,
-                    &mut hashmap,
-                );
+            &self.decompress_buf[..n]
+        } else {
+            self.base += offset;
 
-                values.push(hashmap);
+            &buf[offset..]
+        };
 
-                pos += 1;
+        offset = 0;
 
-                if pos >= (from + len) {
-                    println!("Length reached {pos}");
-                    break 'outer;
+        // Packet size and identifier
+        let packet_identifier = col_from_buf(
+            &packet_info.packet_identifier,
+            buf,
+            &mut offset,
+            &mut 0,
+            packing,
+            endian,
+        )?;
+        let packet_size = col_from_buf(
+            &packet_info.packet_size,
+            buf,
+            &mut offset,
+            &mut 0,
+            packing,
+            endian,
+        )?;
+
+        let identifier = packet_identifier.as_u64();
+
+        // Packets left out by include or exclude lists are skipped
+        let listed = match identifier {
+            Some(identifier) => {
+                (packet_info.include.is_empty() || packet_info.include.contains(&identifier))
+                    && !packet_info.exclude.contains(&identifier)
+            }
+            None => packet_info.include.is_empty(),
+        };
+
+        let unknown = || ReaderError::UnknownPacketIdentifier {
+            identifier: packet_identifier.clone(),
+            at: Default::default(),
+        };
+
+        let known = identifier.and_then(|identifier| packet_info.column_details.get(&identifier));
+
+        // Unknown packets are decoded with column details of identifier 0
+        let details_key = identifier.filter(|_| known.is_some()).unwrap_or(0);
+
+        // Get column details, None for skipped packets
+        let column_details = match known {
+            _ if !listed => None,
+            Some(column_details) => Some(column_details),
+            None => match packet_info.on_unknown_identifier {
+                OnUnknownIdentifier::Default => {
+                    Some(packet_info.column_details.get(&0).ok_or_else(unknown)?)
                 }
+                OnUnknownIdentifier::Skip => None,
+                OnUnknownIdentifier::Error => return Err(unknown()),
+            },
+        };
+
+        // Calculate base for next packet
+        // add packet size and skip bytes
+        // Only calculate this for non-compressed packets
+        // Because length changes after decompression
+        if !is_compressed {
+            // Unknown packets are skipped with skip bytes of default details
+            let skip_bytes = known
+                .or_else(|| packet_info.column_details.get(&0))
+                .map(|column_details| column_details.skip_bytes)
+                .or(self.shared_skip_bytes)
+                .ok_or_else(unknown)?;
+
+            self.base += packet_size
+                .as_u64()
+                .ok_or_else(|| ReaderError::InvalidValue {
+                    column: "packet_size".to_string(),
+                    message: format!("Invalid packet size {packet_size}"),
+                    at: Default::default(),
+                })? as usize
+                + skip_bytes as usize;
+        }
+
+        if skip {
+            return Ok(None);
+        }
+
+        let Some(column_details) = column_details else {
+            *self
+                .skipped
+                .entry(value_key(&packet_identifier))
+                .or_default() += 1;
+
+            return Ok(None);
+        };
+
+        // Packets which filter rejects by timestamp or identifier alone are not decoded
+        if let Some(filter) = &self.config.filter {
+            let mut known = Map::new();
+            known.insert("timestamp".to_string(), self.timestamp.clone());
+
+            if let Some(name) = self.identifier_names.get(&details_key) {
+                known.insert(name.to_string(), packet_identifier.clone());
             }
+
+            if filter.rejects(&known) {
+                return Ok(None);
+            }
+        }
+
+        // Read values from packet buf
+        let mut hashmap = Map::new();
+
+        if self
+            .projection
+            .as_ref()
+            .is_none_or(|projection| projection.selects("timestamp"))
+        {
+            hashmap.insert("timestamp".to_string(), self.timestamp.clone());
+        }
+
+        // Columns inherit byte order of packet
+        let layout = Layout {
+            endian: column_details.endian.unwrap_or(endian),
+            projection: self.projection.as_ref(),
+            ..Layout::new(&self.config.native)
+        };
+
+        read_columns(
+            &column_details.columns,
+            &layout,
+            buf.get(column_details.skip_bytes as usize..)
+                .unwrap_or_default(),
+            &mut 0,
+            &mut 0,
+            Some(&mut hashmap),
+            None,
+        )?;
+
+        if self.config.native.flatten {
+            hashmap = flatten_record(hashmap);
         }
 
-        Ok(values)
+        Ok(Some(hashmap))
     }
 }
 
-fn read_uncompressed(
-    columns: &Vec<BufferValue>,
-    packing: usize,
-    buf: &[u8],
-    total_offset: &mut usize,
-    hashmap: &mut Map<String, Value>,
-) {
-    // Offset to track position in buffer
-    let mut offset = 0;
-    let mut bit_offset = 0;
+#[cfg(test)]
+pub(crate) mod tests {
+    use serde_json::json;
+
+    use crate::{Reader, Type};
+
+    use super::*;
+
+    /// Inner packet with identifier and size of payload before payload
+    pub(crate) fn packet(identifier: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = identifier.to_be_bytes().to_vec();
+        packet.extend((payload.len() as u16).to_be_bytes());
+        packet.extend(payload);
+
+        packet
+    }
+
+    /// Udp packet of uncompressed inner packets
+    pub(crate) fn udp(timestamp: u32, packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = (packets.len() as u16).to_be_bytes().to_vec();
+
+        for packet in packets {
+            // Compressed size
+            body.extend([0, 0]);
+            body.extend(packet);
+        }
+
+        let mut udp = timestamp.to_be_bytes().to_vec();
+        udp.extend((body.len() as u32).to_be_bytes());
+        udp.extend(body);
+
+        udp
+    }
+
+    /// Config for packets of udp, extra is merged into root and packet_info
+    pub(crate) fn config(root: Value, packet_info: Value) -> Config {
+        let mut config = json!({
+            "selected_columns": [],
+            "native": {
+                "packet_header": {
+                    "timestamp": {"dtype": "u32", "offset": 0, "length": 4},
+                    "packet_size": {"dtype": "u32", "offset": 4, "length": 4},
+                },
+                "packet_info": {
+                    "no_of_packets": {"dtype": "u16", "offset": 0, "length": 2},
+                    "compressed_packet_size": {"dtype": "u16", "offset": 0, "length": 2},
+                    "packet_identifier": {"dtype": "u16", "offset": 0, "length": 2},
+                    "packet_size": {"dtype": "u16", "offset": 2, "length": 2},
+                },
+            },
+        });
+
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(root.as_object().unwrap().clone());
+        config["native"]["packet_info"]
+            .as_object_mut()
+            .unwrap()
+            .extend(packet_info.as_object().unwrap().clone());
+
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn reads_inner_packets_with_timestamp_of_udp_packet() {
+        let config = config(
+            json!({}),
+            json!({"column_details": {"1": {"skip_bytes": 4, "columns": [
+                {"name": "Price", "dtype": "u32", "length": 4},
+            ]}}}),
+        );
+
+        let mut file = udp(100, &[packet(1, &[0, 0, 0, 5]), packet(1, &[0, 0, 0, 6])]);
+        file.extend(udp(101, &[packet(1, &[0, 0, 0, 7])]));
+
+        let reader = Reader::new_with_config(config, file, Type::MultiNative);
+        let records = reader.read(None, None).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1]["Price"], json!(6));
+        assert_eq!(records[2]["timestamp"], json!(101));
+        assert_eq!(reader.count().unwrap(), 3);
+        assert_eq!(reader.read(Some(2), None).unwrap()[0]["Price"], json!(7));
+    }
+
+    #[test]
+    fn packet_columns_override_byte_order_of_settings() {
+        let config = config(
+            json!({}),
+            json!({"column_details": {"1": {"skip_bytes": 4, "endian": "little", "columns": [
+                {"name": "Price", "dtype": "u32", "length": 4},
+                {"name": "Qty", "dtype": "u16", "length": 2, "endian": "big"},
+            ]}}}),
+        );
+
+        // Headers stay big endian
+        let file = udp(100, &[packet(1, &[5, 0, 0, 0, 0, 6])]);
+
+        let records = Reader::new_with_config(config, file, Type::MultiNative)
+            .read(None, None)
+            .unwrap();
+
+        assert_eq!(
+            Value::from(records),
+            json!([{"timestamp": 100, "Price": 5, "Qty": 6}])
+        );
+    }
+
+    #[test]
+    fn reads_packets_larger_than_fixed_buffers() {
+        let config = config(
+            json!({}),
+            json!({"column_details": {"1": {"skip_bytes": 4, "columns": [
+                {"name": "Data", "dtype": "bytes", "length": 4000},
+                {"name": "End", "dtype": "u8", "length": 1},
+            ]}}}),
+        );
+
+        let mut payload = vec![0; 4000];
+        payload.push(9);
+
+        let file = udp(0, &[packet(1, &payload)]);
+
+        let records = Reader::new_with_config(config, file, Type::MultiNative)
+            .read(None, None)
+            .unwrap();
+
+        assert_eq!(records[0]["End"], json!(9));
+    }
+
+    #[test]
+    fn packets_over_max_size_are_an_error() {
+        let mut config = config(
+            json!({}),
+            json!({"column_details": {"1": {"skip_bytes": 4, "columns": [
+                {"name": "Price", "dtype": "u32", "length": 4},
+            ]}}}),
+        );
+        config.native.max_packet_size = Some(16);
+
+        let mut file = udp(0, &[packet(1, &[0, 0, 0, 1])]);
+        file.extend(udp(
+            0,
+            &[packet(1, &[0, 0, 0, 2]), packet(1, &[0, 0, 0, 3])],
+        ));
+
+        let records: Vec<_> = Reader::new_with_config(config, file, Type::MultiNative)
+            .iter()
+            .unwrap()
+            .collect();
 
-    for column in columns {
-        // Auto increment offset
-        // Auto type cast based on value
-        let val = col_from_buf(column, &buf, &mut offset, &mut bit_offset, packing).unwrap();
+        assert_eq!(records[0].as_ref().unwrap()["Price"], json!(1));
+        assert!(matches!(
+            records[1],
+            Err(ReaderError::PacketTooLarge {
+                size: 22,
+                max: 16,
+                ..
+            })
+        ));
+    }
+
+    /// Udp packet of sub packets with compressed size and indicator before them
+    fn indicated_udp(sub_packets: &[(u16, u8, Vec<u8>)]) -> Vec<u8> {
+        let mut body = (sub_packets.len() as u16).to_be_bytes().to_vec();
 
-        // Null is used for padding
-        // We can skip adding these columns
-        if val.is_null() || column.ignore == true {
-            continue;
+        for (compressed_size, indicator, bytes) in sub_packets {
+            body.extend(compressed_size.to_be_bytes());
+            body.push(*indicator);
+            body.extend(bytes);
         }
 
-        // Push value
-        hashmap.insert(column.name.clone(), val);
+        let mut udp = 0u32.to_be_bytes().to_vec();
+        udp.extend((body.len() as u32).to_be_bytes());
+        udp.extend(body);
+
+        udp
+    }
+
+    fn indicated_config() -> Config {
+        config(
+            json!({}),
+            json!({
+                "compression_indicator": {
+                    "column": {"dtype": "u8", "offset": 2, "length": 1},
+                    "codecs": {"0": null, "1": "snappy"},
+                },
+                "column_details": {"1": {"skip_bytes": 4, "columns": [
+                    {"name": "Price", "dtype": "u32", "length": 4},
+                ]}},
+            }),
+        )
+    }
+
+    #[test]
+    fn compression_indicator_chooses_codec() {
+        let compressed = snap::raw::Encoder::new()
+            .compress_vec(&packet(1, &[0, 0, 0, 6]))
+            .unwrap();
+
+        // Size of uncompressed sub packets is not looked at
+        let file = indicated_udp(&[
+            (99, 0, packet(1, &[0, 0, 0, 5])),
+            (compressed.len() as u16, 1, compressed),
+        ]);
+
+        let records = Reader::new_with_config(indicated_config(), file, Type::MultiNative)
+            .read(None, None)
+            .unwrap();
+
+        assert_eq!(
+            Value::from(records),
+            json!([{"timestamp": 0, "Price": 5}, {"timestamp": 0, "Price": 6}])
+        );
+    }
+
+    #[test]
+    fn compressed_size_selects_compression_type_without_indicator() {
+        let config = config(
+            json!({}),
+            json!({
+                "compression_type": "lz4",
+                "column_details": {"1": {"skip_bytes": 4, "columns": [
+                    {"name": "Price", "dtype": "u32", "length": 4},
+                ]}},
+            }),
+        );
+
+        let compressed = lz4_flex::block::compress(&packet(1, &[0, 0, 0, 6]));
+
+        let mut body = 2u16.to_be_bytes().to_vec();
+        body.extend([0, 0]);
+        body.extend(packet(1, &[0, 0, 0, 5]));
+        body.extend((compressed.len() as u16).to_be_bytes());
+        body.extend(compressed);
+
+        let mut file = 0u32.to_be_bytes().to_vec();
+        file.extend((body.len() as u32).to_be_bytes());
+        file.extend(body);
+
+        let records = Reader::new_with_config(config, file, Type::MultiNative)
+            .read(None, None)
+            .unwrap();
+
+        assert_eq!(records[0]["Price"], json!(5));
+        assert_eq!(records[1]["Price"], json!(6));
+    }
+
+    #[test]
+    fn unknown_compression_indicator_is_an_error() {
+        let file = indicated_udp(&[(0, 7, packet(1, &[0, 0, 0, 5]))]);
+
+        let error = Reader::new_with_config(indicated_config(), file, Type::MultiNative)
+            .read(None, None)
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            ReaderError::InvalidValue { column, .. } if column == "compression_indicator"
+        ));
+    }
+
+    #[test]
+    fn walks_unselected_structs_of_variable_size() {
+        let config = config(
+            json!({"selected_columns": ["Id", "Z"]}),
+            json!({"column_details": {"1": {"skip_bytes": 4, "columns": [
+                {"name": "Id", "dtype": "u8", "length": 1},
+                {"name": "S", "dtype": "struct", "fields": [
+                    {"name": "m", "dtype": "u8", "length": 1},
+                    {"name": "ys", "dtype": "u8", "length": 1, "count_from": "m"},
+                ]},
+                {"name": "Z", "dtype": "u8", "length": 1},
+            ]}}}),
+        );
+
+        let file = udp(0, &[packet(1, &[7, 2, 1, 2, 9]), packet(1, &[8, 0, 10])]);
+
+        let records = Reader::new_with_config(config, file, Type::MultiNative)
+            .read(None, None)
+            .unwrap();
+
+        assert_eq!(
+            Value::from(records),
+            json!([{"Id": 7, "Z": 9}, {"Id": 8, "Z": 10}])
+        );
+    }
+
+    #[test]
+    fn finds_identifier_column_by_offset() {
+        let config: Config = serde_json::from_str(include_str!("../../config_eq.json")).unwrap();
+
+        let names = identifier_columns(&config.native);
+
+        assert_eq!(names.len(), 10);
+        assert_eq!(names[&7208], "TransactionCode");
+        assert_eq!(names[&8207], "BCAST_HEADER.TransactionCode");
+    }
+
+    #[test]
+    fn filter_rejects_packets_by_identifier_before_decoding() {
+        // Identifier is the Kind column, size counts whole packet
+        let columns = |last: &str, dtype: &str, length: u32| {
+            json!({"skip_bytes": 0, "columns": [
+                {"name": "Kind", "dtype": "u16", "length": 2},
+                {"name": "Size", "dtype": "u16", "length": 2},
+                {"name": last, "dtype": dtype, "length": length},
+            ]})
+        };
+        let details = json!({"column_details": {
+            "1": columns("Price", "u16", 2),
+            "2": columns("Big", "u64", 8),
+        }});
+
+        let unfiltered = config(json!({}), details.clone());
+        let names = identifier_columns(&unfiltered.native);
+
+        assert_eq!(names[&1], "Kind");
+        assert_eq!(names[&2], "Kind");
+
+        // Packet 2 is shorter than it's columns
+        let file = udp(0, &[vec![0, 1, 0, 6, 0, 9], vec![0, 2, 0, 4]]);
+
+        let read = |filter: &str| {
+            let config = config(json!({"filter": filter}), details.clone());
+
+            Reader::new_with_config(config, file.clone(), Type::MultiNative).read(None, None)
+        };
+
+        let records = read("Kind = 1").unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["Price"], json!(9));
+
+        assert!(read("Size > 0").is_err());
+    }
+
+    /// Packets 1, 2 and 3, with columns for 1 and 2
+    fn listed(lists: Value) -> Reader {
+        let mut packet_info = json!({"column_details": {
+            "1": {"skip_bytes": 4, "columns": [{"name": "A", "dtype": "u8", "length": 1}]},
+            "2": {"skip_bytes": 4, "columns": [{"name": "B", "dtype": "u8", "length": 1}]},
+        }});
+
+        packet_info
+            .as_object_mut()
+            .unwrap()
+            .extend(lists.as_object().unwrap().clone());
+
+        let file = udp(0, &[packet(1, &[1]), packet(2, &[2]), packet(3, &[3])]);
+
+        Reader::new_with_config(config(json!({}), packet_info), file, Type::MultiNative)
+    }
+
+    fn skipped(pairs: &[(&str, u64)]) -> HashMap<String, u64> {
+        pairs
+            .iter()
+            .map(|&(identifier, count)| (identifier.to_string(), count))
+            .collect()
+    }
+
+    #[test]
+    fn include_list_skips_other_packets() {
+        let reader = listed(json!({"include": [1]}));
+
+        let records = reader.read(None, None).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["A"], json!(1));
+        assert_eq!(reader.skipped_packets(), skipped(&[("2", 1), ("3", 1)]));
+        // Skipped packets still count for positions
+        assert_eq!(reader.count().unwrap(), 3);
     }
 
-    // Increment total offset
-    // Used for multiple packets in same buffer
-    *total_offset += offset;
+    #[test]
+    fn exclude_list_and_unknown_packets_are_skipped() {
+        let reader = listed(json!({"exclude": [2], "on_unknown_identifier": "skip"}));
+
+        let records = reader.read(None, None).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(reader.skipped_packets(), skipped(&[("2", 1), ("3", 1)]));
+
+        // Counts are of last read only
+        reader.read(Some(1), None).unwrap();
+
+        assert_eq!(reader.skipped_packets(), skipped(&[("2", 1), ("3", 1)]));
+        assert_eq!(reader.read(Some(2), None).unwrap().len(), 0);
+        assert_eq!(reader.skipped_packets(), skipped(&[("3", 1)]));
+    }
+
+    #[test]
+    fn streams_at_once_keep_own_counts() {
+        let reader = listed(json!({"include": [1]}));
+
+        let mut first = reader.iter().unwrap();
+        let mut second = reader.iter().unwrap();
+
+        assert!(first.next().is_some());
+        assert!(second.next().is_some());
+        assert!(second.next().is_none());
+
+        drop(second);
+        assert_eq!(reader.skipped_packets(), skipped(&[("2", 1), ("3", 1)]));
+
+        assert!(first.next().is_none());
+
+        drop(first);
+        assert_eq!(reader.skipped_packets(), skipped(&[("2", 1), ("3", 1)]));
+
+        // Counting does not change them
+        reader.count().unwrap();
+        assert_eq!(reader.skipped_packets(), skipped(&[("2", 1), ("3", 1)]));
+    }
+
+    #[test]
+    fn unknown_packets_can_be_an_error() {
+        let reader = listed(json!({"on_unknown_identifier": "error"}));
+
+        let records: Vec<_> = reader.iter().unwrap().collect();
+
+        assert_eq!(records.len(), 3);
+        assert!(matches!(
+            records[2],
+            Err(ReaderError::UnknownPacketIdentifier { .. })
+        ));
+    }
 }
@@ -1,84 +1,455 @@
-use std::{
-    error::Error,
-    fs::File,
-    io::{BufReader, Read, Seek, SeekFrom},
-};
+use std::io::{self, ErrorKind, Read};
 
 use serde_json::{Map, Value};
 
-use crate::Readable;
+use crate::{last_records, BufferValue, Location, Readable, ReaderError, RecordIter, Source};
 
-use super::utils::byte_utils::cast_bytes;
+use super::utils::{
+    byte_utils::{read_columns, Layout},
+    column_utils::{validate_columns, validate_fixed_layout, Projection},
+    file_utils::{open_binary_input, Input},
+    value_utils::flatten_record,
+};
 
 #[derive(Debug)]
 pub struct NativeAdapter {}
 
 impl Readable for NativeAdapter {
-    fn read(
+    fn stream<'a>(
         &self,
-        file_path: &String,
-        config: &crate::Config,
-        from: Option<u64>,
-        len: u64,
-    ) -> Result<Vec<Map<String, Value>>, Box<dyn Error>> {
-        // Create file reader and BufReader
-        let file = File::open(file_path)?;
-        let mut buf_reader = BufReader::new(file);
-
-        // Get last col to calculate packet_size
-        // It is calculated by last_offset + length
-        let last_col = config
-            .native_columns
-            .iter()
-            .max_by_key(|i| i.offset)
-            .ok_or("Empty data")?;
+        source: Source,
+        config: &'a crate::Config,
+        from: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let mut iter = NativeIter::new(source, config)?;
 
-        // Calculate packet_size
-        let packet_size = last_col.offset.unwrap_or(0) + last_col.length;
-        let mut buf = [0; 1024];
+        // Skip till n packets, where n = form
+        // Which is calculated by (from * packet_size)
+        iter.skip_packets(from)?;
+
+        Ok(Box::new(iter))
+    }
+
+    fn count(&self, source: Source, config: &crate::Config) -> Result<u64, ReaderError> {
+        let mut iter = NativeIter::new(source, config)?;
+
+        // Streams are read through to find their size
+        let len = match iter.buf_reader.remaining_len()? {
+            Some(len) => len,
+            None => io::copy(&mut iter.buf_reader, &mut io::sink())?,
+        };
+
+        // Partial packet at the end of file is not a record
+        Ok(len.checked_div(iter.packet_size as u64).unwrap_or(0))
+    }
+
+    fn stream_last<'a>(
+        &self,
+        source: Source,
+        config: &'a crate::Config,
+        n: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let mut iter = NativeIter::new(source, config)?;
+
+        // Packets are of fixed size, so their count follows from file size
+        match iter.buf_reader.remaining_len()? {
+            Some(len) => {
+                let total = len.checked_div(iter.packet_size as u64).unwrap_or(0);
+
+                iter.skip_packets(total.saturating_sub(n))?;
+
+                Ok(Box::new(iter))
+            }
+            None => Ok(last_records(Box::new(iter), n)),
+        }
+    }
+}
+
+impl<'a> NativeIter<'a> {
+    fn new(source: Source, config: &'a crate::Config) -> Result<Self, ReaderError> {
+        validate_columns(&config.native_columns, &config.native)?;
+        validate_fixed_layout(&config.native_columns, &config.native)?;
+
+        if config.native_columns.is_empty() {
+            return Err(ReaderError::Config("Empty native_columns".to_string()));
+        }
+
+        // Open source, decompressed if needed
+        let buf_reader = open_binary_input(source, config.file_compression)?;
 
         // Get column details from config
-        let mut native_columns = config.native_columns.clone();
+        // Columns without offset follow previous column
+        let native_columns = config.native_columns.clone();
 
-        // Sort columns by offset in ascending order
-        native_columns.sort_by_key(|i| i.offset);
+        let layout = Layout::new(&config.native);
 
-        // Set from and to
-        let from = from.unwrap_or(0);
+        let projection = Projection::new(config)
+            .map(|projection| {
+                projection.for_native([&config.native_columns[..]], &config.native, &[])
+            })
+            .transpose()?;
 
-        // Seek till n packets, where n = form
-        // Which is calculated by (from * packet_size)
-        // Seek takes n bytes
-        buf_reader.seek(SeekFrom::Start(from * packet_size as u64))?;
+        // Calculate packet_size
+        // It is the furthest offset reached after a column
+        let mut packet_size = 0;
+        let mut offset = 0;
+        let mut bit_offset = 0;
 
-        let mut data = vec![];
-        let mut pos = 0;
+        for column in &native_columns {
+            read_columns(
+                std::slice::from_ref(column),
+                &layout,
+                &[],
+                &mut offset,
+                &mut bit_offset,
+                None,
+                None,
+            )?;
+
+            packet_size = packet_size.max(offset + usize::from(bit_offset != 0));
+        }
+
+        let max_packet_size = config.native.max_packet_size();
+
+        if packet_size > max_packet_size {
+            return Err(ReaderError::PacketTooLarge {
+                size: packet_size,
+                max: max_packet_size,
+                at: Default::default(),
+            });
+        }
+
+        Ok(NativeIter {
+            buf_reader,
+            native_columns,
+            packet_size,
+            layout,
+            projection,
+            flatten: config.native.flatten,
+            buf: vec![0; packet_size],
+            pos: 0,
+            done: false,
+        })
+    }
+
+    /// Moves n packets forward
+    fn skip_packets(&mut self, n: u64) -> Result<(), ReaderError> {
+        self.buf_reader
+            .skip(n.saturating_mul(self.packet_size as u64))?;
+        self.pos += n;
+
+        Ok(())
+    }
+}
+
+/// Reads one fixed size packet per record
+struct NativeIter<'a> {
+    buf_reader: Input,
+    native_columns: Vec<BufferValue>,
+    packet_size: usize,
+    layout: Layout<'a>,
+    projection: Option<Projection>,
+    /// Output struct and array columns as dotted names
+    flatten: bool,
+    buf: Vec<u8>,
+    pos: u64,
+    done: bool,
+}
+
+impl Iterator for NativeIter<'_> {
+    type Item = Result<Map<String, Value>, ReaderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
 
         // Read into buf for packet size
-        while let Ok(_) = buf_reader.read_exact(&mut buf[0..packet_size]) {
-            // Break if pos is GE than to
-            if pos >= len {
-                break;
-            }
+        // Partial packet at the end of file is ignored
+        if let Err(e) = self.buf_reader.read_exact(&mut self.buf) {
+            self.done = true;
+
+            return match e.kind() {
+                ErrorKind::UnexpectedEof => None,
+                _ => Some(Err(e.into())),
+            };
+        }
 
-            let mut hashmap = Map::new();
+        let at = Location {
+            offset: self.pos * self.packet_size as u64,
+            position: self.pos,
+        };
 
-            // Cast for each column
-            for col in &native_columns {
-                // Get slice from buf
-                let buf = &buf[col.offset.unwrap_or(0)..(col.offset.unwrap_or(0) + col.length)];
+        self.pos += 1;
 
-                // Convert byte array to required type
-                let val = cast_bytes(buf, &col.dtype)?;
+        let mut hashmap = Map::new();
 
-                hashmap.insert(col.name.clone(), Value::from(val));
-            }
+        // Cast for each column
+        // Columns may not reach past packet_size
+        let layout = Layout {
+            projection: self.projection.as_ref(),
+            ..self.layout
+        };
 
-            data.push(hashmap);
+        if let Err(e) = read_columns(
+            &self.native_columns,
+            &layout,
+            &self.buf,
+            &mut 0,
+            &mut 0,
+            Some(&mut hashmap),
+            None,
+        ) {
+            return Some(Err(e.at(at)));
+        }
 
-            pos += 1;
+        if self.flatten {
+            hashmap = flatten_record(hashmap);
         }
 
-        Ok(data)
+        Some(Ok(hashmap))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{Config, Reader, ReaderError, Type};
+
+    fn reader(native_columns: serde_json::Value, buf: Vec<u8>) -> Reader {
+        let config: Config = serde_json::from_value(json!({
+            "selected_columns": [],
+            "native_columns": native_columns,
+        }))
+        .unwrap();
+
+        Reader::new_with_config(config, buf, Type::Native)
+    }
+
+    #[test]
+    fn reads_fixed_size_packets() {
+        let reader = reader(
+            json!([
+                {"name": "a", "dtype": "u16", "length": 2},
+                {"name": "b", "dtype": "char", "length": 3},
+            ]),
+            b"\x00\x01abc\x00\x02xyz".to_vec(),
+        );
+
+        let records = reader.read(None, None).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["a"], json!(2));
+        assert_eq!(records[1]["b"], json!("xyz"));
+        assert_eq!(reader.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn count_from_uses_fixed_slots() {
+        let reader = reader(
+            json!([
+                {"name": "n", "dtype": "u8", "length": 1},
+                {"name": "xs", "dtype": "u8", "length": 1, "count": 3, "count_from": "n"},
+            ]),
+            vec![2, 7, 8, 0, 1, 9, 0, 0],
+        );
+
+        let records = reader.read(None, None).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["xs"], json!([7, 8]));
+        assert_eq!(records[1]["xs"], json!([9]));
+    }
+
+    #[test]
+    fn switch_takes_size_of_largest_arm() {
+        let reader = reader(
+            json!([
+                {"name": "kind", "dtype": "u8", "length": 1},
+                {"name": "", "switch": {
+                    "on": "kind",
+                    "cases": {
+                        "1": [{"name": "small", "dtype": "u16", "length": 2}],
+                        "2": [{"name": "big", "dtype": "u32", "length": 4}],
+                    },
+                }},
+            ]),
+            vec![1, 0, 5, 0, 0, 2, 0, 0, 0, 6],
+        );
+
+        let records = reader.read(None, None).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["small"], json!(5));
+        assert_eq!(records[1]["big"], json!(6));
+    }
+
+    #[test]
+    fn rejects_variable_layout() {
+        let reader = reader(
+            json!([
+                {"name": "len", "dtype": "u8", "length": 1},
+                {"name": "s", "dtype": "char", "length_from": "len"},
+            ]),
+            vec![1, b'a'],
+        );
+
+        let Err(ReaderError::Config(message)) = reader.read(None, None) else {
+            panic!("variable layout must be rejected");
+        };
+
+        assert!(message.contains("use MultiNative"));
+    }
+
+    #[test]
+    fn columns_override_byte_order_of_settings() {
+        let config: Config = serde_json::from_value(json!({
+            "selected_columns": [],
+            "native": {"endian": "little"},
+            "native_columns": [
+                {"name": "a", "dtype": "u16", "length": 2},
+                {"name": "b", "dtype": "u16", "length": 2, "endian": "big"},
+                {"name": "c", "dtype": "f32", "length": 4},
+            ],
+        }))
+        .unwrap();
+
+        let mut buf = vec![1, 0, 0, 1];
+        buf.extend(1.5f32.to_le_bytes());
+
+        let records = Reader::new_with_config(config, buf, Type::Native)
+            .read(None, None)
+            .unwrap();
+
+        assert_eq!(
+            serde_json::Value::from(records),
+            json!([{"a": 1, "b": 1, "c": 1.5}])
+        );
+    }
+
+    fn nested(flatten: bool) -> Reader {
+        let config: Config = serde_json::from_value(json!({
+            "selected_columns": [],
+            "native": {
+                "flatten": flatten,
+                "structs": {"LEVEL": [
+                    {"name": "Price", "dtype": "u16", "length": 2},
+                    {"name": "Qty", "dtype": "u8", "length": 1},
+                ]},
+            },
+            "native_columns": [
+                {"name": "Book", "dtype": "struct", "struct": "LEVEL", "count": 2},
+                {"name": "Last", "dtype": "struct", "fields": [
+                    {"name": "Side", "dtype": "char", "length": 1},
+                ]},
+            ],
+        }))
+        .unwrap();
+
+        Reader::new_with_config(config, vec![0, 10, 1, 0, 11, 2, b'B'], Type::Native)
+    }
+
+    #[test]
+    fn reads_nested_structs_and_arrays() {
+        assert_eq!(
+            serde_json::Value::from(nested(false).read(None, None).unwrap()),
+            json!([{
+                "Book": [{"Price": 10, "Qty": 1}, {"Price": 11, "Qty": 2}],
+                "Last": {"Side": "B"},
+            }])
+        );
+    }
+
+    #[test]
+    fn flattens_nested_columns_to_dotted_names() {
+        assert_eq!(
+            serde_json::Value::from(nested(true).read(None, None).unwrap()),
+            json!([{
+                "Book[0].Price": 10,
+                "Book[0].Qty": 1,
+                "Book[1].Price": 11,
+                "Book[1].Qty": 2,
+                "Last.Side": "B",
+            }])
+        );
+    }
+
+    #[test]
+    fn outputs_labels_of_mapped_columns() {
+        let config: Config = serde_json::from_value(json!({
+            "selected_columns": [],
+            "native": {"value_maps": {
+                "SIDE": {"labels": {"1": "Buy", "2": "Sell"}, "output": "both"},
+                "STATUS": {"labels": {"O": "Open"}, "output": "raw"},
+            }},
+            "native_columns": [
+                {"name": "Side", "dtype": "u8", "length": 1, "value_map": "SIDE"},
+                {"name": "Status", "dtype": "char", "length": 1, "value_map": "STATUS"},
+            ],
+        }))
+        .unwrap();
+
+        let records = Reader::new_with_config(config, b"\x02O".to_vec(), Type::Native)
+            .read(None, None)
+            .unwrap();
+
+        assert_eq!(
+            serde_json::Value::from(records),
+            json!([{"Side": 2, "Side_label": "Sell", "Status": "O"}])
+        );
+    }
+
+    #[test]
+    fn unknown_value_maps_are_an_error() {
+        let reader = reader(
+            json!([{"name": "Side", "dtype": "u8", "length": 1, "value_map": "SIDE"}]),
+            vec![1],
+        );
+
+        assert!(matches!(
+            reader.read(None, None),
+            Err(ReaderError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn packet_size_is_limited_by_settings_not_buffers() {
+        let columns = json!([{"name": "a", "dtype": "bytes", "length": 3000}]);
+
+        let records = reader(columns.clone(), vec![1; 6000])
+            .read(None, None)
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+
+        let config: Config = serde_json::from_value(json!({
+            "selected_columns": [],
+            "native": {"max_packet_size": 1024},
+            "native_columns": columns,
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            Reader::new_with_config(config, vec![1; 6000], Type::Native).read(None, None),
+            Err(ReaderError::PacketTooLarge {
+                size: 3000,
+                max: 1024,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn filters_on_paths_of_nested_columns() {
+        let mut reader = nested(false);
+
+        reader
+            .set_filter("Book[1].Price = 11 and Last.Side = 'B'")
+            .unwrap();
+        assert_eq!(reader.count().unwrap(), 1);
+
+        reader.set_filter("Book[1].Price = 12").unwrap();
+        assert_eq!(reader.count().unwrap(), 0);
     }
 }
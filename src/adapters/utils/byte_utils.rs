@@ -1,76 +1,649 @@
-use std::error::Error;
+use std::{
+    collections::BTreeMap,
+    io::{ErrorKind, Read},
+};
 
-use serde_json::Value;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde_json::{Map, Value};
 
-use crate::{BufferValue, DType};
+use crate::{
+    BufferValue, ByteEncoding, DType, Endian, MapOutput, NativeSettings, ReaderError, Switch,
+    ValueMap,
+};
 
-pub fn cast_bytes(buf: &[u8], dtype: &DType) -> Result<Value, Box<dyn Error>> {
-    Ok(match dtype {
-        DType::Byte => Value::Number(serde_json::Number::from(u8::from_be_bytes(
-            buf.to_vec()
-                .try_into()
-                .map_err(|_| "Failed to convert to byte")?,
-        ))),
+use super::{
+    column_utils::{struct_fields, Projection},
+    value_utils::{format_timestamp, map_value, scale_value, value_key},
+};
+
+/// Reads a number of type from bytes in given byte order
+macro_rules! number {
+    ($t:ty, $buf:expr, $name:expr, $endian:expr) => {{
+        let bytes = sized($buf, $name)?;
+
+        match $endian {
+            Endian::Big => <$t>::from_be_bytes(bytes),
+            Endian::Little => <$t>::from_le_bytes(bytes),
+        }
+    }};
+}
+
+/// Casts bytes to json value of column dtype
+/// Returns reason as error, caller knows which column failed
+pub fn cast_bytes(buf: &[u8], column: &BufferValue, endian: Endian) -> Result<Value, String> {
+    let mut value = cast_raw(buf, column, endian)?;
+
+    if let Some(format) = &column.decimal {
+        value = scale_value(value, format)?;
+    }
+
+    if let Some(format) = &column.timestamp {
+        value = format_timestamp(value, format)?;
+    }
+
+    Ok(value)
+}
+
+fn cast_raw(buf: &[u8], column: &BufferValue, endian: Endian) -> Result<Value, String> {
+    Ok(match column.dtype {
+        DType::Bytes => Value::String(match column.encoding {
+            ByteEncoding::Hex => buf.iter().map(|b| format!("{b:02x}")).collect(),
+            ByteEncoding::Base64 => BASE64_STANDARD.encode(buf),
+        }),
         DType::Char => Value::String(String::from_utf8_lossy(buf).to_string()),
-        DType::Bool => Value::Bool(buf[0] != 0),
-        DType::U32 => Value::Number(serde_json::Number::from(u32::from_be_bytes(
-            buf.to_vec()
-                .try_into()
-                .map_err(|_| "Failed to convert to u32")?,
-        ))),
-        DType::U64 => Value::Number(serde_json::Number::from(u64::from_be_bytes(
-            buf.to_vec()
-                .try_into()
-                .map_err(|_| "Failed to convert to u64")?,
-        ))),
-        DType::Short => Value::Number(serde_json::Number::from(i16::from_be_bytes(
-            buf.try_into()?,
-        ))),
-        DType::I32 => Value::Number(serde_json::Number::from(i32::from_be_bytes(
-            buf.try_into()?,
-        ))),
-        DType::I64 => Value::Number(serde_json::Number::from(i64::from_be_bytes(
-            buf.try_into()?,
-        ))),
+        DType::Bool => Value::Bool(*buf.first().ok_or("Failed to convert to bool")? != 0),
+        DType::U8 | DType::Byte => {
+            Value::Number(serde_json::Number::from(number!(u8, buf, "u8", endian)))
+        }
+        DType::I8 => Value::Number(serde_json::Number::from(number!(i8, buf, "i8", endian))),
+        DType::U16 => Value::Number(serde_json::Number::from(number!(u16, buf, "u16", endian))),
+        DType::Short => Value::Number(serde_json::Number::from(number!(i16, buf, "short", endian))),
+        DType::U32 => Value::Number(serde_json::Number::from(number!(u32, buf, "u32", endian))),
+        DType::I32 => Value::Number(serde_json::Number::from(number!(i32, buf, "i32", endian))),
+        DType::U64 => Value::Number(serde_json::Number::from(number!(u64, buf, "u64", endian))),
+        DType::I64 => Value::Number(serde_json::Number::from(number!(i64, buf, "i64", endian))),
+        // Json numbers can not hold 128 bit values
+        DType::U128 => Value::String(number!(u128, buf, "u128", endian).to_string()),
+        DType::I128 => Value::String(number!(i128, buf, "i128", endian).to_string()),
         DType::F32 => Value::Number(
-            serde_json::Number::from_f64(f32::from_be_bytes(buf.try_into()?) as f64).ok_or("NaN f32")?,
+            serde_json::Number::from_f64(number!(f32, buf, "f32", endian) as f64)
+                .ok_or("NaN f32")?,
         ),
         DType::F64 => Value::Number(
-            serde_json::Number::from_f64(f64::from_be_bytes(buf.try_into()?)).ok_or(format!("NaN f64 {:?}", buf))?
+            serde_json::Number::from_f64(number!(f64, buf, "f64", endian))
+                .ok_or(format!("NaN f64 {:?}", buf))?,
         ),
         DType::None => Value::Null,
-        DType::Bit => {
-            let byte = bits_to_u8(buf.try_into()?);
+        DType::Struct => return Err("Struct columns are not supported here".to_string()),
+        // Bits are passed as big endian u64
+        DType::Bit => Value::Number(serde_json::Number::from(u64::from_be_bytes(sized(
+            buf, "bit",
+        )?))),
+    })
+}
+
+/// Converts slice to array of size N
+/// Fails when column length does not match size of dtype
+fn sized<const N: usize>(buf: &[u8], dtype: &str) -> Result<[u8; N], String> {
+    buf.try_into().map_err(|_| {
+        format!(
+            "Failed to convert {} bytes to {dtype}, expected {N}",
+            buf.len()
+        )
+    })
+}
+
+/// Settings shared by columns of a packet
+#[derive(Clone, Copy)]
+pub struct Layout<'a> {
+    pub packing: usize,
+    /// Byte order for columns without their own
+    pub endian: Endian,
+    /// Definitions of struct columns
+    pub structs: &'a BTreeMap<String, Vec<BufferValue>>,
+    pub value_maps: &'a BTreeMap<String, ValueMap>,
+    /// Selected columns, None to decode every column
+    pub projection: Option<&'a Projection>,
+}
+
+impl<'a> Layout<'a> {
+    pub fn new(native: &'a NativeSettings) -> Layout<'a> {
+        Layout {
+            packing: native.packing,
+            endian: native.endian,
+            structs: &native.structs,
+            value_maps: &native.value_maps,
+            projection: None,
+        }
+    }
+}
+
+/// Values decoded before a column
+/// Used to find counts and lengths of variable columns
+#[derive(Clone, Copy)]
+pub struct Scope<'s> {
+    values: &'s Map<String, Value>,
+    /// Values of ignored columns
+    hidden: &'s Map<String, Value>,
+    /// Scope of struct which contains these columns
+    parent: Option<&'s Scope<'s>>,
+}
+
+impl Scope<'_> {
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.values
+            .get(name)
+            .or_else(|| self.hidden.get(name))
+            .or_else(|| self.parent.and_then(|parent| parent.get(name)))
+    }
+}
+
+/// Reads columns from offset into hashmap and moves offset past them
+/// Columns are only walked over when hashmap is None
+pub fn read_columns(
+    columns: &[BufferValue],
+    layout: &Layout,
+    buf: &[u8],
+    offset: &mut usize,
+    bit_offset: &mut usize,
+    hashmap: Option<&mut Map<String, Value>>,
+    parent: Option<&Scope>,
+) -> Result<(), ReaderError> {
+    // Ignored columns are kept for counts and lengths, but not returned
+    let mut hidden = Map::new();
+
+    read_into(
+        columns,
+        layout,
+        buf,
+        offset,
+        bit_offset,
+        hashmap,
+        &mut hidden,
+        parent,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn read_into(
+    columns: &[BufferValue],
+    layout: &Layout,
+    buf: &[u8],
+    offset: &mut usize,
+    bit_offset: &mut usize,
+    mut hashmap: Option<&mut Map<String, Value>>,
+    hidden: &mut Map<String, Value>,
+    parent: Option<&Scope>,
+) -> Result<(), ReaderError> {
+    for column in columns {
+        let scope = hashmap.as_deref().map(|values| Scope {
+            values,
+            hidden,
+            parent,
+        });
+
+        // Columns of switch arm share scope and offsets of the switch
+        if let Some(switch) = &column.switch {
+            // Arm is not known without decoded values, like in sizing Native packets
+            // Switch then takes size of it's largest arm, like a c union
+            if scope.is_none() {
+                let mut end = (*offset, *bit_offset);
+
+                for arm in switch.cases.values().chain(&switch.default) {
+                    let (mut arm_offset, mut arm_bit_offset) = (*offset, *bit_offset);
+
+                    read_into(
+                        arm,
+                        layout,
+                        buf,
+                        &mut arm_offset,
+                        &mut arm_bit_offset,
+                        None,
+                        hidden,
+                        parent,
+                    )?;
+
+                    end = end.max((arm_offset, arm_bit_offset));
+                }
+
+                (*offset, *bit_offset) = end;
+
+                continue;
+            }
+
+            let arm = switch_arm(column, switch, scope.as_ref())?;
+
+            read_into(
+                arm,
+                layout,
+                buf,
+                offset,
+                bit_offset,
+                hashmap.as_deref_mut(),
+                hidden,
+                parent,
+            )?;
+
+            continue;
+        }
+
+        let selected = layout
+            .projection
+            .is_none_or(|projection| projection.selects(&column.name));
+
+        // None is used for padding, so it is never decoded
+        // Unselected columns are only decoded when others depend on them
+        let decode = scope.is_some()
+            && column.dtype != DType::None
+            && layout
+                .projection
+                .is_none_or(|projection| projection.needs(&column.name));
+
+        let value = read_column(
+            column,
+            layout,
+            buf,
+            offset,
+            bit_offset,
+            scope.as_ref(),
+            decode,
+        )?;
+
+        if let (Some(hashmap), Some(value)) = (hashmap.as_deref_mut(), value) {
+            let target = if column.ignore || !selected {
+                &mut *hidden
+            } else {
+                hashmap
+            };
+
+            match &column.value_map {
+                Some(name) => insert_mapped(column, name, value, layout, target)?,
+                None => {
+                    target.insert(column.name.clone(), value);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Inserts label and or code of column as set in value map
+fn insert_mapped(
+    column: &BufferValue,
+    name: &str,
+    value: Value,
+    layout: &Layout,
+    hashmap: &mut Map<String, Value>,
+) -> Result<(), ReaderError> {
+    let map = layout.value_maps.get(name).ok_or_else(|| {
+        ReaderError::Config(format!(
+            "Column {} uses undefined value map {name}",
+            column.name
+        ))
+    })?;
+
+    if map.output == MapOutput::Raw {
+        hashmap.insert(column.name.clone(), value);
 
-            Value::Number(serde_json::Number::from(byte))
+        return Ok(());
+    }
+
+    let label = map_value(&value, map).map_err(|message| ReaderError::InvalidValue {
+        column: column.name.clone(),
+        message: format!("{message} in value map {name}"),
+        at: Default::default(),
+    })?;
+
+    match map.output {
+        MapOutput::Both => {
+            hashmap.insert(column.name.clone(), value);
+            hashmap.insert(format!("{}_label", column.name), label);
+        }
+        _ => {
+            hashmap.insert(column.name.clone(), label);
         }
+    }
+
+    Ok(())
+}
+
+/// Columns of switch for value of it's column
+fn switch_arm<'a>(
+    column: &BufferValue,
+    switch: &'a Switch,
+    scope: Option<&Scope>,
+) -> Result<&'a [BufferValue], ReaderError> {
+    let value = lookup(column, &switch.on, scope)?;
+
+    let key = value_key(value);
+
+    switch
+        .cases
+        .get(&key)
+        .or(switch.default.as_ref())
+        .map(Vec::as_slice)
+        .ok_or_else(|| ReaderError::InvalidValue {
+            column: switch.on.clone(),
+            message: format!("No switch case for {key}"),
+            at: Default::default(),
+        })
+}
+
+/// Reads column, array or struct at offset and moves offset past it
+/// Returns None when decode is false
+fn read_column(
+    column: &BufferValue,
+    layout: &Layout,
+    buf: &[u8],
+    offset: &mut usize,
+    bit_offset: &mut usize,
+    scope: Option<&Scope>,
+    decode: bool,
+) -> Result<Option<Value>, ReaderError> {
+    let fields = match column.dtype {
+        DType::Struct => Some(struct_fields(column, layout.structs)?),
+        _ => None,
+    };
+
+    // Length of strings and blobs may come from a previous column
+    let length = match &column.length_from {
+        Some(name) => lookup_size(column, name, scope)?,
+        None => column.length,
+    };
+
+    seek_column(
+        column,
+        offset,
+        bit_offset,
+        layout.packing,
+        is_aligned(column, layout),
+    );
+
+    // Fields of struct inherit it's byte order, and are all decoded
+    let layout = Layout {
+        endian: column.endian.unwrap_or(layout.endian),
+        projection: None,
+        ..*layout
+    };
+
+    // Number of elements to decode, and number of elements in packet
+    // count is the number of slots when count comes from a previous column
+    let (count, slots) = match &column.count_from {
+        // Without decoded columns, fixed slots are walked over
+        Some(_) if scope.is_none() && column.count.is_some() => {
+            (0, column.count.unwrap_or_default())
+        }
+        Some(name) => {
+            let count = lookup_size(column, name, scope)?;
+            let slots = column.count.unwrap_or(count);
+
+            if count > slots {
+                return Err(ReaderError::InvalidValue {
+                    column: column.name.clone(),
+                    message: format!("Count {count} from {name} is more than {slots} slots"),
+                    at: Default::default(),
+                });
+            }
+
+            // Count comes from data, it must fit in rest of packet
+            check_count(column, count, length, buf.len(), *offset)?;
+
+            (count, slots)
+        }
+        None => (column.count.unwrap_or(1), column.count.unwrap_or(1)),
+    };
+
+    let mut values = Vec::new();
+
+    for _ in 0..count {
+        let value = match fields {
+            Some(fields) => read_struct(column, fields, &layout, buf, offset, scope, decode)?,
+            None if decode => read_value(column, length, buf, offset, bit_offset, layout.endian)?,
+            None => {
+                skip_value(column, length, offset, bit_offset)?;
+
+                continue;
+            }
+        };
+
+        if decode {
+            values.push(value);
+        }
+    }
+
+    // Unused slots are walked over
+    for _ in count..slots {
+        match fields {
+            Some(fields) => {
+                read_struct(column, fields, &layout, buf, offset, None, false)?;
+            }
+            None => skip_value(column, length, offset, bit_offset)?,
+        }
+    }
+
+    if !decode {
+        return Ok(None);
+    }
+
+    Ok(Some(match (column.count, &column.count_from) {
+        (None, None) => values.pop().unwrap_or_default(),
+        _ => Value::Array(values),
+    }))
+}
+
+/// Fails when count elements can not fit in buffer after offset
+/// Elements take at least a byte, or a bit for bit columns
+fn check_count(
+    column: &BufferValue,
+    count: usize,
+    length: usize,
+    available: usize,
+    offset: usize,
+) -> Result<(), ReaderError> {
+    let available = available.saturating_sub(offset);
+
+    let needed = match column.dtype {
+        DType::Bit => count
+            .checked_mul(length.max(1))
+            .map(|bits| bits.div_ceil(8)),
+        DType::Struct => count.checked_mul(column.length.max(1)),
+        _ => count.checked_mul(length.max(1)),
+    };
+
+    match needed {
+        Some(needed) if needed <= available => Ok(()),
+        _ => Err(ReaderError::InvalidValue {
+            column: column.name.clone(),
+            message: format!("Count {count} does not fit in {available} bytes left in packet"),
+            at: Default::default(),
+        }),
+    }
+}
+
+/// Reads value of a previously decoded column
+fn lookup<'s>(
+    column: &BufferValue,
+    name: &str,
+    scope: Option<&'s Scope>,
+) -> Result<&'s Value, ReaderError> {
+    let scope = scope.ok_or_else(|| {
+        ReaderError::Config(format!(
+            "Column {} has variable layout, which needs previous columns to be decoded",
+            column.name
+        ))
+    })?;
+
+    scope.get(name).ok_or_else(|| ReaderError::InvalidValue {
+        column: column.name.clone(),
+        message: format!("{name} is not decoded before it"),
+        at: Default::default(),
     })
 }
 
-pub fn get_bit_slice(byte: u8, bit_offset: usize, length: usize, out_buf: &mut [u8]) {
-    for i in 0..length {
-        out_buf[i] = (byte >> (7 - bit_offset - i)) & 0x01;
+/// Reads count or length of column from a previously decoded column
+fn lookup_size(
+    column: &BufferValue,
+    name: &str,
+    scope: Option<&Scope>,
+) -> Result<usize, ReaderError> {
+    let value = lookup(column, name, scope)?;
+
+    value
+        .as_u64()
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| ReaderError::InvalidValue {
+            column: column.name.clone(),
+            message: format!("{name} is {value}, expected a non negative integer"),
+            at: Default::default(),
+        })
+}
+
+/// Reads one element of struct column and moves offset past it
+/// Offsets of fields are relative to start of struct
+fn read_struct(
+    column: &BufferValue,
+    fields: &[BufferValue],
+    layout: &Layout,
+    buf: &[u8],
+    offset: &mut usize,
+    scope: Option<&Scope>,
+    decode: bool,
+) -> Result<Value, ReaderError> {
+    // Size of struct is known without walking over it's fields
+    if !decode && column.length != 0 {
+        *offset += column.length;
+
+        return Ok(Value::Null);
+    }
+
+    let start = *offset;
+    let mut struct_offset = 0;
+    let mut bit_offset = 0;
+    let mut hashmap = Map::new();
+
+    read_columns(
+        fields,
+        layout,
+        buf.get(start..).unwrap_or_default(),
+        &mut struct_offset,
+        &mut bit_offset,
+        decode.then_some(&mut hashmap),
+        scope,
+    )?;
+
+    // Trailing bits take the whole byte
+    if bit_offset != 0 {
+        struct_offset += 1;
     }
+
+    // Like in c, size of struct is rounded up to packing
+    // Length in config overrides it
+    *offset = start
+        + if column.length != 0 {
+            column.length
+        } else if layout.packing > 0 && is_aligned(column, layout) {
+            struct_offset.next_multiple_of(layout.packing)
+        } else {
+            struct_offset
+        };
+
+    Ok(Value::Object(hashmap))
 }
 
-pub fn bits_to_u8(bits: [u8; 8]) -> u8 {
-    let byte = bits
-        .iter()
-        .enumerate()
-        .fold(0, |acc, (i, &bit)| acc | (bit << (7 - i)));
+/// Top level column which starts at offset, found by walking columns before it
+/// None when no column starts there or a column before it has variable size
+pub fn column_at<'c>(
+    columns: &'c [BufferValue],
+    layout: &Layout,
+    target: usize,
+) -> Option<&'c BufferValue> {
+    let mut offset = 0;
+    let mut bit_offset = 0;
+
+    for column in columns {
+        if column.switch.is_some() {
+            return None;
+        }
+
+        let (mut start, mut start_bit) = (offset, bit_offset);
+
+        seek_column(
+            column,
+            &mut start,
+            &mut start_bit,
+            layout.packing,
+            is_aligned(column, layout),
+        );
+
+        if start == target && start_bit == 0 && column.dtype != DType::Bit {
+            return Some(column);
+        }
+
+        read_columns(
+            std::slice::from_ref(column),
+            layout,
+            &[],
+            &mut offset,
+            &mut bit_offset,
+            None,
+            None,
+        )
+        .ok()?;
+    }
 
-    byte
+    None
 }
 
+/// If column is padded to start at a multiple of packing
+/// Structs are padded when any of their fields is
+fn is_aligned(column: &BufferValue, layout: &Layout) -> bool {
+    match column.dtype {
+        // Strings of variable length are not padded
+        _ if column.length_from.is_some() => false,
+        DType::Bit => false,
+        DType::Struct if column.length == 0 => struct_fields(column, layout.structs)
+            .is_ok_and(|fields| fields.iter().any(|field| is_aligned(field, layout))),
+        _ => column.length.is_multiple_of(layout.packing),
+    }
+}
+
+/// Reads column at offset and moves offset past it
+/// endian is used when column does not set it's own byte order
 pub fn col_from_buf(
     column: &BufferValue,
     buf: &[u8],
     offset: &mut usize,
     bit_offset: &mut usize,
     packing: usize,
-) -> Result<Value, Box<dyn Error>> {
-    // println!("{} {}", column.name, offset);
+    endian: Endian,
+) -> Result<Value, ReaderError> {
+    seek_column(
+        column,
+        offset,
+        bit_offset,
+        packing,
+        column.dtype != DType::Bit && column.length.is_multiple_of(packing),
+    );
+
+    read_value(
+        column,
+        column.length,
+        buf,
+        offset,
+        bit_offset,
+        column.endian.unwrap_or(endian),
+    )
+}
 
+/// Moves offset to start of column
+fn seek_column(
+    column: &BufferValue,
+    offset: &mut usize,
+    bit_offset: &mut usize,
+    packing: usize,
+    aligned: bool,
+) {
     // If byte sized column and bit_offset is non zero, increase offset and reset bit_offset
     if column.dtype != DType::Bit && *bit_offset != 0 {
         *bit_offset = 0;
@@ -78,46 +651,392 @@ pub fn col_from_buf(
     }
 
     // Calculation for padding
-    // Ignore for bit columns
-    if column.length % packing == 0 && *offset % packing != 0 && column.dtype != DType::Bit {
-        // println!("Adding offset at {} {}",column.name, offset);
+    // Existing configs are laid out with this rule, it is not rounding up to packing
+    if aligned && packing > 0 && !offset.is_multiple_of(packing) {
         *offset += *offset % packing;
     }
 
-    if column.offset.is_some() {
-        *offset = column.offset.unwrap();
+    if let Some(column_offset) = column.offset {
+        *offset = column_offset;
     }
+}
 
-    let mut bit_slice = [0; 8];
+/// Casts value of column at offset and moves offset past it
+fn read_value(
+    column: &BufferValue,
+    length: usize,
+    buf: &[u8],
+    offset: &mut usize,
+    bit_offset: &mut usize,
+    endian: Endian,
+) -> Result<Value, ReaderError> {
+    let bits;
 
     let slice = if column.dtype == DType::Bit {
-        get_bit_slice(buf[*offset], *bit_offset, column.length, &mut bit_slice);
+        // Bits are read from most significant bit, and can span over bytes
+        let end_bit = *bit_offset + length;
+        let end = offset
+            .checked_add(end_bit.div_ceil(8))
+            .ok_or_else(|| out_of_bounds(column, *offset, usize::MAX, buf.len()))?;
+
+        let bytes = buf
+            .get(*offset..end)
+            .ok_or_else(|| out_of_bounds(column, *offset, end, buf.len()))?;
+
+        let mut value = bytes
+            .iter()
+            .fold(0u128, |acc, &byte| acc << 8 | byte as u128)
+            >> (bytes.len() * 8 - end_bit)
+            & ((1u128 << length) - 1);
 
-        &bit_slice
+        // Up to 8 bits are kept at top of a byte, e.g. a set flag is 128
+        if length <= 8 {
+            value <<= 8 - length;
+        }
+
+        bits = (value as u64).to_be_bytes();
+
+        &bits[..]
     } else {
-        &buf[*offset..(*offset + column.length)]
+        let end = offset
+            .checked_add(length)
+            .ok_or_else(|| out_of_bounds(column, *offset, usize::MAX, buf.len()))?;
+
+        buf.get(*offset..end)
+            .ok_or_else(|| out_of_bounds(column, *offset, end, buf.len()))?
     };
 
-    // Increase offset depending on dtype
-    if column.dtype == DType::Bit {
-        *bit_offset += column.length;
+    skip_value(column, length, offset, bit_offset)?;
 
-        // Increase offset once bit offset is reached to 8
-        if *bit_offset == 8 {
-            *bit_offset = 0;
-            *offset += 1;
-        }
+    cast_bytes(slice, column, endian).map_err(|message| ReaderError::InvalidValue {
+        column: column.name.clone(),
+        message,
+        at: Default::default(),
+    })
+}
+
+/// Moves offset past value of column
+/// Fails when length from data moves it past end of memory
+fn skip_value(
+    column: &BufferValue,
+    length: usize,
+    offset: &mut usize,
+    bit_offset: &mut usize,
+) -> Result<(), ReaderError> {
+    let bytes = if column.dtype == DType::Bit {
+        *bit_offset += length;
+
+        let bytes = *bit_offset / 8;
+        *bit_offset %= 8;
+
+        bytes
     } else {
-        *offset += column.length;
+        length
+    };
+
+    *offset = offset
+        .checked_add(bytes)
+        .ok_or_else(|| out_of_bounds(column, *offset, usize::MAX, usize::MAX))?;
+
+    Ok(())
+}
+
+fn out_of_bounds(column: &BufferValue, start: usize, end: usize, available: usize) -> ReaderError {
+    ReaderError::ColumnOutOfBounds {
+        column: column.name.clone(),
+        start,
+        end,
+        available,
+        at: Default::default(),
+    }
+}
+
+/// Returns slice of length from offset and moves offset past it
+/// Fails with truncated packet if buffer is shorter than length
+pub fn get_buffer_slice<'a>(
+    buf: &'a [u8],
+    length: usize,
+    offset: &mut usize,
+) -> Result<&'a [u8], ReaderError> {
+    let truncated = || ReaderError::TruncatedPacket {
+        expected: length,
+        available: buf.len().saturating_sub(*offset),
+        at: Default::default(),
+    };
+
+    let end = offset.checked_add(length).ok_or_else(truncated)?;
+    let slice = buf.get(*offset..end).ok_or_else(truncated)?;
+
+    *offset = end;
+
+    Ok(slice)
+}
+
+/// Reads until buf is full or EOF is reached
+/// Returns number of bytes read, which is less than buf length only at EOF
+pub fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
     }
 
-    cast_bytes(slice, &column.dtype)
+    Ok(n)
 }
 
-pub fn get_buffer_slice<'a>(buf: &'a [u8], length: usize, offset: &mut usize) -> &'a [u8] {
-    let slice = &buf[*offset..*offset + length];
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn column(value: Value) -> BufferValue {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn cast(dtype: &str, buf: &[u8], endian: Endian) -> Value {
+        let length = buf.len();
+
+        cast_bytes(
+            buf,
+            &column(json!({"dtype": dtype, "length": length})),
+            endian,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn casts_integer_dtypes() {
+        assert_eq!(cast("u8", &[0xff], Endian::Big), json!(255));
+        assert_eq!(cast("i8", &[0xff], Endian::Big), json!(-1));
+        assert_eq!(cast("u16", &[0x01, 0x02], Endian::Big), json!(0x0102));
+        assert_eq!(cast("u16", &[0x01, 0x02], Endian::Little), json!(0x0201));
+        assert_eq!(cast("i16", &[0xff, 0xfe], Endian::Big), json!(-2));
+        assert_eq!(cast("u32", &[0, 0, 1, 0], Endian::Big), json!(256));
+        assert_eq!(cast("i64", &[0xff; 8], Endian::Little), json!(-1));
+        assert_eq!(
+            cast("u128", &[0xff; 16], Endian::Big),
+            json!(u128::MAX.to_string())
+        );
+        assert_eq!(cast("i128", &[0xff; 16], Endian::Big), json!("-1"));
+    }
+
+    #[test]
+    fn byte_is_one_byte_number() {
+        assert_eq!(cast("byte", &[0x2a], Endian::Big), json!(42));
+    }
+
+    #[test]
+    fn bytes_are_encoded_as_string() {
+        let buf = [0xde, 0xad, 0xbe, 0xef];
+
+        assert_eq!(cast("bytes", &buf, Endian::Big), json!("deadbeef"));
+
+        let base64 = column(json!({"dtype": "bytes", "length": 4, "encoding": "base64"}));
+
+        assert_eq!(
+            cast_bytes(&buf, &base64, Endian::Big).unwrap(),
+            json!("3q2+7w==")
+        );
+    }
 
-    *offset += length;
+    #[test]
+    fn bits_can_span_bytes() {
+        let buf = [0b1010_1100, 0b0011_1111, 0xff];
+        let (mut offset, mut bit_offset) = (0, 0);
+
+        let mut read = |length: usize| {
+            let column = column(json!({"dtype": "bit", "length": length}));
+
+            read_value(
+                &column,
+                length,
+                &buf,
+                &mut offset,
+                &mut bit_offset,
+                Endian::Big,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(read(3), json!(0b1010_0000));
+        assert_eq!(read(10), json!(0b01100_00111));
+        assert_eq!(read(11), json!(0b111_1111_1111));
+        assert_eq!((offset, bit_offset), (3, 0));
+    }
+
+    #[test]
+    fn flags_are_aligned_to_top_of_byte() {
+        let (record, offset) = read(
+            json!([
+                {"name": "Buy", "dtype": "bit", "length": 1},
+                {"name": "Sell", "dtype": "bit", "length": 1},
+                {"name": "Rest", "dtype": "bit", "length": 6},
+            ]),
+            &[0b0100_0011],
+        )
+        .unwrap();
+
+        assert_eq!(record["Buy"], json!(0));
+        assert_eq!(record["Sell"], json!(128));
+        assert_eq!(record["Rest"], json!(0b0000_1100));
+        assert_eq!(offset, 1);
+    }
+
+    fn read(columns: Value, buf: &[u8]) -> Result<(Map<String, Value>, usize), ReaderError> {
+        let columns: Vec<BufferValue> = serde_json::from_value(columns).unwrap();
+        let native = NativeSettings::default();
+        let mut hashmap = Map::new();
+        let (mut offset, mut bit_offset) = (0, 0);
+
+        read_columns(
+            &columns,
+            &Layout::new(&native),
+            buf,
+            &mut offset,
+            &mut bit_offset,
+            Some(&mut hashmap),
+            None,
+        )?;
+
+        Ok((hashmap, offset))
+    }
+
+    #[test]
+    fn reads_counts_and_lengths_from_columns() {
+        let columns = json!([
+            {"name": "n", "dtype": "u8", "length": 1},
+            {"name": "xs", "dtype": "u8", "length": 1, "count_from": "n"},
+            {"name": "len", "dtype": "u8", "length": 1},
+            {"name": "s", "dtype": "char", "length_from": "len"},
+        ]);
+
+        let (record, offset) = read(columns, &[2, 7, 8, 3, b'a', b'b', b'c']).unwrap();
+
+        assert_eq!(record["xs"], json!([7, 8]));
+        assert_eq!(record["s"], json!("abc"));
+        assert_eq!(offset, 7);
+    }
 
-    slice
+    #[test]
+    fn reads_arm_of_switch() {
+        let columns = json!([
+            {"name": "kind", "dtype": "u8", "length": 1},
+            {"name": "", "switch": {
+                "on": "kind",
+                "cases": {"1": [{"name": "a", "dtype": "u8", "length": 1}]},
+                "default": [{"name": "b", "dtype": "u16", "length": 2}],
+            }},
+            {"name": "c", "dtype": "u8", "length": 1},
+        ]);
+
+        let (record, offset) = read(columns.clone(), &[1, 5, 6]).unwrap();
+
+        assert_eq!(
+            record,
+            json!({"kind": 1, "a": 5, "c": 6})
+                .as_object()
+                .unwrap()
+                .clone()
+        );
+        assert_eq!(offset, 3);
+
+        let (record, offset) = read(columns, &[2, 0, 5, 6]).unwrap();
+
+        assert_eq!(record["b"], json!(5));
+        assert_eq!(record["c"], json!(6));
+        assert_eq!(offset, 4);
+    }
+
+    #[test]
+    fn unmatched_switch_is_an_error() {
+        let columns = json!([
+            {"name": "kind", "dtype": "u8", "length": 1},
+            {"name": "", "switch": {"on": "kind", "cases": {"1": []}}},
+        ]);
+
+        assert!(matches!(
+            read(columns, &[2]),
+            Err(ReaderError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn huge_length_from_data_is_an_error() {
+        let columns = json!([
+            {"name": "len", "dtype": "u64", "length": 8},
+            {"name": "s", "dtype": "char", "length_from": "len"},
+        ]);
+
+        let error = read(columns, &[0xff; 9]).unwrap_err();
+
+        assert!(matches!(error, ReaderError::ColumnOutOfBounds { .. }));
+    }
+
+    #[test]
+    fn count_from_data_must_fit_in_packet() {
+        let columns = json!([
+            {"name": "n", "dtype": "u32", "length": 4},
+            {"name": "xs", "dtype": "none", "length": 2, "count_from": "n"},
+        ]);
+
+        let error = read(columns.clone(), &[0xff, 0xff, 0xff, 0xff, 0, 0]).unwrap_err();
+
+        assert!(matches!(error, ReaderError::InvalidValue { .. }));
+
+        let (_, offset) = read(columns, &[0, 0, 0, 2, 0, 1, 0, 2]).unwrap();
+
+        assert_eq!(offset, 8);
+    }
+
+    #[test]
+    fn pads_columns_by_remainder_of_packing() {
+        let mut offset = 5;
+        let mut bit_offset = 0;
+
+        seek_column(
+            &column(json!({"dtype": "u32", "length": 4})),
+            &mut offset,
+            &mut bit_offset,
+            4,
+            true,
+        );
+
+        assert_eq!(offset, 6);
+
+        offset = 7;
+        seek_column(
+            &column(json!({"dtype": "u32", "length": 4})),
+            &mut offset,
+            &mut bit_offset,
+            4,
+            true,
+        );
+
+        assert_eq!(offset, 10);
+    }
+
+    #[test]
+    fn buffer_slice_does_not_overflow() {
+        let mut offset = 2;
+
+        assert!(get_buffer_slice(&[0; 4], usize::MAX, &mut offset).is_err());
+        assert_eq!(
+            get_buffer_slice(&[1, 2, 3, 4], 2, &mut offset).unwrap(),
+            &[3, 4]
+        );
+        assert_eq!(offset, 4);
+    }
+
+    #[test]
+    fn wrong_length_is_an_error() {
+        let column = column(json!({"dtype": "u32", "length": 4}));
+
+        assert!(cast_bytes(&[0, 1], &column, Endian::Big).is_err());
+    }
 }
@@ -1,11 +1,562 @@
-use crate::BufferValue;
+use std::{
+    collections::{BTreeMap, HashSet},
+    iter,
+};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    filter::root_column, BufferValue, Config, DType, MapOutput, NativeSettings, ReaderError,
+};
+
+use super::value_utils::MAX_DECIMAL_SCALE;
+
+/// Max nesting of struct columns
+/// Also stops structs which contain themselves
+const MAX_STRUCT_DEPTH: usize = 32;
 
 pub fn get_len_from_columns(columns: Vec<&BufferValue>) -> usize {
     let mut columns = columns.clone();
 
     columns.sort_by_key(|e| e.offset.unwrap_or(0));
 
-    let last = columns.last().unwrap();
+    columns
+        .last()
+        .map_or(0, |last| last.offset.unwrap_or(0) + last.length)
+}
+
+/// Checks length of column against size of it's dtype
+/// and if it's conversions are valid for it's dtype
+pub fn validate_column(
+    column: &BufferValue,
+    native: &NativeSettings,
+    depth: usize,
+) -> Result<(), ReaderError> {
+    if let Some(switch) = &column.switch {
+        if switch.on.is_empty() {
+            return Err(ReaderError::Config(
+                "Switch must have a column to switch on".to_string(),
+            ));
+        }
+
+        return switch
+            .cases
+            .values()
+            .chain(&switch.default)
+            .flatten()
+            .try_for_each(|column| validate_column(column, native, depth + 1));
+    }
+
+    if column.dtype == DType::Struct {
+        if depth >= MAX_STRUCT_DEPTH {
+            return Err(ReaderError::Config(format!(
+                "Struct column {} is nested deeper than {MAX_STRUCT_DEPTH}, is it recursive?",
+                column.name
+            )));
+        }
+
+        if column.decimal.is_some() || column.timestamp.is_some() {
+            return Err(ReaderError::Config(format!(
+                "Struct column {} can not be decimal or timestamp",
+                column.name
+            )));
+        }
+
+        let fields = struct_fields(column, &native.structs)?;
+
+        if fields.is_empty() {
+            return Err(ReaderError::Config(format!(
+                "Struct column {} has no fields",
+                column.name
+            )));
+        }
+
+        return fields
+            .iter()
+            .try_for_each(|field| validate_column(field, native, depth + 1));
+    } else if column.struct_name.is_some() || !column.fields.is_empty() {
+        return Err(ReaderError::Config(format!(
+            "Column {} has struct fields but dtype {:?}",
+            column.name, column.dtype
+        )));
+    }
+
+    if let Some(name) = &column.value_map {
+        if column.dtype == DType::Struct || !native.value_maps.contains_key(name) {
+            return Err(ReaderError::Config(format!(
+                "Column {} of dtype {:?} can not use value map {name}",
+                column.name, column.dtype
+            )));
+        }
+    }
+
+    if column.length_from.is_some()
+        && !matches!(column.dtype, DType::Char | DType::Bytes | DType::None)
+    {
+        return Err(ReaderError::Config(format!(
+            "Column {} of dtype {:?} can not have length_from",
+            column.name, column.dtype
+        )));
+    }
+
+    if let Some(format) = &column.decimal {
+        if matches!(
+            column.dtype,
+            DType::Char | DType::Bytes | DType::Bool | DType::None
+        ) {
+            return Err(ReaderError::Config(format!(
+                "Column {} of dtype {:?} can not be decimal",
+                column.name, column.dtype
+            )));
+        }
+
+        if format.scale > MAX_DECIMAL_SCALE {
+            return Err(ReaderError::Config(format!(
+                "Column {} has scale {}, max is {MAX_DECIMAL_SCALE}",
+                column.name, format.scale
+            )));
+        }
+
+        if format.divisor.is_some_and(|divisor| divisor.is_zero()) {
+            return Err(ReaderError::Config(format!(
+                "Column {} has divisor 0",
+                column.name
+            )));
+        }
+    }
+
+    // Timestamps are counts of time units
+    if column.timestamp.is_some()
+        && matches!(
+            column.dtype,
+            DType::Char | DType::Bytes | DType::Bool | DType::Bit | DType::None
+        )
+    {
+        return Err(ReaderError::Config(format!(
+            "Column {} of dtype {:?} can not be timestamp",
+            column.name, column.dtype
+        )));
+    }
+
+    match column.dtype.size() {
+        Some(size) if size != column.length => Err(ReaderError::Config(format!(
+            "Column {} of dtype {:?} must have length {size}, found {}",
+            column.name, column.dtype, column.length
+        ))),
+        None if column.dtype == DType::Bit && !(1..=64).contains(&column.length) => {
+            Err(ReaderError::Config(format!(
+                "Bit column {} must have length between 1 and 64, found {}",
+                column.name, column.length
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn validate_columns<'a>(
+    columns: impl IntoIterator<Item = &'a BufferValue>,
+    native: &NativeSettings,
+) -> Result<(), ReaderError> {
+    columns
+        .into_iter()
+        .try_for_each(|column| validate_column(column, native, 0))
+}
+
+/// Checks that size of columns does not depend on decoded values
+/// Native type reads packets of one size, which is known before reading
+pub fn validate_fixed_layout<'a>(
+    columns: impl IntoIterator<Item = &'a BufferValue>,
+    native: &NativeSettings,
+) -> Result<(), ReaderError> {
+    columns.into_iter().try_for_each(|column| {
+        if let Some(switch) = &column.switch {
+            return validate_fixed_layout(
+                switch.cases.values().chain(&switch.default).flatten(),
+                native,
+            );
+        }
+
+        // Counts from a previous column are fine when slots are fixed
+        if column.length_from.is_some() || (column.count_from.is_some() && column.count.is_none()) {
+            return Err(ReaderError::Config(format!(
+                "Column {} has variable size, Native type needs fixed layout; use MultiNative",
+                column.name
+            )));
+        }
+
+        if column.dtype == DType::Struct {
+            validate_fixed_layout(struct_fields(column, &native.structs)?, native)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Fields of struct column, from named definition or inline fields
+pub fn struct_fields<'a>(
+    column: &'a BufferValue,
+    structs: &'a BTreeMap<String, Vec<BufferValue>>,
+) -> Result<&'a [BufferValue], ReaderError> {
+    match &column.struct_name {
+        Some(name) => structs.get(name).map(Vec::as_slice).ok_or_else(|| {
+            ReaderError::Config(format!(
+                "Column {} uses undefined struct {name}",
+                column.name
+            ))
+        }),
+        None => Ok(&column.fields),
+    }
+}
+
+/// Columns with columns of switch arms in their place
+pub fn expand_switches(columns: &[BufferValue]) -> Vec<&BufferValue> {
+    columns
+        .iter()
+        .flat_map(|column| match &column.switch {
+            Some(switch) => switch
+                .cases
+                .values()
+                .chain(&switch.default)
+                .flat_map(|arm| expand_switches(arm))
+                .collect(),
+            None => vec![column],
+        })
+        .collect()
+}
+
+/// Columns returned by adapters, from selected_columns
+/// Columns are selected by top level name, fields of structs come with their column
+#[derive(Debug)]
+pub struct Projection {
+    selected: HashSet<String>,
+    /// Unselected columns of filter, decoded but removed once filter is applied
+    filtered: HashSet<String>,
+    /// Unselected columns which counts, lengths or switches are read from
+    referenced: HashSet<String>,
+    /// Native columns whose label is put under <name>_label
+    labelled: HashSet<String>,
+}
+
+impl Projection {
+    /// None when no columns are selected, every column is returned then
+    pub fn new(config: &Config) -> Option<Projection> {
+        if config.selected_columns.is_empty() {
+            return None;
+        }
+
+        let selected: HashSet<String> = config.selected_columns.iter().cloned().collect();
+
+        let filtered = config
+            .filter
+            .iter()
+            .flat_map(|filter| filter.columns())
+            .filter(|column| !selected.contains(*column))
+            .map(String::from)
+            .collect();
+
+        Some(Projection {
+            selected,
+            filtered,
+            referenced: HashSet::new(),
+            labelled: labelled_columns(config),
+        })
+    }
+
+    /// True for columns which are put in records
+    pub fn selects(&self, name: &str) -> bool {
+        self.selected.contains(name) || self.filtered.contains(name)
+    }
+
+    /// Removes columns which were only decoded for filter
+    pub fn trim(&self, record: &mut Map<String, Value>) {
+        if self.filtered.is_empty() {
+            return;
+        }
+
+        record.retain(|key, _| {
+            // Labels of value maps follow their column
+            let column = key
+                .strip_suffix("_label")
+                .filter(|column| self.labelled.contains(*column))
+                .unwrap_or(key);
+
+            !self.filtered.contains(root_column(key)) && !self.filtered.contains(column)
+        });
+    }
+
+    /// True for selected columns and columns which others depend on
+    pub fn needs(&self, name: &str) -> bool {
+        self.selects(name) || self.referenced.contains(name)
+    }
+
+    /// Errors for selected columns which are not in columns of file
+    pub fn check<'c>(&self, columns: impl IntoIterator<Item = &'c str>) -> Result<(), ReaderError> {
+        let columns: HashSet<&str> = columns.into_iter().collect();
+
+        let mut missing: Vec<&str> = self
+            .selected
+            .iter()
+            .chain(&self.filtered)
+            .map(String::as_str)
+            .filter(|name| !columns.contains(name))
+            .collect();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        missing.sort_unstable();
+
+        Err(ReaderError::Config(format!(
+            "Selected or filtered columns {} do not exist",
+            missing.join(", ")
+        )))
+    }
+
+    /// Checks selection against native columns and keeps columns others depend on
+    /// Added columns are set by adapter in every record, e.g. timestamp
+    pub fn for_native<'c>(
+        mut self,
+        columns: impl IntoIterator<Item = &'c [BufferValue]> + Clone,
+        native: &NativeSettings,
+        added: &[&str],
+    ) -> Result<Projection, ReaderError> {
+        self.check(
+            columns
+                .clone()
+                .into_iter()
+                .flat_map(expand_switches)
+                .filter(|column| column.dtype != DType::None && !column.ignore)
+                .map(|column| column.name.as_str())
+                .chain(added.iter().copied()),
+        )?;
+
+        for columns in columns {
+            self.reference(columns, native, 0);
+        }
+
+        Ok(self)
+    }
+
+    fn reference(&mut self, columns: &[BufferValue], native: &NativeSettings, depth: usize) {
+        // Columns are validated before, this only guards recursive structs
+        if depth >= MAX_STRUCT_DEPTH {
+            return;
+        }
+
+        for column in columns {
+            if let Some(switch) = &column.switch {
+                self.referenced.insert(switch.on.clone());
+
+                for arm in switch.cases.values().chain(&switch.default) {
+                    self.reference(arm, native, depth);
+                }
+            }
+
+            for name in [&column.count_from, &column.length_from]
+                .into_iter()
+                .flatten()
+            {
+                self.referenced.insert(name.clone());
+            }
+
+            if column.dtype == DType::Struct {
+                if let Ok(fields) = struct_fields(column, &native.structs) {
+                    // End of struct is only known by decoding it, unless length is set
+                    if column.length == 0 && is_variable(fields, native, depth + 1) {
+                        self.referenced.insert(column.name.clone());
+                    }
+
+                    self.reference(fields, native, depth + 1);
+                }
+            }
+        }
+    }
+}
+
+/// Top level native columns of value maps which output code and label
+fn labelled_columns(config: &Config) -> HashSet<String> {
+    let native = &config.native;
+
+    iter::once(&config.native_columns[..])
+        .chain(
+            native
+                .packet_info
+                .column_details
+                .values()
+                .map(|details| &details.columns[..]),
+        )
+        .flat_map(expand_switches)
+        .filter(|column| {
+            column
+                .value_map
+                .as_ref()
+                .and_then(|name| native.value_maps.get(name))
+                .is_some_and(|map| map.output == MapOutput::Both)
+        })
+        .map(|column| column.name.clone())
+        .collect()
+}
+
+/// True when size of columns depends on decoded values
+/// Fixed slots of count_from are walked over without them
+fn is_variable(columns: &[BufferValue], native: &NativeSettings, depth: usize) -> bool {
+    depth < MAX_STRUCT_DEPTH
+        && columns.iter().any(|column| {
+            column.switch.is_some()
+                || column.length_from.is_some()
+                || (column.count_from.is_some() && column.count.is_none())
+                || (column.dtype == DType::Struct
+                    && column.length == 0
+                    && struct_fields(column, &native.structs)
+                        .is_ok_and(|fields| is_variable(fields, native, depth + 1)))
+        })
+}
+
+/// Columns of file with unselected ones set to None
+pub fn select_columns(
+    columns: Vec<String>,
+    config: &Config,
+) -> Result<Vec<Option<String>>, ReaderError> {
+    let Some(projection) = Projection::new(config) else {
+        return Ok(columns.into_iter().map(Some).collect());
+    };
+
+    projection.check(columns.iter().map(String::as_str))?;
+
+    Ok(columns
+        .into_iter()
+        .map(|column| projection.selects(&column).then_some(column))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn column(value: Value) -> BufferValue {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn validate(value: Value) -> Result<(), ReaderError> {
+        validate_column(&column(value), &NativeSettings::default(), 0)
+    }
+
+    #[test]
+    fn length_must_match_dtype() {
+        assert!(validate(json!({"name": "a", "dtype": "u16", "length": 2})).is_ok());
+        assert!(validate(json!({"name": "a", "dtype": "byte", "length": 1})).is_ok());
+        assert!(validate(json!({"name": "a", "dtype": "bytes", "length": 7})).is_ok());
+
+        for (dtype, length) in [("u16", 4), ("i128", 8), ("byte", 2), ("u8", 2)] {
+            let result = validate(json!({"name": "a", "dtype": dtype, "length": length}));
+
+            assert!(
+                matches!(result, Err(ReaderError::Config(_))),
+                "{dtype} of length {length}"
+            );
+        }
+    }
+
+    fn projection(config: Value, columns: Value) -> Result<Projection, ReaderError> {
+        let config: Config = serde_json::from_value(config).unwrap();
+        let columns: Vec<BufferValue> = serde_json::from_value(columns).unwrap();
+
+        Projection::new(&config)
+            .unwrap()
+            .for_native([&columns[..]], &config.native, &["timestamp"])
+    }
+
+    #[test]
+    fn needs_columns_others_depend_on() {
+        let projection = projection(
+            json!({"selected_columns": ["xs"]}),
+            json!([
+                {"name": "n", "dtype": "u8", "length": 1},
+                {"name": "xs", "dtype": "u8", "length": 1, "count_from": "n"},
+                {"name": "other", "dtype": "u8", "length": 1},
+            ]),
+        )
+        .unwrap();
+
+        assert!(projection.selects("xs"));
+        assert!(!projection.selects("n"));
+        assert!(projection.needs("n"));
+        assert!(!projection.needs("other"));
+    }
+
+    #[test]
+    fn needs_structs_of_variable_size() {
+        let projection = projection(
+            json!({"selected_columns": ["Id"]}),
+            json!([
+                {"name": "Id", "dtype": "u8", "length": 1},
+                {"name": "S", "dtype": "struct", "fields": [
+                    {"name": "m", "dtype": "u8", "length": 1},
+                    {"name": "ys", "dtype": "u8", "length": 1, "count_from": "m"},
+                ]},
+                {"name": "Fixed", "dtype": "struct", "fields": [
+                    {"name": "k", "dtype": "u8", "length": 1},
+                    {"name": "zs", "dtype": "u8", "length": 1, "count": 2, "count_from": "k"},
+                ]},
+            ]),
+        )
+        .unwrap();
+
+        assert!(projection.needs("S"));
+        assert!(!projection.needs("Fixed"));
+    }
+
+    #[test]
+    fn filter_columns_are_trimmed() {
+        let mut projection = projection(
+            json!({
+                "selected_columns": ["a", "c_label"],
+                "filter": "b > 1 and c > 1",
+                "native": {"value_maps": {"N": {"labels": {"2": "two"}, "output": "both"}}},
+                "native_columns": [
+                    {"name": "b", "dtype": "u8", "length": 1, "value_map": "N"},
+                ],
+            }),
+            json!([
+                {"name": "a", "dtype": "u8", "length": 1},
+                {"name": "b", "dtype": "u8", "length": 1, "value_map": "N"},
+                {"name": "c", "dtype": "u8", "length": 1},
+                {"name": "c_label", "dtype": "char", "length": 1},
+            ]),
+        )
+        .unwrap();
+
+        assert!(projection.selects("b"));
+
+        // c_label is a column of it's own, not a label of c
+        let mut record = json!({"a": 1, "b": 2, "b_label": "two", "c": 2, "c_label": "x"})
+            .as_object()
+            .unwrap()
+            .clone();
+
+        projection.trim(&mut record);
+
+        assert_eq!(Value::Object(record), json!({"a": 1, "c_label": "x"}));
+
+        projection.selected.insert("missing".to_string());
+
+        assert!(projection.check(["a", "b"]).is_err());
+    }
+
+    #[test]
+    fn unknown_selected_columns_are_an_error() {
+        let result = projection(
+            json!({"selected_columns": ["a", "nope"]}),
+            json!([{"name": "a", "dtype": "u8", "length": 1}]),
+        );
+
+        let Err(ReaderError::Config(message)) = result else {
+            panic!("missing column must be an error");
+        };
 
-    last.offset.unwrap_or(0) + last.length
+        assert!(message.contains("nope"));
+    }
 }
@@ -0,0 +1,380 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use bzip2::bufread::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder;
+use xz2::bufread::XzDecoder;
+
+use crate::{FileCompression, ReadSeek, Source};
+
+/// Bytes read at once while scanning backwards
+const TAIL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Opened source, decompressed if needed
+/// Files and buffers can seek, streams and compressed sources are read through
+pub enum Input {
+    Seekable(BufReader<Box<dyn ReadSeek>>),
+    Stream {
+        reader: BufReader<Box<dyn Read>>,
+        /// Bytes consumed so far, streams can only seek forward from here
+        position: u64,
+    },
+}
+
+/// Opens source and wraps it in a decoder for it's compression
+/// Auto detects compression by magic bytes, then by extension of paths
+pub fn open_input(source: Source, compression: FileCompression) -> io::Result<Input> {
+    open(source, compression, true)
+}
+
+/// Opens binary source, auto detecting compression only by extension of paths
+/// Raw packets may start with bytes which look like magic bytes
+pub fn open_binary_input(source: Source, compression: FileCompression) -> io::Result<Input> {
+    open(source, compression, false)
+}
+
+fn open(source: Source, compression: FileCompression, sniff: bool) -> io::Result<Input> {
+    let (mut input, file_path) = match source {
+        Source::Path(path) => {
+            let file: Box<dyn ReadSeek> = Box::new(File::open(&path)?);
+
+            (Input::Seekable(BufReader::new(file)), Some(path))
+        }
+        Source::Bytes(bytes) => {
+            let cursor: Box<dyn ReadSeek> = Box::new(Cursor::new(bytes));
+
+            (Input::Seekable(BufReader::new(cursor)), None)
+        }
+        Source::Stream(reader) => (Input::stream(reader), None),
+        Source::Seekable(reader) => (Input::Seekable(BufReader::new(reader)), None),
+    };
+
+    let compression = match compression {
+        FileCompression::Auto => {
+            let magic = match sniff {
+                true => detect_by_magic(input.fill_buf()?),
+                false => None,
+            };
+
+            magic
+                .or_else(|| file_path.as_deref().map(detect_by_extension))
+                .unwrap_or(FileCompression::None)
+        }
+        compression => compression,
+    };
+
+    let decoder: Box<dyn Read> = match compression {
+        FileCompression::Auto | FileCompression::None => return Ok(input),
+        FileCompression::Gzip => Box::new(MultiGzDecoder::new(input)),
+        FileCompression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(input)?),
+        FileCompression::Bzip2 => Box::new(MultiBzDecoder::new(input)),
+        FileCompression::Xz => Box::new(XzDecoder::new_multi_decoder(input)),
+    };
+
+    Ok(Input::stream(decoder))
+}
+
+fn detect_by_magic(magic: &[u8]) -> Option<FileCompression> {
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Some(FileCompression::Gzip)
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(FileCompression::Zstd)
+    } else if magic.starts_with(b"BZh") {
+        Some(FileCompression::Bzip2)
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Some(FileCompression::Xz)
+    } else {
+        None
+    }
+}
+
+fn detect_by_extension(file_path: &str) -> FileCompression {
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("gz" | "gzip") => FileCompression::Gzip,
+        Some("zst" | "zstd") => FileCompression::Zstd,
+        Some("bz2") => FileCompression::Bzip2,
+        Some("xz") => FileCompression::Xz,
+        _ => FileCompression::None,
+    }
+}
+
+impl Input {
+    fn stream(reader: Box<dyn Read>) -> Input {
+        Input::Stream {
+            reader: BufReader::new(reader),
+            position: 0,
+        }
+    }
+
+    /// Moves n bytes forward
+    /// Seeks when possible, otherwise bytes are read and discarded
+    pub fn skip(&mut self, n: u64) -> io::Result<()> {
+        self.seek(SeekFrom::Current(i64::try_from(n).unwrap_or(i64::MAX)))
+            .map(|_| ())
+    }
+
+    /// Seeks to start of n'th line from end, scanning file backwards
+    /// Returns it's offset, None for streams which can not be scanned backwards
+    pub fn seek_last_lines(&mut self, n: u64) -> io::Result<Option<u64>> {
+        let Input::Seekable(reader) = self else {
+            return Ok(None);
+        };
+
+        let len = reader.seek(SeekFrom::End(0))?;
+
+        let mut chunk = vec![0; TAIL_CHUNK_SIZE];
+        let mut end = len;
+        let mut lines = 0;
+        let mut offset = if n == 0 { len } else { 0 };
+
+        'scan: while end > 0 && lines < n {
+            let start = end.saturating_sub(TAIL_CHUNK_SIZE as u64);
+            let chunk = &mut chunk[..(end - start) as usize];
+
+            reader.seek(SeekFrom::Start(start))?;
+            reader.read_exact(chunk)?;
+
+            // Newline ending last line does not start another one
+            for (i, byte) in chunk.iter().enumerate().rev() {
+                let next = start + i as u64 + 1;
+
+                if *byte == b'\n' && next < len {
+                    lines += 1;
+
+                    if lines == n {
+                        offset = next;
+                        break 'scan;
+                    }
+                }
+            }
+
+            end = start;
+        }
+
+        reader.seek(SeekFrom::Start(offset))?;
+
+        Ok(Some(offset))
+    }
+
+    /// Bytes left after current position, None for streams
+    pub fn remaining_len(&mut self) -> io::Result<Option<u64>> {
+        let Input::Seekable(reader) = self else {
+            return Ok(None);
+        };
+
+        let position = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(position))?;
+
+        Ok(Some(len.saturating_sub(position)))
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Input::Seekable(reader) => reader.seek(pos),
+            Input::Stream { reader, position } => {
+                let target = match pos {
+                    SeekFrom::Start(offset) => Some(offset),
+                    SeekFrom::Current(offset) => position.checked_add_signed(offset),
+                    SeekFrom::End(_) => None,
+                };
+
+                match target {
+                    Some(target) if target >= *position => {
+                        *position +=
+                            io::copy(&mut reader.take(target - *position), &mut io::sink())?;
+
+                        Ok(*position)
+                    }
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "Streams and compressed files can only seek forward",
+                    )),
+                }
+            }
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Seekable(reader) => reader.read(buf),
+            Input::Stream { reader, position } => {
+                let n = reader.read(buf)?;
+                *position += n as u64;
+
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl BufRead for Input {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Input::Seekable(reader) => reader.fill_buf(),
+            Input::Stream { reader, .. } => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            Input::Seekable(reader) => reader.consume(amt),
+            Input::Stream { reader, position } => {
+                *position += amt as u64;
+                reader.consume(amt);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{Config, Position, Reader, Type};
+
+    const LINES: &[u8] = b"{\"a\": 1}\n{\"a\": 2}\n{\"a\": 3}\n";
+
+    fn compressed(compression: FileCompression) -> Vec<u8> {
+        match compression {
+            FileCompression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(LINES).unwrap();
+                encoder.finish().unwrap()
+            }
+            FileCompression::Zstd => zstd::encode_all(LINES, 0).unwrap(),
+            FileCompression::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(LINES).unwrap();
+                encoder.finish().unwrap()
+            }
+            FileCompression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(LINES).unwrap();
+                encoder.finish().unwrap()
+            }
+            FileCompression::Auto | FileCompression::None => LINES.to_vec(),
+        }
+    }
+
+    fn reader(bytes: Vec<u8>, file_compression: &str) -> Reader {
+        let config: Config = serde_json::from_value(json!({
+            "selected_columns": [],
+            "file_compression": file_compression,
+        }))
+        .unwrap();
+
+        Reader::new_with_config(config, bytes, Type::JsonLines)
+    }
+
+    fn values(reader: &Reader, position: Position) -> Vec<Value> {
+        reader
+            .read_at(position, None)
+            .unwrap()
+            .into_iter()
+            .map(|record| record["a"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn detects_compression_by_magic_bytes() {
+        for compression in [
+            FileCompression::Gzip,
+            FileCompression::Zstd,
+            FileCompression::Bzip2,
+            FileCompression::Xz,
+        ] {
+            let reader = reader(compressed(compression), "auto");
+
+            assert_eq!(
+                values(&reader, Position::FromStart(1)),
+                [json!(2), json!(3)],
+                "{compression:?}"
+            );
+            assert_eq!(values(&reader, Position::FromEnd(1)), [json!(3)]);
+            assert_eq!(reader.count().unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn detects_compression_of_paths_by_extension() {
+        assert_eq!(
+            detect_by_extension("capture.jsonl.GZ"),
+            FileCompression::Gzip
+        );
+        assert_eq!(detect_by_extension("capture.zst"), FileCompression::Zstd);
+        assert_eq!(detect_by_extension("capture.bz2"), FileCompression::Bzip2);
+        assert_eq!(detect_by_extension("capture.xz"), FileCompression::Xz);
+        assert_eq!(detect_by_extension("capture.bin"), FileCompression::None);
+        assert_eq!(detect_by_magic(LINES), None);
+    }
+
+    #[test]
+    fn set_compression_is_not_detected() {
+        let reader = reader(compressed(FileCompression::Gzip), "none");
+
+        assert!(reader.read(None, None).is_err());
+        assert_eq!(
+            values(
+                &self::reader(LINES.to_vec(), "none"),
+                Position::FromStart(0)
+            )
+            .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn streams_only_seek_forward() {
+        let mut input = open_input(
+            Source::Bytes(compressed(FileCompression::Gzip).into()),
+            FileCompression::Auto,
+        )
+        .unwrap();
+
+        input.skip(9).unwrap();
+
+        let mut line = String::new();
+        input.read_line(&mut line).unwrap();
+
+        assert_eq!(line, "{\"a\": 2}\n");
+        assert_eq!(input.remaining_len().unwrap(), None);
+        assert!(input.seek(SeekFrom::Start(0)).is_err());
+    }
+
+    #[test]
+    fn binary_files_are_not_detected_by_content() {
+        // Raw packet starting like gzip
+        let packet = vec![0x1f, 0x8b, 0, 1];
+
+        let mut input =
+            open_binary_input(Source::Bytes(packet.clone().into()), FileCompression::Auto).unwrap();
+
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes).unwrap();
+
+        assert_eq!(bytes, packet);
+        assert!(
+            open_input(Source::Bytes(packet.into()), FileCompression::Auto)
+                .and_then(|mut input| input.read_to_end(&mut Vec::new()))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    fmt,
+    io::{BufRead, Read},
+    marker::PhantomData,
+};
+
+use serde::{
+    de::{DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::{Map, Value};
+
+use crate::{Checkpoint, CheckpointIter, Location, ReaderError};
+
+use super::column_utils::Projection;
+
+/// Lazily deserializes elements of a top level json array
+/// Elements are expected to be objects or arrays
+/// because scalar elements need one byte lookahead which is lost between elements
+pub struct JsonArrayIter<R, T> {
+    reader: CountingReader<R>,
+    position: u64,
+    started: bool,
+    done: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<R: BufRead, T: DeserializeOwned> JsonArrayIter<R, T> {
+    pub fn new(reader: R) -> JsonArrayIter<R, T> {
+        JsonArrayIter {
+            reader: CountingReader {
+                inner: reader,
+                count: 0,
+            },
+            position: 0,
+            started: false,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Continues reading at an element boundary from an earlier location
+    /// Reader must be at offset, just after element position - 1
+    pub fn resume(reader: R, location: Location) -> JsonArrayIter<R, T> {
+        JsonArrayIter {
+            reader: CountingReader {
+                inner: reader,
+                count: location.offset,
+            },
+            position: location.position,
+            started: true,
+            done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Byte offset before next element and it's index
+    pub fn location(&self) -> Location {
+        Location {
+            offset: self.reader.count,
+            position: self.position,
+        }
+    }
+
+    /// Deserializes next element with seed instead of T
+    pub fn next_seed<'de, S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Option<Result<S::Value, ReaderError>> {
+        match self.next_element_start() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let element =
+            seed.deserialize(&mut serde_json::Deserializer::from_reader(&mut self.reader));
+
+        self.position += 1;
+
+        // Position in stream is unknown after a bad element
+        if element.is_err() {
+            self.done = true;
+        }
+
+        Some(element.map_err(ReaderError::from))
+    }
+
+    /// Skip n elements without building values for them
+    pub fn skip_elements(&mut self, n: u64) -> Result<(), ReaderError> {
+        for _ in 0..n {
+            if !self.skip_element()? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Skip next element without building value for it
+    /// Returns false once closing bracket is consumed
+    pub fn skip_element(&mut self) -> Result<bool, ReaderError> {
+        if !self.next_element_start()? {
+            return Ok(false);
+        }
+
+        let element =
+            IgnoredAny::deserialize(&mut serde_json::Deserializer::from_reader(&mut self.reader));
+
+        self.position += 1;
+
+        // Position in stream is unknown after a bad element
+        if element.is_err() {
+            self.done = true;
+        }
+
+        element?;
+
+        Ok(true)
+    }
+
+    /// Moves reader to start of next element
+    /// Returns false once closing bracket is consumed
+    fn next_element_start(&mut self) -> Result<bool, ReaderError> {
+        if self.done {
+            return Ok(false);
+        }
+
+        // First call must see opening bracket, others must see a separator
+        let expected = if self.started { b',' } else { b'[' };
+
+        match peek_byte(&mut self.reader)? {
+            Some(b']') if self.started => {
+                self.reader.consume(1);
+                self.done = true;
+
+                return Ok(false);
+            }
+            Some(byte) if byte == expected => self.reader.consume(1),
+            Some(byte) => {
+                self.done = true;
+
+                return Err(self.invalid(format!("Unexpected '{}' in json array", byte as char)));
+            }
+            None => {
+                self.done = true;
+
+                return Err(self.invalid("Unexpected end of json array".to_string()));
+            }
+        }
+
+        // Empty array
+        if !self.started {
+            self.started = true;
+
+            if peek_byte(&mut self.reader)? == Some(b']') {
+                self.reader.consume(1);
+                self.done = true;
+
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn invalid(&self, message: String) -> ReaderError {
+        ReaderError::InvalidRecord {
+            message,
+            at: self.location(),
+        }
+    }
+}
+
+impl<R: BufRead, T: DeserializeOwned> Iterator for JsonArrayIter<R, T> {
+    type Item = Result<T, ReaderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_seed(PhantomData::<T>)
+    }
+}
+
+/// Json object with values built only for selected keys
+/// Every key is kept without projection
+#[derive(Clone, Copy)]
+pub struct ObjectRecord<'p>(pub Option<&'p Projection>);
+
+impl ObjectRecord<'_> {
+    /// Decodes one json object from line
+    pub fn parse(self, line: &str) -> Result<Map<String, Value>, serde_json::Error> {
+        let mut deserializer = serde_json::Deserializer::from_str(line);
+
+        let record = self.deserialize(&mut deserializer)?;
+        deserializer.end()?;
+
+        Ok(record)
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for ObjectRecord<'_> {
+    type Value = Map<String, Value>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ObjectRecord<'_> {
+    type Value = Map<String, Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a json object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut record = Map::new();
+
+        while let Some(key) = map.next_key::<String>()? {
+            if self.0.is_none_or(|projection| projection.selects(&key)) {
+                let value = map.next_value()?;
+                record.insert(key, value);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+
+        Ok(record)
+    }
+}
+
+/// Json array with it's elements named by columns
+/// Elements of unselected columns, set to None, are not built
+#[derive(Clone, Copy)]
+pub struct ArrayRecord<'c>(pub &'c [Option<String>]);
+
+impl<'de> DeserializeSeed<'de> for ArrayRecord<'_> {
+    type Value = Map<String, Value>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ArrayRecord<'_> {
+    type Value = Map<String, Value>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a json array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut record = Map::new();
+        let mut columns = self.0.iter();
+
+        // Elements past last column are walked over
+        loop {
+            match columns.next() {
+                Some(Some(name)) => match seq.next_element()? {
+                    Some(value) => {
+                        record.insert(name.clone(), value);
+                    }
+                    None => break,
+                },
+                _ => {
+                    if seq.next_element::<IgnoredAny>()?.is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(record)
+    }
+}
+
+/// Walks remaining elements and reports where each starts
+/// Records are numbered without leading header rows
+pub fn element_checkpoints<'a, R: BufRead + 'a>(
+    mut values: JsonArrayIter<R, IgnoredAny>,
+    header_rows: u64,
+) -> CheckpointIter<'a> {
+    Box::new(std::iter::from_fn(move || {
+        let at = values.location();
+
+        match values.skip_element() {
+            Ok(true) => Some(Ok(Checkpoint {
+                record: at.position - header_rows,
+                offset: at.offset,
+                inner: 0,
+            })),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }))
+}
+
+/// Returns next non whitespace byte without consuming it
+fn peek_byte<R: BufRead>(reader: &mut R) -> Result<Option<u8>, ReaderError> {
+    loop {
+        let buf = reader.fill_buf()?;
+
+        if buf.is_empty() {
+            return Ok(None);
+        }
+
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => {
+                let byte = buf[i];
+                reader.consume(i);
+
+                return Ok(Some(byte));
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Keeps count of bytes consumed from inner reader
+/// Used to report byte offset of errors
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count += amt as u64;
+        self.inner.consume(amt);
+    }
+}
@@ -1,2 +1,5 @@
 pub mod byte_utils;
 pub mod column_utils;
+pub mod file_utils;
+pub mod json_utils;
+pub mod value_utils;
@@ -0,0 +1,355 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, SecondsFormat, TimeDelta};
+use chrono_tz::Tz;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+use crate::{
+    DecimalFormat, DecimalOutput, OnUnknown, TimeUnit, TimeZone, TimestampFormat, ValueMap,
+};
+
+/// Largest scale supported by decimal
+pub const MAX_DECIMAL_SCALE: u32 = 28;
+
+/// Applies multiplier, divisor, scale and offset to a raw numeric value
+pub fn scale_value(value: Value, format: &DecimalFormat) -> Result<Value, String> {
+    let raw = match &value {
+        Value::Number(n) => {
+            Decimal::from_str(&n.to_string()).or_else(|_| Decimal::from_scientific(&n.to_string()))
+        }
+        // 128 bit values are decoded as strings
+        Value::String(s) => Decimal::from_str(s),
+        _ => return Err(format!("Can not scale non numeric value {value}")),
+    }
+    .map_err(|e| format!("Can not convert {value} to decimal: {e}"))?;
+
+    let overflow = || format!("Decimal overflow while scaling {value}");
+
+    let mut decimal = raw;
+
+    if let Some(multiplier) = format.multiplier {
+        decimal = decimal.checked_mul(multiplier).ok_or_else(overflow)?;
+    }
+
+    if let Some(divisor) = format.divisor {
+        decimal = decimal
+            .checked_div(divisor)
+            .ok_or_else(|| format!("Can not divide {value} by {divisor}"))?;
+    }
+
+    if format.scale != 0 {
+        let mut scaled = decimal;
+
+        // Raw integers keep all decimal places, e.g. 12300 with scale 2 is 123.00
+        scaled
+            .set_scale(decimal.scale() + format.scale)
+            .map_err(|_| overflow())?;
+
+        decimal = scaled;
+    }
+
+    if let Some(offset) = format.offset {
+        decimal = decimal.checked_add(offset).ok_or_else(overflow)?;
+    }
+
+    Ok(match format.output {
+        DecimalOutput::String => Value::String(decimal.to_string()),
+        DecimalOutput::Number => decimal
+            .to_f64()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number)
+            .ok_or_else(overflow)?,
+    })
+}
+
+/// Converts a raw count of time units since epoch to datetime string
+pub fn format_timestamp(value: Value, format: &TimestampFormat) -> Result<Value, String> {
+    let unit_nanos: i128 = match format.unit {
+        TimeUnit::S => 1_000_000_000,
+        TimeUnit::Ms => 1_000_000,
+        TimeUnit::Us => 1_000,
+        TimeUnit::Ns => 1,
+    };
+
+    // Nanoseconds since epoch
+    let nanos = match &value {
+        Value::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(n), _, _) => n as i128 * unit_nanos,
+            (_, Some(n), _) => n as i128 * unit_nanos,
+            (_, _, Some(n)) => (n * unit_nanos as f64) as i128,
+            _ => return Err(format!("Invalid timestamp {value}")),
+        },
+        _ => {
+            return Err(format!(
+                "Can not convert non numeric value {value} to timestamp"
+            ))
+        }
+    };
+
+    let delta = i64::try_from(nanos.div_euclid(1_000_000_000))
+        .ok()
+        .and_then(|secs| TimeDelta::new(secs, nanos.rem_euclid(1_000_000_000) as u32))
+        .ok_or_else(|| format!("Timestamp {value} out of range"))?;
+
+    let epoch = format.epoch.map_or(DateTime::UNIX_EPOCH, |e| e.to_utc());
+
+    let datetime = epoch
+        .checked_add_signed(delta)
+        .ok_or_else(|| format!("Timestamp {value} out of range"))?;
+
+    Ok(Value::String(match format.timezone {
+        Some(TimeZone::Named(tz)) => render(datetime.with_timezone(&tz), format),
+        Some(TimeZone::Fixed(offset)) => render(datetime.with_timezone(&offset), format),
+        None => render(datetime, format),
+    }))
+}
+
+fn render<T: chrono::TimeZone>(datetime: DateTime<T>, format: &TimestampFormat) -> String
+where
+    T::Offset: Display,
+{
+    match &format.format {
+        Some(pattern) => datetime.format(pattern).to_string(),
+        None => datetime.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    }
+}
+
+/// Parses datetime strings emitted for timestamp columns
+/// Use this to get typed datetimes from records
+pub fn parse_datetime(value: &Value) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value.as_str()?).ok()
+}
+
+/// Accepts date, naive datetime in UTC, or RFC 3339 datetime
+pub fn deserialize_epoch<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<FixedOffset>>, D::Error> {
+    let epoch = String::deserialize(deserializer)?;
+
+    let datetime = DateTime::parse_from_rfc3339(&epoch)
+        .or_else(|_| NaiveDateTime::from_str(&epoch).map(|naive| naive.and_utc().fixed_offset()))
+        .or_else(|_| {
+            NaiveDate::from_str(&epoch)
+                .map(|date| date.and_time(Default::default()).and_utc().fixed_offset())
+        })
+        .map_err(|_| serde::de::Error::custom(format!("Invalid epoch {epoch}")))?;
+
+    Ok(Some(datetime))
+}
+
+/// Accepts IANA timezone name or fixed offset
+pub fn deserialize_timezone<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<TimeZone>, D::Error> {
+    let timezone = String::deserialize(deserializer)?;
+
+    let parsed = match Tz::from_str(&timezone) {
+        Ok(tz) => TimeZone::Named(tz),
+        Err(_) => TimeZone::Fixed(
+            FixedOffset::from_str(&timezone)
+                .map_err(|_| serde::de::Error::custom(format!("Invalid timezone {timezone}")))?,
+        ),
+    };
+
+    Ok(Some(parsed))
+}
+
+/// Expands struct and array values to dotted names
+/// e.g. MBP_INFORMATION[0].Quantity
+pub fn flatten_record(record: Map<String, Value>) -> Map<String, Value> {
+    let mut flat = Map::new();
+
+    for (name, value) in record {
+        flatten_value(name, value, &mut flat);
+    }
+
+    flat
+}
+
+fn flatten_value(name: String, value: Value, flat: &mut Map<String, Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (field, value) in fields {
+                flatten_value(format!("{name}.{field}"), value, flat);
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.into_iter().enumerate() {
+                flatten_value(format!("{name}[{i}]"), value, flat);
+            }
+        }
+        value => {
+            flat.insert(name, value);
+        }
+    }
+}
+
+/// Text of value used to match switch cases and value map codes
+/// Chars are padded with spaces or nulls, which are trimmed
+pub fn value_key(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.trim_end_matches([' ', '\0']).to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Replaces code with it's label from map
+/// Arrays are mapped element wise
+pub fn map_value(value: &Value, map: &ValueMap) -> Result<Value, String> {
+    if let Value::Array(values) = value {
+        return values
+            .iter()
+            .map(|value| map_value(value, map))
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+
+    let key = value_key(value);
+
+    match map.labels.get(&key) {
+        Some(label) => Ok(Value::String(label.clone())),
+        None => match map.on_unknown {
+            OnUnknown::Keep => Ok(value.clone()),
+            OnUnknown::Null => Ok(Value::Null),
+            OnUnknown::Error => Err(format!("Unknown code {key}")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn decimal(format: Value, value: Value) -> Result<Value, String> {
+        scale_value(value, &serde_json::from_value(format).unwrap())
+    }
+
+    #[test]
+    fn scales_raw_integers_to_decimals() {
+        assert_eq!(
+            decimal(json!({"scale": 2}), json!(12345)),
+            Ok(json!("123.45"))
+        );
+        assert_eq!(decimal(json!({"scale": 2}), json!(-5)), Ok(json!("-0.05")));
+        assert_eq!(
+            decimal(json!({"scale": 2}), json!(12300)),
+            Ok(json!("123.00"))
+        );
+        assert_eq!(
+            decimal(json!({"scale": 4}), json!(u128::MAX.to_string())),
+            Err(format!(
+                "Can not convert \"{}\" to decimal: Invalid decimal: overflow from too many digits",
+                u128::MAX
+            ))
+        );
+    }
+
+    #[test]
+    fn applies_multiplier_divisor_and_offset() {
+        assert_eq!(
+            decimal(json!({"divisor": "100", "offset": "0.5"}), json!(250)),
+            Ok(json!("3.00"))
+        );
+        assert_eq!(
+            decimal(json!({"multiplier": "3", "scale": 1}), json!(5)),
+            Ok(json!("1.5"))
+        );
+        assert!(decimal(json!({"divisor": "0"}), json!(1)).is_err());
+        assert!(decimal(json!({"scale": 2}), json!("abc")).is_err());
+    }
+
+    #[test]
+    fn outputs_decimals_as_numbers() {
+        assert_eq!(
+            decimal(json!({"scale": 2, "output": "number"}), json!(12345)),
+            Ok(json!(123.45))
+        );
+    }
+
+    fn timestamp(format: Value, value: Value) -> Result<Value, String> {
+        format_timestamp(value, &serde_json::from_value(format).unwrap())
+    }
+
+    #[test]
+    fn converts_units_since_unix_epoch() {
+        assert_eq!(
+            timestamp(json!({}), json!(0)),
+            Ok(json!("1970-01-01T00:00:00Z"))
+        );
+        assert_eq!(
+            timestamp(json!({"unit": "ms"}), json!(1_500)),
+            Ok(json!("1970-01-01T00:00:01.500Z"))
+        );
+        assert_eq!(
+            timestamp(json!({"unit": "ns"}), json!(-1)),
+            Ok(json!("1969-12-31T23:59:59.999999999Z"))
+        );
+        assert!(timestamp(json!({}), json!(i64::MAX)).is_err());
+        assert!(timestamp(json!({}), json!("soon")).is_err());
+    }
+
+    #[test]
+    fn converts_from_other_epochs_and_timezones() {
+        // Exchange time, seconds since 1980
+        assert_eq!(
+            timestamp(json!({"epoch": "1980-01-01"}), json!(86_400)),
+            Ok(json!("1980-01-02T00:00:00Z"))
+        );
+        assert_eq!(
+            timestamp(
+                json!({"epoch": "1980-01-01T00:00:00+05:30", "timezone": "Asia/Kolkata"}),
+                json!(60)
+            ),
+            Ok(json!("1980-01-01T00:01:00+05:30"))
+        );
+        assert_eq!(
+            timestamp(
+                json!({"timezone": "-02:00", "format": "%Y-%m-%d %H:%M"}),
+                json!(0)
+            ),
+            Ok(json!("1969-12-31 22:00"))
+        );
+        assert!(serde_json::from_value::<TimestampFormat>(json!({"timezone": "Mars"})).is_err());
+    }
+
+    #[test]
+    fn parses_emitted_datetimes() {
+        let datetime = parse_datetime(&json!("1980-01-01T00:01:00+05:30")).unwrap();
+
+        assert_eq!(datetime.timestamp(), 315_513_060);
+        assert_eq!(parse_datetime(&json!(315_513_000)), None);
+    }
+
+    fn value_map(map: Value) -> ValueMap {
+        serde_json::from_value(map).unwrap()
+    }
+
+    #[test]
+    fn maps_codes_to_labels_by_text() {
+        let map = value_map(json!({"labels": {"1": "Buy", "2": "Sell", "B": "Bid"}}));
+
+        assert_eq!(map_value(&json!(1), &map), Ok(json!("Buy")));
+        assert_eq!(map_value(&json!("B \0"), &map), Ok(json!("Bid")));
+        assert_eq!(map_value(&json!([2, 1]), &map), Ok(json!(["Sell", "Buy"])));
+    }
+
+    #[test]
+    fn unknown_codes_are_kept_nulled_or_an_error() {
+        let labels = json!({"1": "Buy"});
+
+        let keep = value_map(json!({"labels": labels}));
+        let null = value_map(json!({"labels": labels, "on_unknown": "null"}));
+        let error = value_map(json!({"labels": labels, "on_unknown": "error"}));
+
+        assert_eq!(map_value(&json!(3), &keep), Ok(json!(3)));
+        assert_eq!(map_value(&json!(3), &null), Ok(Value::Null));
+        assert_eq!(
+            map_value(&json!(3), &error),
+            Err("Unknown code 3".to_string())
+        );
+    }
+}
@@ -0,0 +1,655 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use chrono::{DateTime, SecondsFormat};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde_json::{Map, Number, Value};
+
+use crate::{filter::compare, parse_datetime, ReaderError, RecordIter};
+
+/// Aggregates to compute per group of records, in one pass over them
+/// e.g. max of Price per Token, or sum of Volume per minute
+#[derive(Debug, Default, Clone)]
+pub struct Aggregation {
+    /// Records with same values of these columns are aggregated together
+    /// Missing columns group as null
+    pub group_by: Vec<String>,
+    /// Also groups records by time bucket of a column
+    pub bucket: Option<TimeBucket>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+/// Groups records by start of fixed width time window
+/// Windows are aligned to unix epoch, datetime starts are in UTC
+#[derive(Debug, Clone)]
+pub struct TimeBucket {
+    /// Datetime column, or number like epoch seconds
+    pub column: String,
+    /// Seconds for datetime columns, units of column for numbers
+    /// Starts of numeric windows are integers
+    pub width: u64,
+}
+
+/// One output column of aggregation
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    /// Name of output column, e.g. max(Price)
+    pub name: String,
+    pub function: AggregateFunction,
+    /// Column to aggregate, None counts records
+    pub column: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunction {
+    /// Number of records, or of non null values of column
+    Count,
+    /// Integers and decimal strings are summed exactly, decimals stay strings
+    Sum,
+    Min,
+    Max,
+    /// Mean of decimal strings is a decimal string
+    Mean,
+    /// First non null value
+    First,
+    /// Last non null value
+    Last,
+    /// Number of different non null values
+    DistinctCount,
+}
+
+impl AggregateFunction {
+    fn name(&self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+            AggregateFunction::Mean => "mean",
+            AggregateFunction::First => "first",
+            AggregateFunction::Last => "last",
+            AggregateFunction::DistinctCount => "distinct_count",
+        }
+    }
+}
+
+impl Aggregate {
+    /// Aggregate named after function and column, e.g. sum(Volume)
+    pub fn new(function: AggregateFunction, column: Option<&str>) -> Aggregate {
+        let name = match column {
+            Some(column) => format!("{}({column})", function.name()),
+            None => function.name().to_string(),
+        };
+
+        Aggregate {
+            name,
+            function,
+            column: column.map(String::from),
+        }
+    }
+
+    pub fn count() -> Aggregate {
+        Aggregate::new(AggregateFunction::Count, None)
+    }
+
+    pub fn sum(column: &str) -> Aggregate {
+        Aggregate::new(AggregateFunction::Sum, Some(column))
+    }
+
+    pub fn min(column: &str) -> Aggregate {
+        Aggregate::new(AggregateFunction::Min, Some(column))
+    }
+
+    pub fn max(column: &str) -> Aggregate {
+        Aggregate::new(AggregateFunction::Max, Some(column))
+    }
+
+    pub fn mean(column: &str) -> Aggregate {
+        Aggregate::new(AggregateFunction::Mean, Some(column))
+    }
+
+    pub fn first(column: &str) -> Aggregate {
+        Aggregate::new(AggregateFunction::First, Some(column))
+    }
+
+    pub fn last(column: &str) -> Aggregate {
+        Aggregate::new(AggregateFunction::Last, Some(column))
+    }
+
+    pub fn distinct_count(column: &str) -> Aggregate {
+        Aggregate::new(AggregateFunction::DistinctCount, Some(column))
+    }
+
+    /// Renames output column
+    pub fn named(mut self, name: &str) -> Aggregate {
+        self.name = name.to_string();
+        self
+    }
+}
+
+impl Aggregation {
+    /// Columns records need for aggregation
+    pub(crate) fn columns(&self) -> impl Iterator<Item = &str> {
+        self.group_by
+            .iter()
+            .chain(self.bucket.iter().map(|bucket| &bucket.column))
+            .chain(
+                self.aggregates
+                    .iter()
+                    .flat_map(|aggregate| &aggregate.column),
+            )
+            .map(String::as_str)
+    }
+
+    /// Aggregates records into one row per group, in order groups first appear
+    /// Rows have group columns, then bucket start, then aggregates
+    pub(crate) fn run(&self, records: RecordIter) -> Result<Vec<Map<String, Value>>, ReaderError> {
+        let mut groups: Vec<(Vec<Value>, Vec<State>)> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+
+        for record in records {
+            let record = record?;
+
+            let mut key: Vec<Value> = self
+                .group_by
+                .iter()
+                .map(|column| record.get(column).cloned().unwrap_or(Value::Null))
+                .collect();
+
+            if let Some(bucket) = &self.bucket {
+                key.push(bucket.start(record.get(&bucket.column))?);
+            }
+
+            // Values are not hashable, their json text is
+            let i = *index
+                .entry(Value::from(key.clone()).to_string())
+                .or_insert_with(|| {
+                    let states = self
+                        .aggregates
+                        .iter()
+                        .map(|aggregate| State::new(aggregate.function))
+                        .collect();
+
+                    groups.push((key, states));
+                    groups.len() - 1
+                });
+
+            for (aggregate, state) in self.aggregates.iter().zip(&mut groups[i].1) {
+                let value = match &aggregate.column {
+                    Some(column) => record.get(column).filter(|value| !value.is_null()),
+                    // Every record counts
+                    None => Some(&Value::Null),
+                };
+
+                if let Some(value) = value {
+                    state.add(value);
+                }
+            }
+        }
+
+        // Like sql, aggregates of no records without grouping are still one row
+        if groups.is_empty() && self.group_by.is_empty() && self.bucket.is_none() {
+            let states = self
+                .aggregates
+                .iter()
+                .map(|aggregate| State::new(aggregate.function))
+                .collect();
+
+            groups.push((Vec::new(), states));
+        }
+
+        Ok(groups
+            .into_iter()
+            .map(|(key, states)| {
+                let mut row = Map::new();
+
+                let names = self
+                    .group_by
+                    .iter()
+                    .chain(self.bucket.iter().map(|bucket| &bucket.column));
+
+                for (name, value) in names.zip(key) {
+                    row.insert(name.clone(), value);
+                }
+
+                for (aggregate, state) in self.aggregates.iter().zip(states) {
+                    row.insert(aggregate.name.clone(), state.finish());
+                }
+
+                row
+            })
+            .collect())
+    }
+}
+
+impl TimeBucket {
+    /// Start of bucket which value falls in
+    fn start(&self, value: Option<&Value>) -> Result<Value, ReaderError> {
+        let width = self.width.max(1);
+
+        let Some(value) = value.filter(|value| !value.is_null()) else {
+            return Ok(Value::Null);
+        };
+
+        if let Some(datetime) = parse_datetime(value) {
+            let seconds = datetime.timestamp();
+            let start = seconds - seconds.rem_euclid(width as i64);
+
+            // Same instants with other offsets fall in one bucket
+            return Ok(DateTime::from_timestamp(start, 0)
+                .map(|start| Value::from(start.to_rfc3339_opts(SecondsFormat::AutoSi, true)))
+                .unwrap_or(Value::Null));
+        }
+
+        match as_number(value) {
+            Some(number) if number.is_i64() || number.is_u64() => {
+                let number = number
+                    .as_i64()
+                    .map_or_else(|| number.as_u64().map_or(0, i128::from), i128::from);
+                let start = number - number.rem_euclid(width as i128);
+
+                Ok(i64::try_from(start).map_or(Value::Null, Value::from))
+            }
+            // Fractions fall in bucket of integer start, like integers do
+            Some(number) => {
+                let number = number.as_f64().unwrap_or_default();
+                let start = number - number.rem_euclid(width as f64);
+
+                Ok(match start.is_finite() && start.abs() < i64::MAX as f64 {
+                    true => Value::from(start as i64),
+                    false => Value::Null,
+                })
+            }
+            None => Err(ReaderError::InvalidValue {
+                column: self.column.clone(),
+                message: format!("Can not bucket {value}, expected RFC 3339 datetime or number"),
+                at: Default::default(),
+            }),
+        }
+    }
+}
+
+/// Running value of an aggregate
+enum State {
+    Count(u64),
+    Sum(Option<Total>),
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Mean { sum: Option<Total>, count: u64 },
+    First(Option<Value>),
+    Last(Option<Value>),
+    DistinctCount(HashSet<String>),
+}
+
+/// Exact sum of integers and decimal strings, until a float shows up
+#[derive(Clone, Copy)]
+enum Total {
+    Int(i128),
+    Decimal(Decimal),
+    Float(f64),
+}
+
+impl State {
+    fn new(function: AggregateFunction) -> State {
+        match function {
+            AggregateFunction::Count => State::Count(0),
+            AggregateFunction::Sum => State::Sum(None),
+            AggregateFunction::Min => State::Min(None),
+            AggregateFunction::Max => State::Max(None),
+            AggregateFunction::Mean => State::Mean {
+                sum: None,
+                count: 0,
+            },
+            AggregateFunction::First => State::First(None),
+            AggregateFunction::Last => State::Last(None),
+            AggregateFunction::DistinctCount => State::DistinctCount(HashSet::new()),
+        }
+    }
+
+    /// Adds non null value, values which are not numbers are ignored by sum and mean
+    fn add(&mut self, value: &Value) {
+        match self {
+            State::Count(count) => *count += 1,
+            State::Sum(sum) => {
+                if let Some(term) = Total::of(value) {
+                    *sum = Some(sum.map_or(term, |sum| sum.add(term)));
+                }
+            }
+            State::Min(min) => {
+                if min
+                    .as_ref()
+                    .is_none_or(|min| order(value, min) == Some(Ordering::Less))
+                {
+                    *min = Some(value.clone());
+                }
+            }
+            State::Max(max) => {
+                if max
+                    .as_ref()
+                    .is_none_or(|max| order(value, max) == Some(Ordering::Greater))
+                {
+                    *max = Some(value.clone());
+                }
+            }
+            State::Mean { sum, count } => {
+                if let Some(term) = Total::of(value) {
+                    *sum = Some(sum.map_or(term, |sum| sum.add(term)));
+                    *count += 1;
+                }
+            }
+            State::First(first) => {
+                if first.is_none() {
+                    *first = Some(value.clone());
+                }
+            }
+            State::Last(last) => *last = Some(value.clone()),
+            State::DistinctCount(values) => {
+                values.insert(value.to_string());
+            }
+        }
+    }
+
+    fn finish(self) -> Value {
+        match self {
+            State::Count(count) => Value::from(count),
+            State::Sum(None) | State::Mean { sum: None, .. } => Value::Null,
+            State::Sum(Some(Total::Int(int))) => i64::try_from(int)
+                .map(Value::from)
+                .unwrap_or_else(|_| Value::from(int as f64)),
+            State::Sum(Some(sum)) => sum.to_value(),
+            State::Mean {
+                sum: Some(Total::Decimal(sum)),
+                count,
+            } => sum
+                .checked_div(Decimal::from(count))
+                .map(|mean| Total::Decimal(mean).to_value())
+                .unwrap_or_else(|| Value::from(sum.to_f64().unwrap_or_default() / count as f64)),
+            State::Mean {
+                sum: Some(sum),
+                count,
+            } => Value::from(sum.to_f64() / count as f64),
+            State::Min(value) | State::Max(value) | State::First(value) | State::Last(value) => {
+                value.unwrap_or(Value::Null)
+            }
+            State::DistinctCount(values) => Value::from(values.len()),
+        }
+    }
+}
+
+impl Total {
+    /// Decimal columns are exact strings, floats are only json numbers or exponents
+    fn of(value: &Value) -> Option<Total> {
+        let number = as_number(value)?;
+
+        if let Some(int) = number.as_i64().map(i128::from) {
+            return Some(Total::Int(int));
+        }
+
+        if let Some(int) = number.as_u64().map(i128::from) {
+            return Some(Total::Int(int));
+        }
+
+        match value {
+            Value::String(text) => Decimal::from_str(text.trim())
+                .ok()
+                .map(Total::Decimal)
+                .or_else(|| number.as_f64().map(Total::Float)),
+            _ => number.as_f64().map(Total::Float),
+        }
+    }
+
+    /// Integers widen to decimal, anything widens to float on overflow
+    fn add(self, other: Total) -> Total {
+        match (self, other) {
+            (Total::Int(a), Total::Int(b)) => match a.checked_add(b) {
+                Some(sum) => Total::Int(sum),
+                None => Total::Float(a as f64 + b as f64),
+            },
+            (Total::Float(_), _) | (_, Total::Float(_)) => {
+                Total::Float(self.to_f64() + other.to_f64())
+            }
+            _ => match (self.to_decimal(), other.to_decimal()) {
+                (Some(a), Some(b)) => a
+                    .checked_add(b)
+                    .map(Total::Decimal)
+                    .unwrap_or_else(|| Total::Float(self.to_f64() + other.to_f64())),
+                _ => Total::Float(self.to_f64() + other.to_f64()),
+            },
+        }
+    }
+
+    fn to_decimal(self) -> Option<Decimal> {
+        match self {
+            Total::Int(int) => Decimal::try_from_i128_with_scale(int, 0).ok(),
+            Total::Decimal(decimal) => Some(decimal),
+            Total::Float(_) => None,
+        }
+    }
+
+    fn to_f64(self) -> f64 {
+        match self {
+            Total::Int(int) => int as f64,
+            Total::Decimal(decimal) => decimal.to_f64().unwrap_or_default(),
+            Total::Float(float) => float,
+        }
+    }
+
+    /// Decimals stay exact strings, like decimal columns
+    fn to_value(self) -> Value {
+        match self {
+            Total::Decimal(decimal) => Value::from(decimal.to_string()),
+            _ => Value::from(self.to_f64()),
+        }
+    }
+}
+
+/// Number of value, text formats have numbers as strings
+fn as_number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Number(number) => Some(number.clone()),
+        Value::String(text) => Number::from_str(text.trim()).ok(),
+        _ => None,
+    }
+}
+
+/// Orders numbers and numeric strings by value, others like filter compares them
+pub(crate) fn order(a: &Value, b: &Value) -> Option<Ordering> {
+    match (as_number(a), as_number(b)) {
+        (Some(a), Some(b)) => compare(&Value::Number(a), &Value::Number(b)),
+        _ => compare(a, b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Config, Reader, Type};
+
+    fn aggregate(lines: &str, aggregation: &Aggregation) -> Vec<Value> {
+        let config: Config = serde_json::from_value(json!({"selected_columns": []})).unwrap();
+        let reader = Reader::new_with_config(config, lines.as_bytes().to_vec(), Type::JsonLines);
+
+        reader
+            .aggregate(aggregation)
+            .unwrap()
+            .into_iter()
+            .map(Value::Object)
+            .collect()
+    }
+
+    fn bucket(column: &str, width: u64) -> Aggregation {
+        Aggregation {
+            bucket: Some(TimeBucket {
+                column: column.to_string(),
+                width,
+            }),
+            aggregates: vec![Aggregate::count()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn groups_in_order_of_first_record() {
+        let lines = r#"{"Token": 2, "Price": 10}
+{"Token": 1, "Price": 30}
+{"Price": 5}
+{"Token": 2, "Price": 20}
+"#;
+
+        let aggregation = Aggregation {
+            group_by: vec!["Token".into()],
+            aggregates: vec![
+                Aggregate::count(),
+                Aggregate::sum("Price"),
+                Aggregate::min("Price"),
+                Aggregate::max("Price"),
+                Aggregate::mean("Price").named("avg"),
+                Aggregate::first("Price"),
+                Aggregate::last("Price"),
+                Aggregate::distinct_count("Price"),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            aggregate(lines, &aggregation),
+            vec![
+                json!({"Token": 2, "count": 2, "sum(Price)": 30, "min(Price)": 10, "max(Price)": 20,
+                    "avg": 15.0, "first(Price)": 10, "last(Price)": 20, "distinct_count(Price)": 2}),
+                json!({"Token": 1, "count": 1, "sum(Price)": 30, "min(Price)": 30, "max(Price)": 30,
+                    "avg": 30.0, "first(Price)": 30, "last(Price)": 30, "distinct_count(Price)": 1}),
+                json!({"Token": null, "count": 1, "sum(Price)": 5, "min(Price)": 5, "max(Price)": 5,
+                    "avg": 5.0, "first(Price)": 5, "last(Price)": 5, "distinct_count(Price)": 1}),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_records_are_one_row_without_grouping() {
+        let aggregation = Aggregation {
+            aggregates: vec![
+                Aggregate::count(),
+                Aggregate::sum("Price"),
+                Aggregate::mean("Price"),
+                Aggregate::max("Price"),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            aggregate("", &aggregation),
+            vec![json!({"count": 0, "sum(Price)": null, "mean(Price)": null, "max(Price)": null})]
+        );
+
+        let grouped = Aggregation {
+            group_by: vec!["Token".into()],
+            ..aggregation
+        };
+
+        assert!(aggregate("", &grouped).is_empty());
+    }
+
+    #[test]
+    fn sums_decimals_exactly() {
+        let lines = r#"{"Price": "0.10"}
+{"Price": "0.20"}
+{"Price": 1}
+{"Price": "-0.05"}
+"#;
+
+        let aggregation = Aggregation {
+            aggregates: vec![Aggregate::sum("Price"), Aggregate::mean("Price")],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            aggregate(lines, &aggregation),
+            vec![json!({"sum(Price)": "1.25", "mean(Price)": "0.3125"})]
+        );
+    }
+
+    #[test]
+    fn sums_integers_exactly_and_floats_as_floats() {
+        let lines = format!(
+            "{{\"a\": {max}, \"b\": 0.1}}\n{{\"a\": {max}, \"b\": 0.2}}\n{{\"a\": 2, \"b\": \"1e1\"}}\n",
+            max = u64::MAX
+        );
+
+        let aggregation = Aggregation {
+            aggregates: vec![Aggregate::sum("a"), Aggregate::sum("b")],
+            ..Default::default()
+        };
+
+        let rows = aggregate(&lines, &aggregation);
+
+        assert_eq!(rows[0]["sum(a)"], json!(2.0 * u64::MAX as f64));
+        assert_eq!(rows[0]["sum(b)"], json!(0.1 + 0.2 + 10.0));
+    }
+
+    #[test]
+    fn orders_numeric_strings_by_value() {
+        let lines = r#"{"Qty": "30"}
+{"Qty": "100"}
+{"Qty": "9.5"}
+"#;
+
+        let aggregation = Aggregation {
+            aggregates: vec![Aggregate::min("Qty"), Aggregate::max("Qty")],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            aggregate(lines, &aggregation),
+            vec![json!({"min(Qty)": "9.5", "max(Qty)": "100"})]
+        );
+    }
+
+    #[test]
+    fn buckets_numbers_from_epoch() {
+        let lines = "{\"t\": -1}\n{\"t\": 0}\n{\"t\": 59}\n{\"t\": 60}\n{\"t\": 90.5}\n{}\n";
+
+        assert_eq!(
+            aggregate(lines, &bucket("t", 60)),
+            vec![
+                json!({"t": -60, "count": 1}),
+                json!({"t": 0, "count": 2}),
+                json!({"t": 60, "count": 2}),
+                json!({"t": null, "count": 1}),
+            ]
+        );
+    }
+
+    #[test]
+    fn buckets_same_instants_together_in_utc() {
+        let lines = r#"{"t": "2024-01-01T05:29:59+05:30"}
+{"t": "2024-01-01T05:30:00+05:30"}
+{"t": "2024-01-01T00:00:00Z"}
+{"t": "2024-01-01T00:59:59.999Z"}
+"#;
+
+        assert_eq!(
+            aggregate(lines, &bucket("t", 3600)),
+            vec![
+                json!({"t": "2023-12-31T23:00:00Z", "count": 1}),
+                json!({"t": "2024-01-01T00:00:00Z", "count": 3}),
+            ]
+        );
+    }
+
+    #[test]
+    fn buckets_must_be_times_or_numbers() {
+        let config: Config = serde_json::from_value(json!({"selected_columns": []})).unwrap();
+        let reader =
+            Reader::new_with_config(config, b"{\"t\": \"soon\"}\n".to_vec(), Type::JsonLines);
+
+        assert!(matches!(
+            reader.aggregate(&bucket("t", 60)),
+            Err(ReaderError::InvalidValue { column, .. }) if column == "t"
+        ));
+    }
+}
@@ -0,0 +1,1241 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    adapters::utils::column_utils::validate_columns, NativeSettings, PacketColumns, ReaderError,
+};
+
+/// Options for generating a config from a c header
+#[derive(Debug, Default, Clone)]
+pub struct HeaderOptions {
+    /// Packet identifier like transaction code, with name of struct of packet
+    pub packets: Vec<(u64, String)>,
+    /// Packing used before any #pragma pack, natural alignment if None
+    pub packing: Option<usize>,
+    /// skip_bytes of every generated packet
+    pub skip_bytes: u32,
+    /// Sets native.flatten, for dotted column names
+    pub flatten: bool,
+    /// Config to add generated structs and packets to
+    /// e.g. one with packet_header and packet_info columns
+    pub base: Option<Value>,
+}
+
+/// Generates config json with structs and column details from a c header
+///
+/// Supports struct, typedef, arrays, bitfields, #define constants and #pragma pack
+/// Offsets and sizes follow c layout rules, so they are written to every column
+/// long is 4 bytes, like in exchange specs
+/// Bitfields are packed from the highest bit, in units of their type
+/// A bitfield which would cross a unit starts the next one
+pub fn generate_config(header: &str, options: &HeaderOptions) -> Result<Value, ReaderError> {
+    let (tokens, defines) = tokenize(header)?;
+
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        defines,
+        pack: options.packing,
+        pack_stack: Vec::new(),
+        structs: Vec::new(),
+        tags: HashMap::new(),
+        typedefs: HashMap::new(),
+    };
+
+    parser.parse()?;
+
+    let mut layouts = HashMap::new();
+
+    // Structs of packets and all structs used by them
+    let mut packets = Vec::new();
+
+    for (identifier, name) in &options.packets {
+        let index = parser.struct_index(name).ok_or_else(|| {
+            ReaderError::Config(format!(
+                "Struct {name} of packet {identifier} is not defined"
+            ))
+        })?;
+
+        packets.push((identifier, index));
+    }
+
+    let roots: Vec<usize> = if packets.is_empty() {
+        (0..parser.structs.len())
+            .filter(|&i| parser.structs[i].defined && parser.structs[i].name.is_some())
+            .collect()
+    } else {
+        packets.iter().map(|(_, index)| *index).collect()
+    };
+
+    let mut used = Vec::new();
+
+    for index in roots {
+        parser.collect_used(index, &mut used, &mut HashSet::new())?;
+    }
+
+    let mut structs = Map::new();
+
+    for &index in &used {
+        if let Some(name) = &parser.structs[index].name {
+            let layout = parser.layout(index, &mut layouts, &mut HashSet::new())?;
+
+            structs.insert(name.clone(), Value::Array(layout.columns.clone()));
+        }
+    }
+
+    let mut column_details = Map::new();
+
+    for (identifier, index) in packets {
+        let layout = parser.layout(index, &mut layouts, &mut HashSet::new())?;
+
+        column_details.insert(
+            identifier.to_string(),
+            json!({
+                "skip_bytes": options.skip_bytes,
+                "columns": layout.columns,
+            }),
+        );
+    }
+
+    let config = merge(options, structs, column_details)?;
+
+    // Generated columns must be readable by the native adapters
+    // Packet header and info may be missing without a base config
+    let invalid =
+        |e: serde_json::Error| ReaderError::Config(format!("Generated config is invalid: {e}"));
+
+    let native = &config["native"];
+
+    let settings = NativeSettings {
+        structs: serde_json::from_value(native["structs"].clone()).map_err(invalid)?,
+        value_maps: match native.get("value_maps") {
+            Some(value_maps) => serde_json::from_value(value_maps.clone()).map_err(invalid)?,
+            None => BTreeMap::new(),
+        },
+        ..Default::default()
+    };
+    let column_details: HashMap<u64, PacketColumns> =
+        serde_json::from_value(native["packet_info"]["column_details"].clone()).map_err(invalid)?;
+
+    validate_columns(
+        settings
+            .structs
+            .values()
+            .flatten()
+            .chain(column_details.values().flat_map(|details| &details.columns)),
+        &settings,
+    )?;
+
+    Ok(config)
+}
+
+/// Adds generated structs and packets to base config
+fn merge(
+    options: &HeaderOptions,
+    structs: Map<String, Value>,
+    column_details: Map<String, Value>,
+) -> Result<Value, ReaderError> {
+    let mut config = options.base.clone().unwrap_or_else(|| json!({}));
+
+    let invalid = |key: &str| ReaderError::Config(format!("{key} of base config is not an object"));
+
+    let config_map = config.as_object_mut().ok_or_else(|| invalid("root"))?;
+
+    config_map
+        .entry("selected_columns")
+        .or_insert_with(|| json!([]));
+
+    let native = config_map
+        .entry("native")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| invalid("native"))?;
+
+    if options.flatten {
+        native.insert("flatten".to_string(), Value::Bool(true));
+    }
+
+    native
+        .entry("structs")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| invalid("native.structs"))?
+        .extend(structs);
+
+    native
+        .entry("packet_info")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| invalid("native.packet_info"))?
+        .entry("column_details")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| invalid("native.packet_info.column_details"))?
+        .extend(column_details);
+
+    Ok(config)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Number(u64),
+    Punct(char),
+    Pack(PackOp),
+}
+
+/// Change of packing from #pragma pack
+#[derive(Debug, Clone, PartialEq)]
+enum PackOp {
+    Set(Option<usize>),
+    Push(Option<usize>),
+    Pop,
+}
+
+#[derive(Debug)]
+struct Token {
+    tok: Tok,
+    line: usize,
+}
+
+/// Splits header into tokens
+/// Returns values of #define constants, which can be used as array lengths
+fn tokenize(header: &str) -> Result<(Vec<Token>, HashMap<String, u64>), ReaderError> {
+    let mut tokens = Vec::new();
+    let mut defines = HashMap::new();
+
+    for (i, line) in strip_comments(header).lines().enumerate() {
+        let line_no = i + 1;
+        let trimmed = line.trim();
+
+        // Preprocessor directives
+        if let Some(directive) = trimmed.strip_prefix('#') {
+            let mut words = directive.split_whitespace();
+
+            match words.next() {
+                Some("define") => {
+                    if let (Some(name), Some(value)) = (words.next(), words.next()) {
+                        if let Some(value) = parse_number(value.trim_matches(['(', ')'])) {
+                            defines.insert(name.to_string(), value);
+                        }
+                    }
+                }
+                Some("pragma") if words.next().is_some_and(|w| w.starts_with("pack")) => {
+                    tokens.push(Token {
+                        tok: Tok::Pack(parse_pack(directive, line_no)?),
+                        line: line_no,
+                    });
+                }
+                // Includes and conditionals are ignored
+                _ => {}
+            }
+
+            continue;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut j = 0;
+
+        while j < chars.len() {
+            let c = chars[j];
+
+            if c.is_whitespace() {
+                j += 1;
+            } else if c.is_ascii_alphanumeric() || c == '_' {
+                let start = j;
+
+                while j < chars.len() && (chars[j].is_ascii_alphanumeric() || chars[j] == '_') {
+                    j += 1;
+                }
+
+                let word: String = chars[start..j].iter().collect();
+
+                let tok = if c.is_ascii_digit() {
+                    Tok::Number(parse_number(&word).ok_or_else(|| {
+                        ReaderError::Config(format!("line {line_no}: invalid number {word}"))
+                    })?)
+                } else {
+                    Tok::Ident(word)
+                };
+
+                tokens.push(Token { tok, line: line_no });
+            } else {
+                tokens.push(Token {
+                    tok: Tok::Punct(c),
+                    line: line_no,
+                });
+                j += 1;
+            }
+        }
+    }
+
+    Ok((tokens, defines))
+}
+
+/// Replaces comments with spaces, keeping line breaks
+fn strip_comments(header: &str) -> String {
+    let mut out = String::with_capacity(header.len());
+    let mut chars = header.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+
+                let mut last = ' ';
+
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        out.push('\n');
+                    }
+
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+
+                    last = c;
+                }
+
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Parses decimal, hex and octal literals with integer suffixes
+fn parse_number(word: &str) -> Option<u64> {
+    let word = word.trim_end_matches(['u', 'U', 'l', 'L']);
+
+    if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if word.len() > 1 && word.starts_with('0') {
+        u64::from_str_radix(&word[1..], 8).ok()
+    } else {
+        word.parse().ok()
+    }
+}
+
+/// Parses arguments of #pragma pack(...)
+fn parse_pack(directive: &str, line: usize) -> Result<PackOp, ReaderError> {
+    let invalid = || ReaderError::Config(format!("line {line}: invalid #pragma pack"));
+
+    let args = directive
+        .split_once('(')
+        .and_then(|(_, rest)| rest.split_once(')'))
+        .map(|(args, _)| args)
+        .ok_or_else(invalid)?;
+
+    let args: Vec<&str> = args
+        .split(',')
+        .map(str::trim)
+        .filter(|arg| !arg.is_empty())
+        .collect();
+
+    let size = |arg: Option<&&str>| -> Result<Option<usize>, ReaderError> {
+        arg.map(|arg| parse_number(arg).map(|n| n as usize).ok_or_else(invalid))
+            .transpose()
+    };
+
+    match args.first() {
+        None => Ok(PackOp::Set(None)),
+        Some(&"push") => Ok(PackOp::Push(size(args.get(1))?)),
+        Some(&"pop") => Ok(PackOp::Pop),
+        first => Ok(PackOp::Set(size(first)?)),
+    }
+}
+
+/// Primitive c type with it's dtype in config
+#[derive(Debug, Clone, Copy)]
+struct Prim {
+    dtype: &'static str,
+    size: usize,
+}
+
+#[derive(Debug, Clone)]
+enum CType {
+    Prim(Prim),
+    /// Index of struct definition
+    Struct(usize),
+}
+
+/// Type defined with typedef, with it's array lengths
+#[derive(Debug, Clone)]
+struct Alias {
+    ty: CType,
+    dims: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    ty: CType,
+    dims: Vec<usize>,
+    bits: Option<usize>,
+    line: usize,
+}
+
+#[derive(Debug)]
+struct StructDef {
+    /// Typedef name or tag, None for anonymous structs
+    name: Option<String>,
+    fields: Vec<Field>,
+    /// Packing in effect where struct was defined
+    pack: Option<usize>,
+    /// False for forward declarations
+    defined: bool,
+}
+
+/// Size, alignment and columns of a struct
+#[derive(Debug)]
+struct StructLayout {
+    size: usize,
+    align: usize,
+    columns: Vec<Value>,
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    defines: HashMap<String, u64>,
+    pack: Option<usize>,
+    pack_stack: Vec<Option<usize>>,
+    structs: Vec<StructDef>,
+    /// Struct tags to index of struct
+    tags: HashMap<String, usize>,
+    typedefs: HashMap<String, Alias>,
+}
+
+impl Parser<'_> {
+    fn parse(&mut self) -> Result<(), ReaderError> {
+        while let Some(tok) = self.peek().cloned() {
+            match tok {
+                Tok::Ident(word) if word == "typedef" => {
+                    self.pos += 1;
+                    self.parse_typedef()?;
+                }
+                Tok::Ident(word) if matches!(word.as_str(), "struct" | "enum") => {
+                    self.parse_type()?;
+
+                    // Declarations of variables are not needed
+                    if !self.eat(&Tok::Punct(';')) {
+                        self.skip_statement();
+                    }
+                }
+                // extern "C" { ... }
+                Tok::Ident(word)
+                    if word == "extern" && self.peek_at(1) == Some(&Tok::Punct('"')) =>
+                {
+                    while self.next().is_some_and(|tok| tok != &Tok::Punct('{')) {}
+                }
+                Tok::Punct('}') => self.pos += 1,
+                // Functions, variables and anything else
+                _ => self.skip_statement(),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Skips a declaration or a function body
+    fn skip_statement(&mut self) {
+        let mut depth = 0i32;
+
+        while let Some(tok) = self.next() {
+            match tok {
+                Tok::Punct(';') if depth == 0 => return,
+                Tok::Punct('{') => depth += 1,
+                Tok::Punct('}') => {
+                    depth -= 1;
+
+                    if depth <= 0 {
+                        self.eat(&Tok::Punct(';'));
+
+                        return;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_typedef(&mut self) -> Result<(), ReaderError> {
+        let first_new = self.structs.len();
+        let (ty, base_dims) = self.parse_type()?;
+
+        loop {
+            // Pointer types can not be read from files
+            if self.eat(&Tok::Punct('*')) {
+                while !matches!(self.peek(), Some(Tok::Punct(',' | ';')) | None) {
+                    self.pos += 1;
+                }
+            } else {
+                let name = self.expect_ident()?;
+                let mut dims = self.parse_dims()?;
+                dims.extend(&base_dims);
+
+                // Anonymous or tagged struct defined here is named by typedef
+                if let CType::Struct(index) = ty {
+                    if index >= first_new && dims.is_empty() {
+                        self.structs[index].name = Some(name.clone());
+                    }
+                }
+
+                self.typedefs.insert(
+                    name,
+                    Alias {
+                        ty: ty.clone(),
+                        dims,
+                    },
+                );
+            }
+
+            if !self.eat(&Tok::Punct(',')) {
+                break;
+            }
+        }
+
+        self.expect(&Tok::Punct(';'))
+    }
+
+    /// Parses a type specifier, returns type with array lengths of typedefs
+    fn parse_type(&mut self) -> Result<(CType, Vec<usize>), ReaderError> {
+        let line = self.line();
+        let mut words = Vec::new();
+
+        while let Some(Tok::Ident(word)) = self.peek() {
+            let word = word.clone();
+
+            match word.as_str() {
+                "const" | "volatile" | "static" | "register" | "extern" => {}
+                "struct" => {
+                    self.pos += 1;
+
+                    return Ok((CType::Struct(self.parse_struct()?), Vec::new()));
+                }
+                "union" => {
+                    return Err(ReaderError::Config(format!(
+                        "line {line}: unions are not supported"
+                    )))
+                }
+                "enum" => {
+                    self.pos += 1;
+
+                    self.eat_ident();
+
+                    if self.eat(&Tok::Punct('{')) {
+                        while self.next().is_some_and(|tok| tok != &Tok::Punct('}')) {}
+                    }
+
+                    // Enums are stored as int
+                    return Ok((prim("i32", 4), Vec::new()));
+                }
+                "signed" | "unsigned" | "char" | "short" | "int" | "long" | "float" | "double"
+                | "bool" | "_Bool" => words.push(word),
+                _ if words.is_empty() => {
+                    self.pos += 1;
+
+                    return match (stdint(&word), self.typedefs.get(&word)) {
+                        (Some(ty), _) => Ok((ty, Vec::new())),
+                        (None, Some(alias)) => Ok((alias.ty.clone(), alias.dims.clone())),
+                        (None, None) => Err(ReaderError::Config(format!(
+                            "line {line}: unknown type {word}"
+                        ))),
+                    };
+                }
+                _ => break,
+            }
+
+            self.pos += 1;
+        }
+
+        Ok((primitive(&words, line)?, Vec::new()))
+    }
+
+    /// Parses struct after struct keyword, returns index of struct
+    fn parse_struct(&mut self) -> Result<usize, ReaderError> {
+        let tag = self.eat_ident();
+
+        let index = match &tag {
+            Some(tag) => match self.tags.get(tag) {
+                Some(&index) => index,
+                None => {
+                    self.structs.push(StructDef {
+                        name: Some(tag.clone()),
+                        fields: Vec::new(),
+                        pack: self.pack,
+                        defined: false,
+                    });
+                    self.tags.insert(tag.clone(), self.structs.len() - 1);
+
+                    self.structs.len() - 1
+                }
+            },
+            None => {
+                self.structs.push(StructDef {
+                    name: None,
+                    fields: Vec::new(),
+                    pack: self.pack,
+                    defined: false,
+                });
+
+                self.structs.len() - 1
+            }
+        };
+
+        if !self.eat(&Tok::Punct('{')) {
+            return Ok(index);
+        }
+
+        let mut fields = Vec::new();
+
+        while !self.eat(&Tok::Punct('}')) {
+            let (ty, base_dims) = self.parse_type()?;
+
+            loop {
+                let line = self.line();
+
+                if self.eat(&Tok::Punct('*')) {
+                    return Err(ReaderError::Config(format!(
+                        "line {line}: pointers can not be read from files"
+                    )));
+                }
+
+                // Unnamed bitfields are padding
+                let name = match self.peek() {
+                    Some(Tok::Punct(':')) => String::new(),
+                    _ => self.expect_ident()?,
+                };
+
+                let mut dims = self.parse_dims()?;
+                dims.extend(&base_dims);
+
+                let bits = if self.eat(&Tok::Punct(':')) {
+                    Some(self.parse_number()? as usize)
+                } else {
+                    None
+                };
+
+                fields.push(Field {
+                    name,
+                    ty: ty.clone(),
+                    dims,
+                    bits,
+                    line,
+                });
+
+                if !self.eat(&Tok::Punct(',')) {
+                    break;
+                }
+            }
+
+            self.expect(&Tok::Punct(';'))?;
+        }
+
+        let def = &mut self.structs[index];
+        def.fields = fields;
+        def.pack = self.pack;
+        def.defined = true;
+
+        Ok(index)
+    }
+
+    fn parse_dims(&mut self) -> Result<Vec<usize>, ReaderError> {
+        let mut dims = Vec::new();
+
+        while self.eat(&Tok::Punct('[')) {
+            dims.push(self.parse_number()? as usize);
+            self.expect(&Tok::Punct(']'))?;
+        }
+
+        Ok(dims)
+    }
+
+    /// Number literal or #define constant
+    fn parse_number(&mut self) -> Result<u64, ReaderError> {
+        let line = self.line();
+
+        match self.next().cloned() {
+            Some(Tok::Number(n)) => Ok(n),
+            Some(Tok::Ident(name)) => self.defines.get(&name).copied().ok_or_else(|| {
+                ReaderError::Config(format!("line {line}: unknown constant {name}"))
+            }),
+            tok => Err(ReaderError::Config(format!(
+                "line {line}: expected number, found {tok:?}"
+            ))),
+        }
+    }
+
+    /// Index of struct by typedef name or tag
+    fn struct_index(&self, name: &str) -> Option<usize> {
+        match self.typedefs.get(name) {
+            Some(Alias {
+                ty: CType::Struct(index),
+                dims,
+            }) if dims.is_empty() => Some(*index),
+            _ => self.tags.get(name).copied(),
+        }
+    }
+
+    /// Adds struct and structs of it's fields to used, in order of definition
+    fn collect_used(
+        &self,
+        index: usize,
+        used: &mut Vec<usize>,
+        visiting: &mut HashSet<usize>,
+    ) -> Result<(), ReaderError> {
+        if used.contains(&index) {
+            return Ok(());
+        }
+
+        if !visiting.insert(index) {
+            return Err(self.recursive(index));
+        }
+
+        for field in &self.structs[index].fields {
+            if let CType::Struct(field_index) = field.ty {
+                self.collect_used(field_index, used, visiting)?;
+            }
+        }
+
+        used.push(index);
+
+        Ok(())
+    }
+
+    /// Lays out fields of struct like a c compiler
+    fn layout<'b>(
+        &self,
+        index: usize,
+        layouts: &'b mut HashMap<usize, StructLayout>,
+        visiting: &mut HashSet<usize>,
+    ) -> Result<&'b StructLayout, ReaderError> {
+        if !layouts.contains_key(&index) {
+            let layout = self.compute_layout(index, layouts, visiting)?;
+            layouts.insert(index, layout);
+        }
+
+        Ok(&layouts[&index])
+    }
+
+    fn compute_layout(
+        &self,
+        index: usize,
+        layouts: &mut HashMap<usize, StructLayout>,
+        visiting: &mut HashSet<usize>,
+    ) -> Result<StructLayout, ReaderError> {
+        let def = &self.structs[index];
+
+        if !def.defined {
+            return Err(ReaderError::Config(format!(
+                "Struct {} is used but not defined",
+                def.name.as_deref().unwrap_or("<anonymous>")
+            )));
+        }
+
+        if !visiting.insert(index) {
+            return Err(self.recursive(index));
+        }
+
+        // Alignment is capped by packing
+        let cap = |align: usize| def.pack.filter(|&p| p > 0).map_or(align, |p| align.min(p));
+
+        let mut columns = Vec::new();
+        let mut offset: usize = 0;
+        let mut bit_offset = 0;
+        let mut max_align = 1;
+
+        for field in &def.fields {
+            let invalid = |message: &str| {
+                ReaderError::Config(format!("line {}: {} {message}", field.line, field.name))
+            };
+
+            if let Some(bits) = field.bits {
+                let CType::Prim(ty) = field.ty else {
+                    return Err(invalid("bitfield must be an integer"));
+                };
+
+                if !field.dims.is_empty() {
+                    return Err(invalid("bitfield can not be an array"));
+                }
+
+                max_align = max_align.max(cap(ty.size));
+
+                let unit = ty.size * 8;
+
+                if bits > unit {
+                    return Err(invalid(&format!(
+                        "bitfield of {bits} bits is wider than it's type of {unit} bits"
+                    )));
+                }
+
+                // Zero width bitfield moves to next unit of it's type,
+                // bits which would cross a unit are moved there too
+                let position = offset * 8 + bit_offset;
+                let crosses = bits == 0 || position / unit != (position + bits - 1) / unit;
+
+                if crosses && !position.is_multiple_of(unit) {
+                    let next = position.next_multiple_of(unit);
+
+                    columns.push(json!({
+                        "name": "",
+                        "dtype": "bit",
+                        "offset": offset,
+                        "length": next - position,
+                        "ignore": true,
+                    }));
+
+                    offset = next / 8;
+                    bit_offset = 0;
+                }
+
+                if bits == 0 {
+                    continue;
+                }
+
+                let mut column = json!({
+                    "name": field.name,
+                    "dtype": "bit",
+                    "offset": offset,
+                    "length": bits,
+                });
+
+                if field.name.is_empty() {
+                    column["ignore"] = Value::Bool(true);
+                }
+
+                columns.push(column);
+
+                bit_offset += bits;
+                offset += bit_offset / 8;
+                bit_offset %= 8;
+
+                continue;
+            }
+
+            if bit_offset != 0 {
+                offset += 1;
+                bit_offset = 0;
+            }
+
+            let count: usize = field.dims.iter().product();
+            let array = !field.dims.is_empty();
+
+            // dtype, length of one element, count, size and alignment
+            let (dtype, length, count, size, align) = match field.ty {
+                // Char arrays are strings, outer dimensions are arrays of strings
+                CType::Prim(ty) if ty.dtype == "char" && array => {
+                    let length = field.dims.last().copied().unwrap_or(1);
+
+                    let count = (field.dims.len() > 1).then(|| count / length.max(1));
+
+                    ("char", length, count, count.unwrap_or(1) * length, 1)
+                }
+                CType::Prim(ty) => (
+                    ty.dtype,
+                    ty.size,
+                    array.then_some(count),
+                    ty.size * count,
+                    ty.size,
+                ),
+                CType::Struct(field_index) => {
+                    let layout = self.layout(field_index, layouts, visiting)?;
+
+                    (
+                        "struct",
+                        layout.size,
+                        array.then_some(count),
+                        layout.size * count,
+                        layout.align,
+                    )
+                }
+            };
+
+            let align = cap(align);
+
+            offset = offset.next_multiple_of(align);
+            max_align = max_align.max(align);
+
+            let mut column = json!({
+                "name": field.name,
+                "dtype": dtype,
+                "offset": offset,
+                "length": length,
+            });
+
+            if let Some(count) = count {
+                column["count"] = json!(count);
+            }
+
+            // Anonymous structs are written inline
+            if let CType::Struct(field_index) = field.ty {
+                match &self.structs[field_index].name {
+                    Some(name) => column["struct"] = json!(name),
+                    None => column["fields"] = json!(layouts[&field_index].columns),
+                }
+            }
+
+            columns.push(column);
+
+            offset += size;
+        }
+
+        if bit_offset != 0 {
+            offset += 1;
+        }
+
+        visiting.remove(&index);
+
+        Ok(StructLayout {
+            size: offset.next_multiple_of(max_align),
+            align: max_align,
+            columns,
+        })
+    }
+
+    fn recursive(&self, index: usize) -> ReaderError {
+        ReaderError::Config(format!(
+            "Struct {} contains itself",
+            self.structs[index].name.as_deref().unwrap_or("<anonymous>")
+        ))
+    }
+
+    /// Next token, applying #pragma pack on the way
+    fn peek(&mut self) -> Option<&Tok> {
+        while let Some(Token {
+            tok: Tok::Pack(op), ..
+        }) = self.tokens.get(self.pos)
+        {
+            match op {
+                PackOp::Set(pack) => self.pack = *pack,
+                PackOp::Push(pack) => {
+                    self.pack_stack.push(self.pack);
+
+                    if pack.is_some() {
+                        self.pack = *pack;
+                    }
+                }
+                PackOp::Pop => self.pack = self.pack_stack.pop().flatten(),
+            }
+
+            self.pos += 1;
+        }
+
+        self.tokens.get(self.pos).map(|token| &token.tok)
+    }
+
+    fn peek_at(&self, n: usize) -> Option<&Tok> {
+        self.tokens.get(self.pos + n).map(|token| &token.tok)
+    }
+
+    fn next(&mut self) -> Option<&Tok> {
+        self.peek()?;
+        self.pos += 1;
+
+        self.tokens.get(self.pos - 1).map(|token| &token.tok)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(0, |token| token.line)
+    }
+
+    fn eat(&mut self, tok: &Tok) -> bool {
+        if self.peek() == Some(tok) {
+            self.pos += 1;
+
+            return true;
+        }
+
+        false
+    }
+
+    fn eat_ident(&mut self) -> Option<String> {
+        match self.peek() {
+            Some(Tok::Ident(word)) => {
+                let word = word.clone();
+                self.pos += 1;
+
+                Some(word)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, tok: &Tok) -> Result<(), ReaderError> {
+        let line = self.line();
+
+        match self.next() {
+            Some(found) if found == tok => Ok(()),
+            found => Err(ReaderError::Config(format!(
+                "line {line}: expected {tok:?}, found {found:?}"
+            ))),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ReaderError> {
+        let line = self.line();
+
+        self.eat_ident().ok_or_else(|| {
+            ReaderError::Config(format!(
+                "line {line}: expected name, found {:?}",
+                self.peek_at(0)
+            ))
+        })
+    }
+}
+
+fn prim(dtype: &'static str, size: usize) -> CType {
+    CType::Prim(Prim { dtype, size })
+}
+
+/// Maps fixed width integer types of stdint.h
+fn stdint(word: &str) -> Option<CType> {
+    Some(match word {
+        "int8_t" => prim("i8", 1),
+        "uint8_t" => prim("u8", 1),
+        "int16_t" => prim("short", 2),
+        "uint16_t" => prim("u16", 2),
+        "int32_t" => prim("i32", 4),
+        "uint32_t" => prim("u32", 4),
+        "int64_t" => prim("i64", 8),
+        "uint64_t" => prim("u64", 8),
+        _ => return None,
+    })
+}
+
+/// Maps keywords of a primitive type like unsigned long long to it's dtype
+fn primitive(words: &[String], line: usize) -> Result<CType, ReaderError> {
+    let has = |keyword: &str| words.iter().any(|word| word == keyword);
+    let longs = words.iter().filter(|word| *word == "long").count();
+    let unsigned = has("unsigned");
+
+    Ok(if has("bool") || has("_Bool") {
+        prim("bool", 1)
+    } else if has("float") {
+        prim("f32", 4)
+    } else if has("double") && longs == 0 {
+        prim("f64", 8)
+    } else if has("char") {
+        match (unsigned, has("signed")) {
+            (true, _) => prim("u8", 1),
+            (_, true) => prim("i8", 1),
+            _ => prim("char", 1),
+        }
+    } else if has("short") {
+        if unsigned {
+            prim("u16", 2)
+        } else {
+            prim("short", 2)
+        }
+    } else if longs >= 2 {
+        if unsigned {
+            prim("u64", 8)
+        } else {
+            prim("i64", 8)
+        }
+    } else if !words.is_empty() && !has("double") {
+        if unsigned {
+            prim("u32", 4)
+        } else {
+            prim("i32", 4)
+        }
+    } else {
+        return Err(ReaderError::Config(format!(
+            "line {line}: unsupported type {}",
+            words.join(" ")
+        )));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Config, Reader, Type};
+
+    fn generate(header: &str) -> Value {
+        generate_config(header, &HeaderOptions::default()).unwrap()
+    }
+
+    /// Name, offset and length of generated columns
+    fn layout(config: &Value, name: &str) -> Vec<(String, u64, u64)> {
+        config["native"]["structs"][name]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|column| {
+                (
+                    column["name"].as_str().unwrap().to_string(),
+                    column["offset"].as_u64().unwrap(),
+                    column["length"].as_u64().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn columns(names: &[(&str, u64, u64)]) -> Vec<(String, u64, u64)> {
+        names
+            .iter()
+            .map(|&(name, offset, length)| (name.to_string(), offset, length))
+            .collect()
+    }
+
+    #[test]
+    fn aligns_fields_naturally() {
+        let config = generate("typedef struct { char a; int b; short c; double d; } S;");
+
+        assert_eq!(
+            layout(&config, "S"),
+            columns(&[("a", 0, 1), ("b", 4, 4), ("c", 8, 2), ("d", 16, 8)])
+        );
+    }
+
+    #[test]
+    fn packs_fields() {
+        let config = generate(
+            "#pragma pack(push, 1)
+            struct Packed { char a; int b; };
+            #pragma pack(pop)
+            struct Natural { char a; int b; };",
+        );
+
+        assert_eq!(
+            layout(&config, "Packed"),
+            columns(&[("a", 0, 1), ("b", 1, 4)])
+        );
+        assert_eq!(
+            layout(&config, "Natural"),
+            columns(&[("a", 0, 1), ("b", 4, 4)])
+        );
+    }
+
+    #[test]
+    fn nests_structs_and_arrays() {
+        let config = generate(
+            "#define LEVELS 5
+            typedef struct { int price; short qty; } Level;
+            typedef struct {
+                char symbol[10];
+                Level bids[LEVELS];
+                char names[2][4];
+                struct { char flag; } inner;
+            } Book;",
+        );
+
+        let book = &config["native"]["structs"]["Book"];
+
+        assert_eq!(book[0]["dtype"], "char");
+        assert_eq!(book[0]["length"], 10);
+        assert_eq!(book[1]["struct"], "Level");
+        assert_eq!(book[1]["count"], 5);
+        assert_eq!(book[1]["offset"], 12);
+        assert_eq!(book[1]["length"], 8);
+        assert_eq!(book[2]["count"], 2);
+        assert_eq!(book[2]["length"], 4);
+        assert_eq!(book[2]["offset"], 52);
+        assert_eq!(book[3]["fields"][0]["name"], "flag");
+        assert_eq!(
+            layout(&config, "Level"),
+            columns(&[("price", 0, 4), ("qty", 4, 2)])
+        );
+    }
+
+    #[test]
+    fn lays_out_bitfields_in_units_of_their_type() {
+        let config = generate(
+            "typedef struct {
+                unsigned int a : 3;
+                unsigned int b : 20;
+                unsigned int c : 12;
+                unsigned int : 0;
+                unsigned char d : 4;
+                unsigned int e;
+            } Bits;",
+        );
+
+        assert_eq!(
+            layout(&config, "Bits"),
+            columns(&[
+                ("a", 0, 3),
+                ("b", 0, 20),
+                // c would cross into second int
+                ("", 2, 9),
+                ("c", 4, 12),
+                // Zero width field moves to third int
+                ("", 5, 20),
+                ("d", 8, 4),
+                ("e", 12, 4),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_bitfields_wider_than_type() {
+        let error = generate_config(
+            "struct S { unsigned char a : 9; };",
+            &HeaderOptions::default(),
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("wider than it's type"));
+    }
+
+    #[test]
+    fn reads_wide_bitfields() {
+        let config =
+            generate("struct S { unsigned int a : 4; unsigned int b : 20; unsigned short c; };");
+
+        let config: Config = serde_json::from_value(json!({
+            "selected_columns": [],
+            "native_columns": config["native"]["structs"]["S"],
+        }))
+        .unwrap();
+
+        let buf = vec![0xa1, 0x23, 0x45, 0x00, 0x00, 0x07, 0x00, 0x00];
+        let records = Reader::new_with_config(config, buf, Type::Native)
+            .read(None, None)
+            .unwrap();
+
+        // Fields of up to 8 bits stay at top of a byte
+        assert_eq!(records[0]["a"], json!(0xa0));
+        assert_eq!(records[0]["b"], json!(0x12345));
+        assert_eq!(records[0]["c"], json!(7));
+    }
+
+    #[test]
+    fn generates_packets_into_base_config() {
+        let config = generate_config(
+            "struct Trade { int price; }; struct Order { short qty; };",
+            &HeaderOptions {
+                packets: vec![(20, "Trade".to_string())],
+                skip_bytes: 2,
+                base: Some(json!({"native": {"packing": 1}})),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let native = &config["native"];
+
+        assert_eq!(native["packing"], 1);
+        assert_eq!(
+            native["packet_info"]["column_details"]["20"]["skip_bytes"],
+            2
+        );
+        assert!(native["structs"].get("Trade").is_some());
+        // Only structs of packets are generated
+        assert!(native["structs"].get("Order").is_none());
+    }
+}
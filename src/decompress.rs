@@ -0,0 +1,221 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use flate2::{Decompress, FlushDecompress, Status};
+use serde::Deserialize;
+
+use crate::ReaderError;
+
+/// Decompresses payload of a multi native packet
+/// Implement this to read packets of a codec which is not built in
+pub trait Decompressor: Send + Sync {
+    /// Decompresses input into output and returns number of bytes written
+    /// Output is sized by max_decompressed_size, larger payloads should fail
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, String>;
+}
+
+/// Codec of compressed packets
+/// Any other name refers to a decompressor registered on reader
+#[derive(Debug, Default, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionType {
+    #[default]
+    Lzo,
+    /// Deflate with zlib header
+    Zlib,
+    /// Raw deflate without header
+    Deflate,
+    /// Lz4 block format, without frame
+    Lz4,
+    Zstd,
+    /// Snappy raw format, without frame
+    Snappy,
+    #[serde(untagged)]
+    Custom(String),
+}
+
+struct Lzo;
+
+impl Decompressor for Lzo {
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, String> {
+        mylzo::decompress(input, output)
+    }
+}
+
+struct Flate {
+    zlib_header: bool,
+}
+
+impl Decompressor for Flate {
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, String> {
+        let mut decompress = Decompress::new(self.zlib_header);
+
+        match decompress.decompress(input, output, FlushDecompress::Finish) {
+            Ok(Status::StreamEnd) => Ok(decompress.total_out() as usize),
+            Ok(_) => Err("incomplete stream or output buffer too small".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+struct Lz4;
+
+impl Decompressor for Lz4 {
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, String> {
+        lz4_flex::block::decompress_into(input, output).map_err(|e| e.to_string())
+    }
+}
+
+struct Zstd;
+
+impl Decompressor for Zstd {
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, String> {
+        zstd::bulk::decompress_to_buffer(input, output).map_err(|e| e.to_string())
+    }
+}
+
+struct Snappy;
+
+impl Decompressor for Snappy {
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, String> {
+        snap::raw::Decoder::new()
+            .decompress(input, output)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Decompressors registered by library users, by name
+#[derive(Default, Clone)]
+pub(crate) struct Decompressors(HashMap<String, Arc<dyn Decompressor>>);
+
+impl fmt::Debug for Decompressors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl Decompressors {
+    pub(crate) fn register(&mut self, name: String, decompressor: Arc<dyn Decompressor>) {
+        self.0.insert(name, decompressor);
+    }
+
+    /// Built in codec or registered one for custom names
+    pub(crate) fn get(
+        &self,
+        compression_type: &CompressionType,
+    ) -> Result<Arc<dyn Decompressor>, ReaderError> {
+        Ok(match compression_type {
+            CompressionType::Lzo => Arc::new(Lzo),
+            CompressionType::Zlib => Arc::new(Flate { zlib_header: true }),
+            CompressionType::Deflate => Arc::new(Flate { zlib_header: false }),
+            CompressionType::Lz4 => Arc::new(Lz4),
+            CompressionType::Zstd => Arc::new(Zstd),
+            CompressionType::Snappy => Arc::new(Snappy),
+            CompressionType::Custom(name) => self.0.get(name).cloned().ok_or_else(|| {
+                ReaderError::Config(format!(
+                    "No decompressor registered for compression type {name}"
+                ))
+            })?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{
+        write::{DeflateEncoder, ZlibEncoder},
+        Compression,
+    };
+
+    use super::*;
+
+    const TEXT: &[u8] = b"price price price price price price price price";
+
+    fn decompress(
+        compression_type: CompressionType,
+        input: &[u8],
+        size: usize,
+    ) -> Result<Vec<u8>, String> {
+        let decompressor = Decompressors::default().get(&compression_type).unwrap();
+        let mut output = vec![0; size];
+
+        let len = decompressor.decompress(input, &mut output)?;
+        output.truncate(len);
+
+        Ok(output)
+    }
+
+    #[test]
+    fn decompresses_built_in_codecs() {
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(TEXT).unwrap();
+
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(TEXT).unwrap();
+
+        let packets = [
+            (CompressionType::Zlib, zlib.finish().unwrap()),
+            (CompressionType::Deflate, deflate.finish().unwrap()),
+            (CompressionType::Lz4, lz4_flex::block::compress(TEXT)),
+            (
+                CompressionType::Zstd,
+                zstd::bulk::compress(TEXT, 0).unwrap(),
+            ),
+            (
+                CompressionType::Snappy,
+                snap::raw::Encoder::new().compress_vec(TEXT).unwrap(),
+            ),
+        ];
+
+        for (compression_type, packet) in packets {
+            assert_eq!(
+                decompress(compression_type.clone(), &packet, 1024).as_deref(),
+                Ok(TEXT),
+                "{compression_type:?}"
+            );
+            assert!(
+                decompress(compression_type.clone(), &packet, 8).is_err(),
+                "{compression_type:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn custom_names_refer_to_registered_decompressors() {
+        struct Reverse;
+
+        impl Decompressor for Reverse {
+            fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, String> {
+                output[..input.len()].copy_from_slice(input);
+                output[..input.len()].reverse();
+
+                Ok(input.len())
+            }
+        }
+
+        let compression_type: CompressionType = serde_json::from_str("\"reverse\"").unwrap();
+        assert_eq!(
+            compression_type,
+            CompressionType::Custom("reverse".to_string())
+        );
+
+        let mut decompressors = Decompressors::default();
+
+        assert!(matches!(
+            decompressors.get(&compression_type),
+            Err(ReaderError::Config(_))
+        ));
+
+        decompressors.register("reverse".to_string(), Arc::new(Reverse));
+
+        let mut output = [0; 3];
+        let len = decompressors
+            .get(&compression_type)
+            .unwrap()
+            .decompress(b"abc", &mut output)
+            .unwrap();
+
+        assert_eq!(&output[..len], b"cba");
+    }
+}
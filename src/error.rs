@@ -0,0 +1,263 @@
+use std::{error::Error, fmt, io};
+
+use serde_json::Value;
+
+/// Position in input where an error occurred
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Location {
+    /// Byte offset in file where failing packet or record starts
+    pub offset: u64,
+    /// Index of failing record
+    pub position: u64,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at byte {}, record {}", self.offset, self.position)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReaderError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    /// Config is invalid or does not match the data
+    Config(String),
+    /// Packet ended before the number of bytes it declared
+    TruncatedPacket {
+        expected: usize,
+        available: usize,
+        at: Location,
+    },
+    /// Declared packet size is larger than the read buffer
+    PacketTooLarge {
+        size: usize,
+        max: usize,
+        at: Location,
+    },
+    /// No column details for identifier and no default `0` entry
+    UnknownPacketIdentifier {
+        identifier: Value,
+        at: Location,
+    },
+    Decompression {
+        message: String,
+        at: Location,
+    },
+    /// Column reads past the end of packet buffer
+    ColumnOutOfBounds {
+        column: String,
+        start: usize,
+        end: usize,
+        available: usize,
+        at: Location,
+    },
+    /// Bytes of column can not be represented as it's dtype
+    InvalidValue {
+        column: String,
+        message: String,
+        at: Location,
+    },
+    /// Record of a text format is malformed
+    InvalidRecord {
+        message: String,
+        at: Location,
+    },
+}
+
+impl ReaderError {
+    /// Sets location on errors raised without knowledge of file position
+    /// e.g. errors from column decoding
+    pub(crate) fn at(mut self, location: Location) -> ReaderError {
+        match &mut self {
+            ReaderError::TruncatedPacket { at, .. }
+            | ReaderError::PacketTooLarge { at, .. }
+            | ReaderError::UnknownPacketIdentifier { at, .. }
+            | ReaderError::Decompression { at, .. }
+            | ReaderError::ColumnOutOfBounds { at, .. }
+            | ReaderError::InvalidValue { at, .. }
+            | ReaderError::InvalidRecord { at, .. } => *at = location,
+            ReaderError::Io(_)
+            | ReaderError::Json(_)
+            | ReaderError::Csv(_)
+            | ReaderError::Config(_) => {}
+        }
+
+        self
+    }
+
+    /// Location of error, if it is tied to a position in input
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            ReaderError::TruncatedPacket { at, .. }
+            | ReaderError::PacketTooLarge { at, .. }
+            | ReaderError::UnknownPacketIdentifier { at, .. }
+            | ReaderError::Decompression { at, .. }
+            | ReaderError::ColumnOutOfBounds { at, .. }
+            | ReaderError::InvalidValue { at, .. }
+            | ReaderError::InvalidRecord { at, .. } => Some(*at),
+            ReaderError::Io(_)
+            | ReaderError::Json(_)
+            | ReaderError::Csv(_)
+            | ReaderError::Config(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReaderError::Io(e) => write!(f, "io error: {e}"),
+            ReaderError::Json(e) => write!(f, "json error: {e}"),
+            ReaderError::Csv(e) => write!(f, "csv error: {e}"),
+            ReaderError::Config(message) => write!(f, "config error: {message}"),
+            ReaderError::TruncatedPacket {
+                expected,
+                available,
+                at,
+            } => write!(
+                f,
+                "truncated packet {at}: expected {expected} bytes, found {available}"
+            ),
+            ReaderError::PacketTooLarge { size, max, at } => {
+                write!(f, "packet too large {at}: {size} bytes, max is {max}")
+            }
+            ReaderError::UnknownPacketIdentifier { identifier, at } => {
+                write!(f, "unknown packet identifier {identifier} {at}")
+            }
+            ReaderError::Decompression { message, at } => {
+                write!(f, "decompression failed {at}: {message}")
+            }
+            ReaderError::ColumnOutOfBounds {
+                column,
+                start,
+                end,
+                available,
+                at,
+            } => write!(
+                f,
+                "column {column} out of bounds {at}: bytes {start}..{end} of {available}"
+            ),
+            ReaderError::InvalidValue {
+                column,
+                message,
+                at,
+            } => write!(f, "invalid value for column {column} {at}: {message}"),
+            ReaderError::InvalidRecord { message, at } => {
+                write!(f, "invalid record {at}: {message}")
+            }
+        }
+    }
+}
+
+impl Error for ReaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReaderError::Io(e) => Some(e),
+            ReaderError::Json(e) => Some(e),
+            ReaderError::Csv(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReaderError {
+    fn from(e: io::Error) -> Self {
+        ReaderError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ReaderError {
+    fn from(e: serde_json::Error) -> Self {
+        ReaderError::Json(e)
+    }
+}
+
+impl From<csv::Error> for ReaderError {
+    fn from(e: csv::Error) -> Self {
+        ReaderError::Csv(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Config, Reader, Type};
+
+    #[test]
+    fn location_is_set_on_errors_of_input() {
+        let at = Location {
+            offset: 10,
+            position: 2,
+        };
+
+        let error = ReaderError::InvalidValue {
+            column: "Price".to_string(),
+            message: "not a number".to_string(),
+            at: Location::default(),
+        }
+        .at(at);
+
+        assert_eq!(error.location(), Some(at));
+        assert_eq!(
+            error.to_string(),
+            "invalid value for column Price at byte 10, record 2: not a number"
+        );
+
+        let error = ReaderError::Config("bad".to_string()).at(at);
+
+        assert_eq!(error.location(), None);
+        assert_eq!(error.to_string(), "config error: bad");
+    }
+
+    #[test]
+    fn bad_record_reports_where_it_starts() {
+        let config: Config = serde_json::from_value(json!({"selected_columns": []})).unwrap();
+        let reader = Reader::new_with_config(
+            config,
+            b"{\"a\": 1}\n{\"a\": 2}\n{\"a\"\n".to_vec(),
+            Type::JsonLines,
+        );
+
+        let error = reader.read(None, None).unwrap_err();
+
+        assert_eq!(
+            error.location(),
+            Some(Location {
+                offset: 18,
+                position: 2
+            })
+        );
+    }
+
+    #[test]
+    fn bad_column_reports_packet_it_is_in() {
+        let config: Config = serde_json::from_value(json!({
+            "selected_columns": [],
+            "native_columns": [{"name": "Price", "dtype": "f64", "offset": 0, "length": 8}]
+        }))
+        .unwrap();
+
+        let mut packets = 1.5f64.to_be_bytes().to_vec();
+        packets.extend(f64::NAN.to_be_bytes());
+
+        let reader = Reader::new_with_config(config, packets, Type::Native);
+        let records: Vec<_> = reader.iter().unwrap().collect();
+
+        assert_eq!(records[0].as_ref().unwrap()["Price"], json!(1.5));
+        assert!(matches!(
+            &records[1],
+            Err(ReaderError::InvalidValue {
+                column,
+                at: Location {
+                    offset: 8,
+                    position: 1
+                },
+                ..
+            }) if column == "Price"
+        ));
+    }
+}
@@ -0,0 +1,818 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+
+use crate::{parse_datetime, ReaderError};
+
+/// Condition records must meet to be returned
+/// e.g. Token = 12345 and TransactionCode in (7208, 7202) and timestamp between '09:15' and '09:20'
+///
+/// Supports =, !=, <, <=, >, >=, in, between, starts_with, is null, and, or, not and parentheses
+/// Strings are compared as numbers, datetimes or times of day when the other side is one
+/// Columns missing from a record are unknown, so they fail every comparison, negated or not
+/// Only is null matches them
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Filter(pub(crate) Expr);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        column: String,
+        op: Op,
+        value: Value,
+    },
+    In {
+        column: String,
+        values: Vec<Value>,
+    },
+    Between {
+        column: String,
+        low: Value,
+        high: Value,
+    },
+    StartsWith {
+        column: String,
+        prefix: String,
+    },
+    IsNull {
+        column: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Filter {
+    pub fn parse(expression: &str) -> Result<Filter, ReaderError> {
+        let tokens = tokenize(expression)?;
+
+        let mut parser = Parser {
+            tokens,
+            index: 0,
+            end: expression.chars().count(),
+        };
+
+        let expr = parser.or()?;
+
+        match parser.tokens.get(parser.index) {
+            None => Ok(Filter(expr)),
+            Some((token, at)) => Err(invalid(*at, format!("unexpected {token}"))),
+        }
+    }
+
+    /// True if record meets the condition
+    pub fn matches(&self, record: &Map<String, Value>) -> bool {
+        self.0.eval(record, false) == Some(true)
+    }
+
+    /// True if condition fails for every record with these columns
+    /// Columns missing from partial record may still match, e.g. under not
+    pub(crate) fn rejects(&self, partial: &Map<String, Value>) -> bool {
+        self.0.eval(partial, true) == Some(false)
+    }
+
+    /// Names of top level columns used by condition
+    pub(crate) fn columns(&self) -> Vec<&str> {
+        let mut columns = Vec::new();
+
+        self.0.columns(&mut columns);
+
+        columns
+    }
+
+    /// Condition met when both are
+    pub(crate) fn and(self, other: Filter) -> Filter {
+        Filter(Expr::And(Box::new(self.0), Box::new(other.0)))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ReaderError;
+
+    fn from_str(expression: &str) -> Result<Filter, ReaderError> {
+        Filter::parse(expression)
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = ReaderError;
+
+    fn try_from(expression: String) -> Result<Filter, ReaderError> {
+        Filter::parse(&expression)
+    }
+}
+
+/// Column name without fields and indexes of flattened names
+/// e.g. Levels for Levels[0].Qty
+pub(crate) fn root_column(name: &str) -> &str {
+    name.split(['.', '[']).next().unwrap_or(name)
+}
+
+/// Value of column, or of field and index path in struct and array columns
+/// e.g. Levels[0].Qty, flattened records have it as a name
+/// None when root column is missing, Some(None) when path is not in it
+fn path_value<'v>(record: &'v Map<String, Value>, column: &str) -> Option<Option<&'v Value>> {
+    if let Some(value) = record.get(column) {
+        return Some(Some(value));
+    }
+
+    let root = root_column(column);
+    let mut value = record.get(root)?;
+    let mut rest = &column[root.len()..];
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let next = if let Some(field) = rest.strip_prefix('.') {
+            let end = field.find(['.', '[']).unwrap_or(field.len());
+            rest = &field[end..];
+
+            value.get(&field[..end])
+        } else if let Some((index, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']'))
+        {
+            rest = after;
+
+            index
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| value.get(i))
+        } else {
+            None
+        };
+
+        match next {
+            Some(next) => value = next,
+            None => return Some(None),
+        }
+    }
+
+    Some(Some(value))
+}
+
+impl Expr {
+    /// None when result is unknown, like sql null
+    /// Comparisons of missing columns are unknown, is null of them only in partial records
+    fn eval(&self, record: &Map<String, Value>, partial: bool) -> Option<bool> {
+        let lookup = |column: &str| match path_value(record, column) {
+            Some(value) => Some(value),
+            None if partial => None,
+            None => Some(None),
+        };
+
+        match self {
+            Expr::And(left, right) => match left.eval(record, partial) {
+                Some(false) => Some(false),
+                left => match (left, right.eval(record, partial)?) {
+                    (_, false) => Some(false),
+                    (left, true) => left,
+                },
+            },
+            Expr::Or(left, right) => match left.eval(record, partial) {
+                Some(true) => Some(true),
+                left => match (left, right.eval(record, partial)?) {
+                    (_, true) => Some(true),
+                    (left, false) => left,
+                },
+            },
+            Expr::Not(expr) => expr.eval(record, partial).map(|matched| !matched),
+            Expr::Compare { column, op, value } => {
+                let ordering = compare(lookup(column)??, value);
+
+                Some(match op {
+                    Op::Eq => ordering == Some(Ordering::Equal),
+                    Op::Ne => ordering != Some(Ordering::Equal),
+                    Op::Lt => ordering == Some(Ordering::Less),
+                    Op::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    Op::Gt => ordering == Some(Ordering::Greater),
+                    Op::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                })
+            }
+            Expr::In { column, values } => {
+                let found = lookup(column)??;
+
+                Some(
+                    values
+                        .iter()
+                        .any(|value| compare(found, value) == Some(Ordering::Equal)),
+                )
+            }
+            Expr::Between { column, low, high } => {
+                let found = lookup(column)??;
+
+                Some(
+                    matches!(
+                        compare(found, low),
+                        Some(Ordering::Greater | Ordering::Equal)
+                    ) && matches!(compare(found, high), Some(Ordering::Less | Ordering::Equal)),
+                )
+            }
+            Expr::StartsWith { column, prefix } => Some(match lookup(column)?? {
+                Value::String(found) => found.starts_with(prefix.as_str()),
+                Value::Number(found) => found.to_string().starts_with(prefix.as_str()),
+                _ => false,
+            }),
+            Expr::IsNull { column } => Some(lookup(column)?.is_none_or(Value::is_null)),
+        }
+    }
+
+    fn columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        match self {
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.columns(columns);
+                right.columns(columns);
+            }
+            Expr::Not(expr) => expr.columns(columns),
+            Expr::Compare { column, .. }
+            | Expr::In { column, .. }
+            | Expr::Between { column, .. }
+            | Expr::StartsWith { column, .. }
+            | Expr::IsNull { column } => {
+                let column = root_column(column);
+
+                if !columns.contains(&column) {
+                    columns.push(column);
+                }
+            }
+        }
+    }
+}
+
+/// Orders value of record against literal of filter
+/// None when they can not be compared, e.g. a string against a number
+pub(crate) fn compare(found: &Value, literal: &Value) -> Option<Ordering> {
+    match (found, literal) {
+        (Value::Number(found), Value::Number(literal)) => compare_numbers(found, literal),
+        // Text formats have numbers as strings
+        (Value::String(found), Value::Number(literal)) => {
+            compare_numbers(&Number::from_str(found.trim()).ok()?, literal)
+        }
+        (Value::Number(found), Value::String(literal)) => {
+            compare_numbers(found, &Number::from_str(literal.trim()).ok()?)
+        }
+        (Value::String(found), Value::String(literal)) => {
+            compare_times(found, literal).or_else(|| Some(found.as_str().cmp(literal)))
+        }
+        (Value::Bool(found), Value::Bool(literal)) => Some(found.cmp(literal)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn compare_numbers(found: &Number, literal: &Number) -> Option<Ordering> {
+    match (found.as_i64(), literal.as_i64()) {
+        (Some(found), Some(literal)) => Some(found.cmp(&literal)),
+        _ => match (found.as_u64(), literal.as_u64()) {
+            (Some(found), Some(literal)) => Some(found.cmp(&literal)),
+            _ => found.as_f64()?.partial_cmp(&literal.as_f64()?),
+        },
+    }
+}
+
+/// Compares datetime of record against a datetime or time of day
+/// Naive literals are taken in offset of record
+fn compare_times(found: &str, literal: &str) -> Option<Ordering> {
+    let found = parse_datetime(&Value::from(found))?;
+
+    if let Some(time) = parse_time(literal) {
+        return Some(found.time().cmp(&time));
+    }
+
+    let literal = parse_datetime(&Value::from(literal))
+        .or_else(|| naive_datetime(literal, found.offset()))?;
+
+    Some(found.cmp(&literal))
+}
+
+fn parse_time(literal: &str) -> Option<NaiveTime> {
+    ["%H:%M:%S%.f", "%H:%M"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(literal, format).ok())
+}
+
+fn naive_datetime(literal: &str, offset: &FixedOffset) -> Option<DateTime<FixedOffset>> {
+    let naive = [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(literal, format).ok())
+    .or_else(|| {
+        NaiveDate::from_str(literal)
+            .ok()
+            .map(|date| date.and_time(NaiveTime::MIN))
+    })?;
+
+    naive.and_local_timezone(*offset).single()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Op(Op),
+    Open,
+    Close,
+    Comma,
+    And,
+    Or,
+    Not,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{name}"),
+            Token::Literal(value) => write!(f, "{value}"),
+            Token::Op(op) => f.write_str(match op {
+                Op::Eq => "'='",
+                Op::Ne => "'!='",
+                Op::Lt => "'<'",
+                Op::Le => "'<='",
+                Op::Gt => "'>'",
+                Op::Ge => "'>='",
+            }),
+            Token::Open => f.write_str("'('"),
+            Token::Close => f.write_str("')'"),
+            Token::Comma => f.write_str("','"),
+            Token::And => f.write_str("and"),
+            Token::Or => f.write_str("or"),
+            Token::Not => f.write_str("not"),
+        }
+    }
+}
+
+fn invalid(at: usize, message: String) -> ReaderError {
+    ReaderError::Config(format!("Invalid filter at character {at}: {message}"))
+}
+
+/// Splits expression into tokens with their character offsets
+fn tokenize(expression: &str) -> Result<Vec<(Token, usize)>, ReaderError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '=' => {
+                // == is the same as =
+                if chars.get(i + 1) == Some(&'=') {
+                    i += 1;
+                }
+
+                Token::Op(Op::Eq)
+            }
+            '!' | '<' | '>' => {
+                let next = chars.get(i + 1).copied();
+
+                let (op, len) = match (c, next) {
+                    ('!', Some('=')) | ('<', Some('>')) => (Token::Op(Op::Ne), 2),
+                    ('<', Some('=')) => (Token::Op(Op::Le), 2),
+                    ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+                    ('<', _) => (Token::Op(Op::Lt), 1),
+                    ('>', _) => (Token::Op(Op::Gt), 1),
+                    _ => (Token::Not, 1),
+                };
+
+                i += len - 1;
+                op
+            }
+            '&' | '|' if chars.get(i + 1) == Some(&c) => {
+                i += 1;
+
+                if c == '&' {
+                    Token::And
+                } else {
+                    Token::Or
+                }
+            }
+            '\'' | '"' | '`' => {
+                // Quotes are escaped by doubling them
+                let mut text = String::new();
+
+                loop {
+                    i += 1;
+
+                    match chars.get(i) {
+                        Some(&q) if q == c && chars.get(i + 1) == Some(&c) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                        Some(&q) if q == c => break,
+                        Some(&other) => text.push(other),
+                        None => return Err(invalid(start, "unterminated quote".to_string())),
+                    }
+                }
+
+                // Backticks quote column names
+                match c {
+                    '`' => Token::Ident(text),
+                    _ => Token::Literal(Value::String(text)),
+                }
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' => {
+                while chars
+                    .get(i + 1)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'))
+                {
+                    i += 1;
+                }
+
+                let text: String = chars[start..=i].iter().collect();
+                let number = Number::from_str(text.trim_start_matches('+'))
+                    .map_err(|_| invalid(start, format!("invalid number {text}")))?;
+
+                Token::Literal(Value::Number(number))
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                // Flattened names have fields and indexes, e.g. Levels[0].Qty
+                while chars
+                    .get(i + 1)
+                    .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '[' | ']'))
+                {
+                    i += 1;
+                }
+
+                let word: String = chars[start..=i].iter().collect();
+
+                match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    _ => Token::Ident(word),
+                }
+            }
+            c => return Err(invalid(start, format!("unexpected '{c}'"))),
+        };
+
+        tokens.push((token, start));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser, not binds tighter than and, and tighter than or
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    /// Character length of expression
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(token, _)| token)
+    }
+
+    /// Offset of next token, or end of expression
+    fn at(&self) -> usize {
+        self.tokens.get(self.index).map_or(self.end, |(_, at)| *at)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.index += 1;
+
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ReaderError> {
+        let at = self.at();
+
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(invalid(at, format!("expected {expected}, found {token}"))),
+            None => Err(invalid(at, format!("expected {expected}"))),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ReaderError> {
+        let mut expr = self.and()?;
+
+        while self.peek() == Some(&Token::Or) {
+            self.index += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ReaderError> {
+        let mut expr = self.unary()?;
+
+        while self.peek() == Some(&Token::And) {
+            self.index += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ReaderError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.index += 1;
+
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.index += 1;
+
+                let expr = self.or()?;
+                self.expect(Token::Close)?;
+
+                Ok(expr)
+            }
+            _ => self.predicate(),
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Expr, ReaderError> {
+        let at = self.at();
+
+        let column = match self.next() {
+            Some(Token::Ident(column)) => column,
+            Some(token) => return Err(invalid(at, format!("expected column, found {token}"))),
+            None => return Err(invalid(at, "expected column".to_string())),
+        };
+
+        // not in, not between and not starts_with negate the predicate
+        let negated = self.peek() == Some(&Token::Not);
+
+        if negated {
+            self.index += 1;
+        }
+
+        let at = self.at();
+
+        let expr = match self.next() {
+            Some(Token::Op(op)) if !negated => Expr::Compare {
+                column,
+                op,
+                value: self.literal()?,
+            },
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("in") => {
+                self.expect(Token::Open)?;
+
+                let mut values = vec![self.literal()?];
+
+                while self.peek() == Some(&Token::Comma) {
+                    self.index += 1;
+                    values.push(self.literal()?);
+                }
+
+                self.expect(Token::Close)?;
+
+                Expr::In { column, values }
+            }
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("between") => {
+                let low = self.literal()?;
+                self.expect(Token::And)?;
+                let high = self.literal()?;
+
+                Expr::Between { column, low, high }
+            }
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("starts_with") => {
+                let at = self.at();
+
+                match self.literal()? {
+                    Value::String(prefix) => Expr::StartsWith { column, prefix },
+                    value => {
+                        return Err(invalid(
+                            at,
+                            format!("expected string prefix, found {value}"),
+                        ))
+                    }
+                }
+            }
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case("is") && !negated => {
+                let negated = self.peek() == Some(&Token::Not);
+
+                if negated {
+                    self.index += 1;
+                }
+
+                self.expect(Token::Literal(Value::Null))?;
+
+                let expr = Expr::IsNull { column };
+
+                return Ok(match negated {
+                    true => Expr::Not(Box::new(expr)),
+                    false => expr,
+                });
+            }
+            Some(token) => return Err(invalid(at, format!("expected operator, found {token}"))),
+            None => return Err(invalid(at, "expected operator".to_string())),
+        };
+
+        Ok(match negated {
+            true => Expr::Not(Box::new(expr)),
+            false => expr,
+        })
+    }
+
+    fn literal(&mut self) -> Result<Value, ReaderError> {
+        let at = self.at();
+
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(value),
+            Some(token) => Err(invalid(at, format!("expected value, found {token}"))),
+            None => Err(invalid(at, "expected value".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn record(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    fn matches(filter: &str, value: Value) -> bool {
+        Filter::parse(filter).unwrap().matches(&record(value))
+    }
+
+    fn rejects(filter: &str, value: Value) -> bool {
+        Filter::parse(filter).unwrap().rejects(&record(value))
+    }
+
+    #[test]
+    fn parses_precedence() {
+        let filter = Filter::parse("a = 1 or b = 2 and not c = 3").unwrap();
+
+        let compare = |column: &str, value: i32| {
+            Box::new(Expr::Compare {
+                column: column.to_string(),
+                op: Op::Eq,
+                value: json!(value),
+            })
+        };
+
+        assert_eq!(
+            filter.0,
+            Expr::Or(
+                compare("a", 1),
+                Box::new(Expr::And(
+                    compare("b", 2),
+                    Box::new(Expr::Not(compare("c", 3)))
+                )),
+            )
+        );
+        assert_eq!(
+            Filter::parse("(a = 1 or b = 2) && c == 3").unwrap(),
+            Filter::parse("(a = 1 || b = 2) and c = 3").unwrap(),
+        );
+    }
+
+    #[test]
+    fn parses_predicates() {
+        let filter = Filter::parse(
+            "`Odd Name` in (1, 'x') and p between -1.5 and 2e3 and s starts_with 'AB'",
+        )
+        .unwrap();
+
+        assert_eq!(filter.columns(), ["Odd Name", "p", "s"]);
+        assert_eq!(
+            Filter::parse("a not in (1)").unwrap().0,
+            Expr::Not(Box::new(Expr::In {
+                column: "a".to_string(),
+                values: vec![json!(1)],
+            }))
+        );
+        assert_eq!(
+            Filter::parse("Levels[0].Qty is not null")
+                .unwrap()
+                .columns(),
+            ["Levels"]
+        );
+    }
+
+    #[test]
+    fn reports_position_of_errors() {
+        for (filter, at) in [
+            ("a = ", 4),
+            ("a = 'x", 4),
+            ("a ~ 1", 2),
+            ("a = 1 b", 6),
+            ("(a = 1", 6),
+            ("a starts_with 1", 14),
+        ] {
+            let Err(ReaderError::Config(message)) = Filter::parse(filter) else {
+                panic!("{filter} must be invalid");
+            };
+
+            assert!(
+                message.starts_with(&format!("Invalid filter at character {at}:")),
+                "{filter}: {message}"
+            );
+        }
+    }
+
+    #[test]
+    fn compares_numbers_strings_and_times() {
+        assert!(matches("a > 9", json!({"a": 10})));
+        // Text formats have numbers as strings
+        assert!(matches("a > 9", json!({"a": "10"})));
+        assert!(matches("a = 'x''y'", json!({"a": "x'y"})));
+        assert!(matches("a < 'b'", json!({"a": "a"})));
+        assert!(!matches("a = 1", json!({"a": "x"})));
+        assert!(matches("a in (1, 2)", json!({"a": 2})));
+        assert!(matches("a between 1 and 2", json!({"a": 1.5})));
+        assert!(matches("a starts_with '12'", json!({"a": 123})));
+        assert!(matches(
+            "t between '09:15' and '09:20'",
+            json!({"t": "2024-01-02T09:16:00+05:30"})
+        ));
+        assert!(matches(
+            "t >= '2024-01-02 09:00'",
+            json!({"t": "2024-01-02T09:16:00+05:30"})
+        ));
+        assert!(matches("a is null", json!({"a": null})));
+    }
+
+    #[test]
+    fn missing_columns_fail_every_comparison() {
+        let empty = json!({});
+
+        assert!(!matches("a = 1", empty.clone()));
+        assert!(!matches("a != 1", empty.clone()));
+        assert!(!matches("not a = 1", empty.clone()));
+        assert!(!matches("a not in (1)", empty.clone()));
+        assert!(matches("a is null", empty.clone()));
+        assert!(!matches("a is not null", empty.clone()));
+        assert!(matches("a = 1 or b = 2", json!({"b": 2})));
+        assert!(!matches("not (a = 1 or b = 3)", json!({"b": 2})));
+    }
+
+    #[test]
+    fn rejects_only_when_no_record_can_match() {
+        // Known columns decide
+        assert!(rejects("a = 1 and b = 2", json!({"a": 2})));
+        assert!(rejects("a = 1 or a = 2", json!({"a": 3})));
+        assert!(rejects("not a = 1", json!({"a": 1})));
+
+        // Unknown columns may still match
+        assert!(!rejects("a = 1 and b = 2", json!({"a": 1})));
+        assert!(!rejects("a = 1 or b = 2", json!({"a": 3})));
+        assert!(!rejects("not b = 1", json!({"a": 1})));
+        assert!(!rejects("b is null", json!({"a": 1})));
+        assert!(!rejects("not b is null", json!({"a": 1})));
+    }
+
+    #[test]
+    fn looks_up_paths_in_struct_and_array_columns() {
+        let record = json!({"Levels": [{"Qty": 6}, {"Qty": 2}], "Last": {"Side": "B"}});
+
+        assert!(matches("Levels[0].Qty > 5", record.clone()));
+        assert!(matches(
+            "Levels[1].Qty = 2 and Last.Side = 'B'",
+            record.clone()
+        ));
+        assert!(matches("Levels[2].Qty is null", record.clone()));
+        assert!(!matches("Levels[2].Qty = 0", record.clone()));
+
+        // Flattened records have paths as names
+        assert!(matches("Levels[0].Qty = 6", json!({"Levels[0].Qty": 6})));
+
+        // Path of an undecoded column is unknown
+        assert!(!rejects("Levels[0].Qty = 1", json!({"Last": {}})));
+        assert!(rejects(
+            "Levels[0].Qty = 1",
+            json!({"Levels": [{"Qty": 6}]})
+        ));
+    }
+}
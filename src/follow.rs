@@ -0,0 +1,289 @@
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use serde_json::{Map, Value};
+
+use crate::{Position, Reader, ReaderError, RecordIter, Source};
+
+/// File which is still being written
+/// Reads wait for more data at EOF instead of returning 0
+/// EOF is only returned once file is rotated or truncated
+struct FollowFile {
+    file: File,
+    file_path: String,
+    position: u64,
+    poll_interval: Duration,
+    replaced: Arc<AtomicBool>,
+}
+
+impl FollowFile {
+    /// True once another file is at path or file is shorter than what was read
+    fn is_replaced(&self) -> io::Result<bool> {
+        // Path is missing while a rotated file is being created
+        let Ok(metadata) = fs::metadata(&self.file_path) else {
+            return Ok(false);
+        };
+
+        Ok(metadata.len() < self.position || !is_same_file(&metadata, &self.file.metadata()?))
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_a: &Metadata, _b: &Metadata) -> bool {
+    true
+}
+
+impl Read for FollowFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.replaced.load(Ordering::Relaxed) {
+            return Ok(0);
+        }
+
+        loop {
+            let n = self.file.read(buf)?;
+
+            if n > 0 {
+                self.position += n as u64;
+
+                return Ok(n);
+            }
+
+            if self.is_replaced()? {
+                // Data written just before rotation is read first
+                let n = self.file.read(buf)?;
+                self.position += n as u64;
+
+                if n == 0 {
+                    self.replaced.store(true, Ordering::Relaxed);
+                }
+
+                return Ok(n);
+            }
+
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+impl Seek for FollowFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.file.seek(pos)?;
+
+        Ok(self.position)
+    }
+}
+
+/// Decodes records of a growing file as they are appended
+/// Starts over when file is rotated or truncated
+pub(crate) struct Follow<'a> {
+    reader: &'a Reader,
+    file_path: String,
+    poll_interval: Duration,
+    records: RecordIter<'a>,
+    replaced: Arc<AtomicBool>,
+}
+
+impl<'a> Follow<'a> {
+    pub(crate) fn new(
+        reader: &'a Reader,
+        file_path: String,
+        poll_interval: Duration,
+    ) -> Result<Follow<'a>, ReaderError> {
+        let file = File::open(&file_path)?;
+        let replaced = Arc::new(AtomicBool::new(false));
+
+        let records = open_records(reader, file, &file_path, poll_interval, &replaced)?;
+
+        Ok(Follow {
+            reader,
+            file_path,
+            poll_interval,
+            records,
+            replaced,
+        })
+    }
+
+    /// Opens file at path again, waiting until it is created
+    fn reopen(&mut self) -> Result<(), ReaderError> {
+        let file = loop {
+            match File::open(&self.file_path) {
+                Ok(file) => break file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => thread::sleep(self.poll_interval),
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        self.replaced = Arc::new(AtomicBool::new(false));
+        self.records = open_records(
+            self.reader,
+            file,
+            &self.file_path,
+            self.poll_interval,
+            &self.replaced,
+        )?;
+
+        Ok(())
+    }
+}
+
+fn open_records<'a>(
+    reader: &'a Reader,
+    file: File,
+    file_path: &str,
+    poll_interval: Duration,
+    replaced: &Arc<AtomicBool>,
+) -> Result<RecordIter<'a>, ReaderError> {
+    let file = FollowFile {
+        file,
+        file_path: file_path.to_string(),
+        position: 0,
+        poll_interval,
+        replaced: replaced.clone(),
+    };
+
+    reader.adapter().stream_at(
+        Source::seekable(file),
+        &reader.config,
+        Position::FromStart(0),
+    )
+}
+
+impl Iterator for Follow<'_> {
+    type Item = Result<Map<String, Value>, ReaderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.records.next() {
+                return Some(record);
+            }
+
+            // Records end early only when decoding can not continue
+            if !self.replaced.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            if let Err(e) = self.reopen() {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs::OpenOptions, io::Write, process};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Config, Type};
+
+    const POLL: Duration = Duration::from_millis(5);
+
+    fn follow_file(name: &str, lines: &str) -> (String, Reader) {
+        let path = env::temp_dir()
+            .join(format!("reader-follow-{}-{name}.jsonl", process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        fs::write(&path, lines).unwrap();
+
+        let config: Config = serde_json::from_value(json!({"selected_columns": []})).unwrap();
+
+        (
+            path.clone(),
+            Reader::new_with_config(config, path, Type::JsonLines),
+        )
+    }
+
+    /// Runs change on file after reader had time to wait at end of it
+    fn later(change: impl FnOnce() + Send + 'static) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            change();
+        })
+    }
+
+    fn next_i(records: &mut RecordIter) -> Value {
+        records.next().unwrap().unwrap()["i"].clone()
+    }
+
+    #[test]
+    fn reads_appended_records() {
+        let (path, reader) = follow_file("append", "{\"i\": 0}\n");
+        let mut records = reader.follow(POLL).unwrap();
+
+        assert_eq!(next_i(&mut records), json!(0));
+
+        let append_path = path.clone();
+        let writer = later(move || {
+            let mut file = OpenOptions::new().append(true).open(append_path).unwrap();
+            file.write_all(b"{\"i\": 1}\n").unwrap();
+        });
+
+        assert_eq!(next_i(&mut records), json!(1));
+
+        writer.join().unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn starts_over_after_rotation() {
+        let (path, reader) = follow_file("rotate", "{\"i\": 0}\n{\"i\": 1}\n");
+        let mut records = reader.follow(POLL).unwrap();
+
+        assert_eq!(next_i(&mut records), json!(0));
+        assert_eq!(next_i(&mut records), json!(1));
+
+        let rotate_path = path.clone();
+        let writer = later(move || {
+            fs::rename(&rotate_path, format!("{rotate_path}.1")).unwrap();
+            fs::write(&rotate_path, "{\"i\": 10}\n").unwrap();
+        });
+
+        assert_eq!(next_i(&mut records), json!(10));
+
+        writer.join().unwrap();
+        fs::remove_file(format!("{path}.1")).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn starts_over_after_truncation() {
+        let (path, reader) = follow_file("truncate", "{\"i\": 0, \"padding\": \"....\"}\n");
+        let mut records = reader.follow(POLL).unwrap();
+
+        assert_eq!(next_i(&mut records), json!(0));
+
+        let truncate_path = path.clone();
+        let writer = later(move || fs::write(truncate_path, "{\"i\": 20}\n").unwrap());
+
+        assert_eq!(next_i(&mut records), json!(20));
+
+        writer.join().unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn needs_file_path() {
+        let config: Config = serde_json::from_value(json!({"selected_columns": []})).unwrap();
+        let reader = Reader::new_with_config(config, b"{}\n".to_vec(), Type::JsonLines);
+
+        assert!(matches!(reader.follow(POLL), Err(ReaderError::Config(_))));
+    }
+}
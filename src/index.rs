@@ -0,0 +1,435 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    sync::{Arc, Mutex, PoisonError},
+    time::UNIX_EPOCH,
+};
+
+use crate::{Config, Readable, ReaderError, Source};
+
+const MAGIC: &[u8; 8] = b"GRIDX002";
+const DEFAULT_STRIDE: u64 = 1024;
+
+/// Where a record starts in input, after decompression
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    /// Index of record
+    pub record: u64,
+    /// Byte offset of record, or of multi native frame which contains it
+    pub offset: u64,
+    /// Sub packet index in multi native frames, line number in csv files
+    pub inner: u64,
+}
+
+/// Walks records without decoding them
+pub type CheckpointIter<'a> = Box<dyn Iterator<Item = Result<Checkpoint, ReaderError>> + 'a>;
+
+/// Size and modification time of indexed file
+/// Index is rebuilt when either changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stamp {
+    size: u64,
+    modified: u64,
+}
+
+impl Stamp {
+    fn of(file_path: &str) -> Result<Stamp, ReaderError> {
+        let metadata = fs::metadata(file_path)?;
+
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+
+        Ok(Stamp {
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Hash of config settings which decide where records start
+/// Index is rebuilt when they change, e.g. after editing packet layouts
+fn fingerprint(config: &Config) -> u64 {
+    let native = &config.native;
+
+    // Config maps are ordered, so their debug text is stable
+    let settings = format!(
+        "{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}",
+        config.native_columns,
+        native.packing,
+        native.endian,
+        native.packet_header,
+        native.packet_info,
+        native.structs,
+        native.max_packet_size,
+        config.file_compression,
+        config.use_default_columns,
+        config.default_columns,
+    );
+
+    // FNV-1a, which unlike std hashers is the same across builds
+    settings.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Checkpoints of every stride'th record of a file
+#[derive(Debug)]
+pub(crate) struct Index {
+    file_path: String,
+    stamp: Stamp,
+    fingerprint: u64,
+    stride: u64,
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// Last used index, saves loading the sidecar file for every page
+#[derive(Debug, Default)]
+pub(crate) struct IndexCache(Mutex<Option<Arc<Index>>>);
+
+impl IndexCache {
+    fn get(&self) -> Option<Arc<Index>> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn set(&self, index: Arc<Index>) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(index);
+    }
+}
+
+impl Index {
+    /// Last checkpoint at or before record from
+    /// None before second checkpoint, as reading from start is as cheap
+    fn find(&self, from: u64) -> Option<Checkpoint> {
+        let i = self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.record <= from);
+
+        i.checked_sub(1)
+            .map(|i| self.checkpoints[i])
+            .filter(|checkpoint| checkpoint.record > 0)
+    }
+
+    fn build(
+        adapter: &dyn Readable,
+        file_path: &str,
+        config: &Config,
+        stamp: Stamp,
+        fingerprint: u64,
+        stride: u64,
+    ) -> Result<Option<Index>, ReaderError> {
+        let Some(records) = adapter.scan(Source::Path(file_path.to_string()), config)? else {
+            return Ok(None);
+        };
+
+        let mut checkpoints = Vec::new();
+
+        for checkpoint in records {
+            match checkpoint {
+                Ok(checkpoint) if checkpoint.record % stride == 0 => checkpoints.push(checkpoint),
+                Ok(_) => {}
+                Err(ReaderError::Io(e)) => return Err(ReaderError::Io(e)),
+                // Bad records still count, they are reported again when read
+                Err(_) => {}
+            }
+        }
+
+        Ok(Some(Index {
+            file_path: file_path.to_string(),
+            stamp,
+            fingerprint,
+            stride,
+            checkpoints,
+        }))
+    }
+
+    /// Loads index file, None if it is missing or belongs to another version of file or config
+    fn load(
+        index_path: &str,
+        file_path: &str,
+        stamp: Stamp,
+        fingerprint: u64,
+        stride: u64,
+    ) -> Option<Index> {
+        let mut reader = BufReader::new(File::open(index_path).ok()?);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic).ok()?;
+
+        let mut next = || {
+            let mut buf = [0; 8];
+            reader
+                .read_exact(&mut buf)
+                .ok()
+                .map(|_| u64::from_le_bytes(buf))
+        };
+
+        if &magic != MAGIC || next()? != fingerprint {
+            return None;
+        }
+
+        let file_stamp = Stamp {
+            size: next()?,
+            modified: next()?,
+        };
+
+        if file_stamp != stamp || next()? != stride {
+            return None;
+        }
+
+        let len = next()?;
+        let checkpoints = (0..len)
+            .map(|_| {
+                Some(Checkpoint {
+                    record: next()?,
+                    offset: next()?,
+                    inner: next()?,
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Index {
+            file_path: file_path.to_string(),
+            stamp,
+            fingerprint,
+            stride,
+            checkpoints,
+        })
+    }
+
+    fn save(&self, index_path: &str) -> Result<(), ReaderError> {
+        let mut writer = BufWriter::new(File::create(index_path)?);
+
+        writer.write_all(MAGIC)?;
+
+        let header = [
+            self.fingerprint,
+            self.stamp.size,
+            self.stamp.modified,
+            self.stride,
+            self.checkpoints.len() as u64,
+        ];
+
+        for value in header {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        for checkpoint in &self.checkpoints {
+            for value in [checkpoint.record, checkpoint.offset, checkpoint.inner] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Index of file, loaded from sidecar file or built and saved if it is stale
+/// None if index is not configured or format seeks without one
+pub(crate) fn load_index(
+    adapter: &dyn Readable,
+    cache: &IndexCache,
+    file_path: &str,
+    config: &Config,
+) -> Result<Option<Arc<Index>>, ReaderError> {
+    let Some(settings) = &config.index else {
+        return Ok(None);
+    };
+
+    let stride = settings.stride.unwrap_or(DEFAULT_STRIDE).max(1);
+    let stamp = Stamp::of(file_path)?;
+    let fingerprint = fingerprint(config);
+
+    let cached = cache.get().filter(|index| {
+        index.file_path == file_path
+            && index.stamp == stamp
+            && index.fingerprint == fingerprint
+            && index.stride == stride
+    });
+
+    if let Some(index) = cached {
+        return Ok(Some(index));
+    }
+
+    let index_path = settings
+        .path
+        .clone()
+        .unwrap_or_else(|| format!("{file_path}.idx"));
+
+    // Index is built without holding the cache, reads of other threads go on meanwhile
+    let index = match Index::load(&index_path, file_path, stamp, fingerprint, stride) {
+        Some(index) => index,
+        None => match Index::build(adapter, file_path, config, stamp, fingerprint, stride)? {
+            Some(index) => {
+                index.save(&index_path)?;
+                index
+            }
+            None => return Ok(None),
+        },
+    };
+
+    let index = Arc::new(index);
+    cache.set(index.clone());
+
+    Ok(Some(index))
+}
+
+/// Checkpoint to start reading at for records from
+/// Only files with a configured index have one
+pub(crate) fn find_checkpoint(
+    adapter: &dyn Readable,
+    cache: &IndexCache,
+    source: &Source,
+    config: &Config,
+    from: u64,
+) -> Result<Option<Checkpoint>, ReaderError> {
+    let Source::Path(file_path) = source else {
+        return Ok(None);
+    };
+
+    if from == 0 {
+        return Ok(None);
+    }
+
+    Ok(load_index(adapter, cache, file_path, config)?.and_then(|index| index.find(from)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Reader, Type};
+
+    /// Json lines file of n records in temp directory
+    fn lines_file(name: &str, n: u64) -> String {
+        let path = env::temp_dir()
+            .join(format!("reader-index-{}-{name}.jsonl", process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let lines: String = (0..n).map(|i| format!("{{\"i\": {i}}}\n")).collect();
+        fs::write(&path, lines).unwrap();
+
+        path
+    }
+
+    fn config(extra: serde_json::Value) -> Config {
+        let mut config = json!({"selected_columns": [], "index": {"stride": 4}});
+
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn cleanup(path: &str) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(format!("{path}.idx"));
+    }
+
+    #[test]
+    fn saves_header_and_checkpoints() {
+        let path = lines_file("format", 10);
+        let reader = Reader::new_with_config(config(json!({})), path.as_str(), Type::JsonLines);
+
+        reader.build_index().unwrap();
+
+        let bytes = fs::read(format!("{path}.idx")).unwrap();
+        let word = |i: usize| u64::from_le_bytes(bytes[8 + i * 8..16 + i * 8].try_into().unwrap());
+
+        assert_eq!(&bytes[..8], MAGIC);
+        assert_eq!(word(0), fingerprint(&reader.config));
+        assert_eq!(word(1), 90);
+        assert_eq!(word(3), 4);
+        // Records 0, 4 and 8
+        assert_eq!(word(4), 3);
+        assert_eq!(bytes.len(), 8 + 5 * 8 + 3 * 3 * 8);
+
+        cleanup(&path);
+    }
+
+    #[test]
+    fn seeks_with_index() {
+        let path = lines_file("seek", 10);
+        let reader = Reader::new_with_config(config(json!({})), path.as_str(), Type::JsonLines);
+
+        let records = reader.read(Some(5), Some(2)).unwrap();
+
+        assert_eq!(records[0]["i"], json!(5));
+        assert_eq!(records[1]["i"], json!(6));
+
+        let index = load_index(
+            &*reader.adapter(),
+            &reader.index_cache,
+            &path,
+            &reader.config,
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(index.find(5).map(|checkpoint| checkpoint.record), Some(4));
+        assert_eq!(index.find(3), None);
+
+        cleanup(&path);
+    }
+
+    #[test]
+    fn index_of_other_config_is_stale() {
+        let path = lines_file("config", 10);
+        let stamp = Stamp::of(&path).unwrap();
+
+        let first = config(json!({}));
+        let other = config(json!({"default_columns": ["i"]}));
+
+        assert_ne!(fingerprint(&first), fingerprint(&other));
+        // Maps of config are ordered, so equal configs hash the same
+        assert_eq!(fingerprint(&first), fingerprint(&config(json!({}))));
+
+        Reader::new_with_config(first, path.as_str(), Type::JsonLines)
+            .build_index()
+            .unwrap();
+
+        let index_path = format!("{path}.idx");
+
+        assert!(Index::load(
+            &index_path,
+            &path,
+            stamp,
+            fingerprint(&config(json!({}))),
+            4
+        )
+        .is_some());
+        assert!(Index::load(&index_path, &path, stamp, fingerprint(&other), 4).is_none());
+        assert!(Index::load(&index_path, &path, stamp, fingerprint(&other), 2).is_none());
+
+        cleanup(&path);
+    }
+
+    #[test]
+    fn index_of_changed_file_is_stale() {
+        let path = lines_file("changed", 10);
+        let reader = Reader::new_with_config(config(json!({})), path.as_str(), Type::JsonLines);
+
+        reader.build_index().unwrap();
+
+        fs::write(&path, "{\"i\": 100}\n".repeat(12)).unwrap();
+
+        let records = reader.read(Some(9), None).unwrap();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["i"], json!(100));
+
+        cleanup(&path);
+    }
+}
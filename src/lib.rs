@@ -1,20 +1,67 @@
-use std::{collections::HashMap, error::Error, fmt::Debug, fs};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt::Debug,
+    fs,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
 
+use adapters::utils::{
+    column_utils::{expand_switches, Projection},
+    value_utils,
+};
 use adapters::{
     csv_adapter::CsvAdapter, json_lines_adapter::JsonLineAdapter,
     multi_native_adapter::MultiNative, native_adapter::NativeAdapter,
 };
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde_json::{Map, Value};
 
 mod adapters;
+mod aggregate;
+mod config_gen;
+mod decompress;
+mod error;
+mod filter;
+mod follow;
+mod index;
+mod source;
+mod sql;
 
+pub use adapters::utils::value_utils::parse_datetime;
+pub use aggregate::{Aggregate, AggregateFunction, Aggregation, TimeBucket};
+pub use config_gen::{generate_config, HeaderOptions};
+pub use decompress::{CompressionType, Decompressor};
+pub use error::{Location, ReaderError};
+pub use filter::Filter;
+pub use index::{Checkpoint, CheckpointIter};
+pub use source::{ReadSeek, Source};
+
+use crate::adapters::multi_native_adapter::SkippedPackets;
 use crate::adapters::{json_adapter::JsonAdapter, json_array_adapter::JsonArrayAdapter};
+use crate::decompress::Decompressors;
+use crate::follow::Follow;
+use crate::index::{load_index, IndexCache};
+use crate::sql::Query;
+
+/// Lazily decoded records
+/// Each record carries it's own result, so one bad record does not hide the rest
+pub type RecordIter<'a> = Box<dyn Iterator<Item = Result<Map<String, Value>, ReaderError>> + 'a>;
 
 pub struct Reader {
     pub config: Config,
-    pub file_path: String,
+    /// Taken on first read for streams, which can only be read once
+    source: Mutex<Option<Source>>,
     pub _type: Type,
+    /// Codecs registered with register_decompressor
+    decompressors: Decompressors,
+    /// Last used index, saves loading the sidecar file for every page
+    index_cache: Arc<IndexCache>,
+    /// Multi native packets skipped by identifier in last read
+    skipped_packets: Arc<SkippedPackets>,
 }
 
 /// Used to define a value in buffer block
@@ -27,62 +74,285 @@ pub struct BufferValue {
     #[serde(default)]
     dtype: DType,
     offset: Option<usize>,
+    /// Size of one element, struct columns take it from their fields when not set
+    #[serde(default)]
     length: usize,
     #[serde(default)]
     default: bool,
     #[serde(default)]
     ignore: bool,
+    /// Byte order of column
+    /// Inherits from packet columns, then native settings
+    endian: Option<Endian>,
+    /// Encoding of bytes columns
+    #[serde(default)]
+    encoding: ByteEncoding,
+    /// Converts numeric column to decimal value
+    decimal: Option<DecimalFormat>,
+    /// Converts integer column counting time since an epoch to datetime
+    timestamp: Option<TimestampFormat>,
+    /// Name of struct definition in native structs, for struct columns
+    #[serde(rename = "struct")]
+    struct_name: Option<String>,
+    /// Inline fields, for struct columns without a named definition
+    #[serde(default)]
+    fields: Vec<BufferValue>,
+    /// Decodes column as fixed length array of count elements
+    count: Option<usize>,
+    /// Name of a previous column holding number of elements
+    /// Array is sized by it, count is then the number of slots in packet
+    count_from: Option<String>,
+    /// Name of a previous column holding length of char, byte or none column
+    /// e.g. a length prefix declared before the string
+    length_from: Option<String>,
+    /// Name of value map in native value_maps, to translate codes to labels
+    value_map: Option<String>,
+    /// Makes this entry a block of columns chosen by a previous column
+    /// Columns of chosen arm are read in place of it
+    /// Native packets are sized by largest arm
+    switch: Option<Switch>,
+}
+
+/// Labels of coded values, e.g. "B" => "Buy"
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct ValueMap {
+    /// Label for each code, numbers and strings are matched by their text
+    labels: BTreeMap<String, String>,
+    #[serde(default)]
+    output: MapOutput,
+    #[serde(default)]
+    on_unknown: OnUnknown,
+}
+
+/// Values emitted for mapped columns
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MapOutput {
+    #[default]
+    Label,
+    Raw,
+    /// Raw code under column name, label under <name>_label
+    Both,
+}
+
+/// Label of codes missing from value map
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnUnknown {
+    /// Raw code is used as label
+    #[default]
+    Keep,
+    Null,
+    Error,
+}
+
+/// Layout variants of a packet, chosen by value of a decoded column
+/// e.g. a sub type or flag field
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct Switch {
+    /// Name of a previous column
+    on: String,
+    /// Columns for each value, numbers and strings are matched by their text
+    #[serde(default)]
+    cases: BTreeMap<String, Vec<BufferValue>>,
+    /// Columns when no case matches, unmatched values are an error without it
+    default: Option<Vec<BufferValue>>,
+}
+
+/// Describes integer timestamps
+/// e.g. seconds since 1980-01-01 in exchange specs
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct TimestampFormat {
+    /// Start of time as date or datetime, defaults to unix epoch
+    /// Dates without offset are in UTC
+    #[serde(default, deserialize_with = "value_utils::deserialize_epoch")]
+    epoch: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    unit: TimeUnit,
+    /// IANA name like Asia/Kolkata or offset like +05:30, defaults to UTC
+    #[serde(default, deserialize_with = "value_utils::deserialize_timezone")]
+    timezone: Option<TimeZone>,
+    /// strftime pattern for output, ISO-8601 if not set
+    format: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeUnit {
+    #[default]
+    S,
+    Ms,
+    Us,
+    Ns,
 }
 
-#[derive(Debug, Default, Deserialize)]
+/// Timezone of decoded timestamps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeZone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+/// Converts raw numbers like prices in paise to real decimals
+/// value = raw * multiplier / divisor / 10^scale + offset
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct DecimalFormat {
+    /// Number of decimal places in raw value
+    #[serde(default)]
+    scale: u32,
+    divisor: Option<Decimal>,
+    multiplier: Option<Decimal>,
+    offset: Option<Decimal>,
+    #[serde(default)]
+    output: DecimalOutput,
+}
+
+/// Json representation of decimal columns
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DecimalOutput {
+    /// Exact decimal string e.g. "1234.50"
+    #[default]
+    String,
+    /// Json number, may lose precision
+    Number,
+}
+
+/// Byte order of numeric columns
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct PacketHeader {
     packet_size: BufferValue,
     timestamp: BufferValue,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct PacketColumns {
     #[serde(default)]
     skip_bytes: u32,
     columns: Vec<BufferValue>,
+    /// Byte order of columns in packet
+    /// Overrides native settings
+    endian: Option<Endian>,
 }
 
-#[derive(Debug, Default, Deserialize, PartialEq)]
-#[serde(rename_all = "snake_case")]
-pub enum CompressionType {
-    #[default]
-    Lzo,
+/// Column of sub packet which tells if and how it is compressed
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct CompressionIndicator {
+    column: BufferValue,
+    /// Codec for each indicator value, null for uncompressed sub packets
+    /// Sub packets with other values are an error
+    codecs: BTreeMap<String, Option<CompressionType>>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct PacketInfo {
     no_of_packets: BufferValue,
     compressed_packet_size: BufferValue,
-    compresseion_type: CompressionType,
+    /// Codec of compressed packets, defaults to lzo
+    #[serde(default, alias = "compresseion_type")]
+    compression_type: CompressionType,
+    /// Selects codec by indicator column instead of compressed_packet_size > 0
+    /// compression_type is not used when this is set
+    compression_indicator: Option<CompressionIndicator>,
     packet_size: BufferValue,
+    /// Filters on column holding the same value reject packets before their columns are decoded
+    /// Column is found by name, or without one by offset of identifier in every packet type
     packet_identifier: BufferValue,
-    column_details: HashMap<u64, PacketColumns>,
+    column_details: BTreeMap<u64, PacketColumns>,
+    /// Only packets with these identifiers are decoded when set
+    /// Skipped packets still count for positions and record counts
+    #[serde(default)]
+    include: BTreeSet<u64>,
+    /// Packets with these identifiers are skipped
+    #[serde(default)]
+    exclude: BTreeSet<u64>,
+    #[serde(default)]
+    on_unknown_identifier: OnUnknownIdentifier,
 }
 
-#[derive(Debug, Default, Deserialize)]
+/// Handling of packets whose identifier has no column details
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnUnknownIdentifier {
+    /// Decoded with column details of identifier 0, error if there are none
+    #[default]
+    Default,
+    /// Skipped like excluded packets
+    Skip,
+    Error,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct NativeSettings {
+    #[serde(default)]
     packing: usize,
+    /// Default byte order for all native columns
+    #[serde(default)]
+    endian: Endian,
+    /// Packet settings are only needed by multi native files
+    #[serde(default)]
     packet_header: PacketHeader,
+    #[serde(default)]
     packet_info: PacketInfo,
+    /// Reusable struct definitions, referenced by name from struct columns
+    #[serde(default)]
+    structs: BTreeMap<String, Vec<BufferValue>>,
+    /// Reusable code to label maps, referenced by name from columns
+    #[serde(default)]
+    value_maps: BTreeMap<String, ValueMap>,
+    /// Largest udp packet or fixed size record in bytes
+    /// Larger packets are an error, defaults to 64 KiB
+    max_packet_size: Option<usize>,
+    /// Largest size of a packet after decompression in bytes, defaults to 64 KiB
+    max_decompressed_size: Option<usize>,
+    /// Outputs struct and array columns as dotted names
+    /// e.g. MBP_INFORMATION[0].Quantity
+    #[serde(default)]
+    flatten: bool,
+}
+
+/// Default limit of packet sizes, large enough for any udp packet
+const DEFAULT_MAX_PACKET_SIZE: usize = 64 * 1024;
+
+impl NativeSettings {
+    pub(crate) fn max_packet_size(&self) -> usize {
+        self.max_packet_size.unwrap_or(DEFAULT_MAX_PACKET_SIZE)
+    }
+
+    pub(crate) fn max_decompressed_size(&self) -> usize {
+        self.max_decompressed_size
+            .unwrap_or(DEFAULT_MAX_PACKET_SIZE)
+    }
 }
 
-/// Register adapter mappings here
-fn get_adapter(_type: &Type) -> Box<dyn Readable> {
-    match _type {
-        Type::Json => Box::new(JsonAdapter {}),
-        Type::JsonArray => Box::new(JsonArrayAdapter {}),
-        Type::JsonLines => Box::new(JsonLineAdapter {}),
-        Type::Native => Box::new(NativeAdapter {}),
-        Type::Csv => Box::new(CsvAdapter {}),
-        Type::MultiNative => Box::new(MultiNative {}),
+impl PacketInfo {
+    /// Packets are left out of records by include, exclude or on_unknown_identifier
+    pub(crate) fn skips_packets(&self) -> bool {
+        !self.include.is_empty()
+            || !self.exclude.is_empty()
+            || self.on_unknown_identifier == OnUnknownIdentifier::Skip
     }
 }
 
+/// Where reading starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    /// Index of first record to read
+    FromStart(u64),
+    /// Number of records before end of file
+    /// Formats which scan backwards report error positions from first returned record
+    /// With a filter, last records which match it, found by reading every record
+    FromEnd(u64),
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum Type {
@@ -97,24 +367,78 @@ pub enum Type {
 #[derive(Debug, Default, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum DType {
-    Char, // 1 byte
-    U32, // 4 bytes
-    U64, // 8 bytes
+    Char, // N bytes
+    U8,   // 1 byte
+    I8,   // 1 byte
+    U16,  // 2 bytes
+    #[serde(alias = "i16")]
     Short, // 2 bytes
-    I32, // 4 bytes
-    I64, // 8 bytes
-    F32, // 4 bytes
-    F64, // 8 bytes
+    U32,  // 4 bytes
+    I32,  // 4 bytes
+    U64,  // 8 bytes
+    I64,  // 8 bytes
+    U128, // 16 bytes, decoded as string
+    I128, // 16 bytes, decoded as string
+    F32,  // 4 bytes
+    F64,  // 8 bytes
     Bool, // 1 byte
-    Byte, // N bytes
-    Bit, // N Bits
+    Byte, // 1 byte, unsigned number
+    Bytes, // N bytes, decoded as hex or base64 string
+    Bit,  // N Bits, up to 64, can span bytes, up to 8 are aligned to top of a byte
+    Struct, // Fields of struct definition
     #[default]
     None, // N bytes
 }
 
-#[derive(Debug, Deserialize)]
+impl DType {
+    /// Size in bytes for fixed size dtypes
+    /// None for dtypes which take length from config
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            DType::U8 | DType::I8 | DType::Byte | DType::Bool => Some(1),
+            DType::U16 | DType::Short => Some(2),
+            DType::U32 | DType::I32 | DType::F32 => Some(4),
+            DType::U64 | DType::I64 | DType::F64 => Some(8),
+            DType::U128 | DType::I128 => Some(16),
+            DType::Char | DType::Bytes | DType::Bit | DType::Struct | DType::None => None,
+        }
+    }
+}
+
+/// String encoding of bytes columns
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileCompression {
+    #[default]
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct IndexSettings {
+    /// Defaults to file path with .idx appended
+    path: Option<String>,
+    /// Records between index entries, defaults to 1024
+    stride: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct Config {
-    /// Selected columns to display
+    /// Columns returned in records, every column when empty
+    /// Unselected columns are not decoded
+    /// Names missing from columns of file are an error, json objects have no fixed columns
     pub selected_columns: Vec<String>,
 
     /// column name with size for native file format
@@ -138,30 +462,118 @@ pub struct Config {
     /// Can be used in case column names are not available
     #[serde(default)]
     pub default_columns: Vec<String>,
+
+    /// Compression of whole input file
+    /// Detected from magic bytes or extension by default
+    /// Native files are detected only by extension, packets may start like magic bytes
+    #[serde(default)]
+    pub file_compression: FileCompression,
+
+    /// Sidecar index of record offsets, used to seek to from
+    /// Built on first read after from and rebuilt when file or layout settings change
+    pub index: Option<IndexSettings>,
+
+    /// Only records matching filter are returned, e.g. "Token = 12345 and Price > 100"
+    /// Positions still count every record of file
+    pub filter: Option<Filter>,
 }
 
 impl Reader {
+    /// Source can be a file path, bytes or a Source stream
     pub fn new(
         config_path: String,
-        file_path: String,
+        source: impl Into<Source>,
         _type: Type,
-    ) -> Result<Reader, Box<dyn Error>> {
+    ) -> Result<Reader, ReaderError> {
         // Load config to struct
         let config_file = fs::read_to_string(&config_path)?;
-        let config = serde_json::from_str(&config_file)?;
+        let config = serde_json::from_str(&config_file)
+            .map_err(|e| ReaderError::Config(format!("{config_path}: {e}")))?;
 
-        Ok(Reader {
-            config,
-            file_path,
-            _type,
-        })
+        Ok(Reader::new_with_config(config, source, _type))
     }
 
-    pub fn new_with_config(config: Config, file_path: String, _type: Type) -> Reader {
+    pub fn new_with_config(config: Config, source: impl Into<Source>, _type: Type) -> Reader {
         Reader {
             config,
-            file_path,
+            source: Mutex::new(Some(source.into())),
             _type,
+            decompressors: Decompressors::default(),
+            index_cache: Arc::default(),
+            skipped_packets: Arc::default(),
+        }
+    }
+
+    /// Register adapter mappings here
+    /// Adapters share caches and codecs of reader
+    fn adapter(&self) -> Box<dyn Readable> {
+        let index_cache = self.index_cache.clone();
+
+        match self._type {
+            Type::Json => Box::new(JsonAdapter { index_cache }),
+            Type::JsonArray => Box::new(JsonArrayAdapter { index_cache }),
+            Type::JsonLines => Box::new(JsonLineAdapter { index_cache }),
+            Type::Native => Box::new(NativeAdapter {}),
+            Type::Csv => Box::new(CsvAdapter { index_cache }),
+            Type::MultiNative => Box::new(MultiNative {
+                index_cache,
+                decompressors: self.decompressors.clone(),
+                skipped_packets: self.skipped_packets.clone(),
+            }),
+        }
+    }
+
+    /// Source for next read
+    /// Paths and bytes are reopened, streams are handed out once
+    fn source(&self) -> Result<Source, ReaderError> {
+        let mut source = self.source.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match source.as_ref().and_then(Source::try_clone) {
+            Some(source) => Ok(source),
+            None => source
+                .take()
+                .ok_or_else(|| ReaderError::Config("Stream source was already read".to_string())),
+        }
+    }
+
+    /// Registers a codec for compressed multi native packets
+    /// Packets use it when compression_type is set to name
+    pub fn register_decompressor(&mut self, name: &str, decompressor: impl Decompressor + 'static) {
+        self.decompressors
+            .register(name.to_string(), Arc::new(decompressor));
+    }
+
+    /// Number of multi native packets skipped in last read, by identifier
+    /// Packets are skipped by include and exclude lists, or for unknown identifiers
+    /// Streams set counts once they are dropped, so streams at once do not mix them
+    pub fn skipped_packets(&self) -> HashMap<String, u64> {
+        self.skipped_packets.get()
+    }
+
+    /// Builds sidecar index of file ahead of first read
+    /// Does nothing if index is valid
+    pub fn build_index(&self) -> Result<(), ReaderError> {
+        let Source::Path(file_path) = self.source()? else {
+            return Err(ReaderError::Config(
+                "Index needs a file path source".to_string(),
+            ));
+        };
+
+        if self.config.index.is_none() {
+            return Err(ReaderError::Config("Index is not configured".to_string()));
+        }
+
+        match load_index(
+            self.adapter().as_ref(),
+            &self.index_cache,
+            &file_path,
+            &self.config,
+        )? {
+            Some(_) => Ok(()),
+            None => Err(ReaderError::Config(format!(
+                "{:?} files are not indexed",
+                self._type
+            ))),
         }
     }
 
@@ -169,13 +581,122 @@ impl Reader {
         &self,
         from: Option<u64>,
         len: Option<u64>,
-    ) -> Result<Vec<Map<String, Value>>, Box<dyn Error>> {
+    ) -> Result<Vec<Map<String, Value>>, ReaderError> {
+        self.read_at(Position::FromStart(from.unwrap_or(0)), len)
+    }
+
+    /// Reads at most len records from position
+    /// e.g. Position::FromEnd(100) reads last 100 records
+    pub fn read_at(
+        &self,
+        position: Position,
+        len: Option<u64>,
+    ) -> Result<Vec<Map<String, Value>>, ReaderError> {
         // Get adapter from mapping
-        let adapter = get_adapter(&self._type);
+        let adapter = self.adapter();
 
         let len = len.unwrap_or(u64::MAX);
 
-        adapter.read(&self.file_path, &self.config, from, len)
+        adapter.read(self.source()?, &self.config, position, len)
+    }
+
+    /// Number of records in source, without decoding them
+    /// Records are decoded when a filter is set, to count matching ones
+    /// Bad records are counted without filter, and are an error with one
+    pub fn count(&self) -> Result<u64, ReaderError> {
+        self.count_with(&self.config)
+    }
+
+    /// Counts records with other settings than reader's, e.g. of a query
+    pub(crate) fn count_with(&self, config: &Config) -> Result<u64, ReaderError> {
+        // Get adapter from mapping
+        let adapter = self.adapter();
+
+        match config.filter {
+            // Records are decoded to match them, bad ones are an error
+            Some(_) => adapter
+                .stream_at(self.source()?, config, Position::FromStart(0))?
+                .try_fold(0, |count, record| record.map(|_| count + 1)),
+            None => adapter.count(self.source()?, config),
+        }
+    }
+
+    /// Groups records and computes aggregates in one pass, without keeping records
+    /// Only records matching filter are aggregated
+    pub fn aggregate(
+        &self,
+        aggregation: &Aggregation,
+    ) -> Result<Vec<Map<String, Value>>, ReaderError> {
+        let selected = &self.config.selected_columns;
+
+        if let Some(column) = aggregation.columns().find(|column| {
+            !selected.is_empty() && !selected.iter().any(|selected| selected == column)
+        }) {
+            return Err(ReaderError::Config(format!(
+                "Aggregated column {column} is not in selected_columns"
+            )));
+        }
+
+        aggregation.run(self.iter()?)
+    }
+
+    /// Runs sql select over records, e.g.
+    /// SELECT Token, max(Price) FROM capture WHERE TransactionCode = 7208 GROUP BY Token
+    /// Any table name refers to source, queried columns and where are pushed down to decoding
+    pub fn query(&self, sql: &str) -> Result<Vec<Map<String, Value>>, ReaderError> {
+        let query = Query::parse(sql)?;
+
+        // Query reads with own columns and filter, reader config is left as is
+        let mut config = self.config.clone();
+
+        config.filter = match (config.filter.take(), query.filter.clone()) {
+            (Some(filter), Some(condition)) => Some(filter.and(condition)),
+            (filter, condition) => filter.or(condition),
+        };
+        config.selected_columns =
+            query.selected_columns(&config.selected_columns, config.filter.as_ref())?;
+
+        query.run(self, &config)
+    }
+
+    /// Parses filter expression and sets it on config
+    pub fn set_filter(&mut self, expression: &str) -> Result<(), ReaderError> {
+        self.config.filter = Some(Filter::parse(expression)?);
+
+        Ok(())
+    }
+
+    /// Returns an iterator which decodes records one by one
+    /// Use this instead of read for large files
+    pub fn iter(&self) -> Result<RecordIter<'_>, ReaderError> {
+        self.iter_at(Position::FromStart(0))
+    }
+
+    /// Returns an iterator which decodes records from position one by one
+    pub fn iter_at(&self, position: Position) -> Result<RecordIter<'_>, ReaderError> {
+        // Get adapter from mapping
+        let adapter = self.adapter();
+
+        adapter.stream_at(self.source()?, &self.config, position)
+    }
+
+    /// Decodes records with other settings than reader's, e.g. of a query
+    pub(crate) fn iter_with<'a>(&self, config: &'a Config) -> Result<RecordIter<'a>, ReaderError> {
+        self.adapter()
+            .stream_at(self.source()?, config, Position::FromStart(0))
+    }
+
+    /// Decodes records of a file which is still being written
+    /// Waits for more data at end of file, checking every poll_interval
+    /// Rotated or truncated files are read again from start
+    pub fn follow(&self, poll_interval: Duration) -> Result<RecordIter<'_>, ReaderError> {
+        let Source::Path(file_path) = self.source()? else {
+            return Err(ReaderError::Config(
+                "Follow needs a file path source".to_string(),
+            ));
+        };
+
+        Ok(Box::new(Follow::new(self, file_path, poll_interval)?))
     }
 
     pub fn get_columns(config: Config, _type: Type) -> Map<String, Value> {
@@ -207,13 +728,12 @@ impl Reader {
                 .column_details
                 .values()
                 .for_each(|c| {
-                    c.columns.iter().for_each(|c| {
+                    expand_switches(&c.columns).into_iter().for_each(|c| {
                         if c.dtype == DType::None {
                             return;
                         }
 
-                        if columns.get(&c.name).is_none() || columns.get(&c.name).unwrap() == false
-                        {
+                        if columns.get(&c.name).is_none_or(|v| v == false) {
                             columns.insert(c.name.to_string(), Value::Bool(c.default));
                         }
                     });
@@ -225,16 +745,368 @@ impl Reader {
 }
 
 pub trait Readable: Send + Sync + Debug {
-    /// read method should read from file_path
+    /// stream method should open source and return a lazy iterator of records
     /// It should parse according to it's implementation and config file
-    /// If from is set, it should read from that index
-    /// If to is set, it should read till that index excluding it
-    /// Returns (columns, values)
+    /// Records before from should be skipped as cheaply as the format allows
+    fn stream<'a>(
+        &self,
+        source: Source,
+        config: &'a Config,
+        from: u64,
+    ) -> Result<RecordIter<'a>, ReaderError>;
+
+    /// scan method should walk all records without decoding them
+    /// and report where each starts, these are saved to index files
+    /// Formats which can seek without an index return None
+    fn scan<'a>(
+        &self,
+        _source: Source,
+        _config: &'a Config,
+    ) -> Result<Option<CheckpointIter<'a>>, ReaderError> {
+        Ok(None)
+    }
+
+    /// stream_last method should return a lazy iterator of last n records
+    /// Default walks all records once and keeps last n of them
+    fn stream_last<'a>(
+        &self,
+        source: Source,
+        config: &'a Config,
+        n: u64,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        Ok(last_records(self.stream(source, config, 0)?, n))
+    }
+
+    /// count method should return number of records in source
+    /// Default decodes every record, adapters override it with cheaper ways
+    fn count(&self, source: Source, config: &Config) -> Result<u64, ReaderError> {
+        count_records(self.stream(source, config, 0)?)
+    }
+
+    fn stream_at<'a>(
+        &self,
+        source: Source,
+        config: &'a Config,
+        position: Position,
+    ) -> Result<RecordIter<'a>, ReaderError> {
+        let records = match position {
+            Position::FromStart(from) => self.stream(source, config, from)?,
+            // Last matching records are only known after a pass over every record
+            Position::FromEnd(n) if config.filter.is_some() => {
+                let records = filter_records(self.stream(source, config, 0)?, config);
+
+                return Ok(last_records(records, n));
+            }
+            Position::FromEnd(n) => self.stream_last(source, config, n)?,
+        };
+
+        Ok(filter_records(records, config))
+    }
+
+    /// read method should read from source
+    /// Starts at position and reads at most len records
     fn read(
         &self,
-        file_path: &String,
+        source: Source,
         config: &Config,
-        from: Option<u64>,
+        position: Position,
         len: u64,
-    ) -> Result<Vec<Map<String, Value>>, Box<dyn Error>>;
+    ) -> Result<Vec<Map<String, Value>>, ReaderError> {
+        let len = usize::try_from(len).unwrap_or(usize::MAX);
+
+        self.stream_at(source, config, position)?
+            .take(len)
+            .collect()
+    }
+}
+
+/// Counts records, including bad ones which are reported when read
+pub(crate) fn count_records<T>(
+    records: impl Iterator<Item = Result<T, ReaderError>>,
+) -> Result<u64, ReaderError> {
+    let mut count = 0;
+
+    for record in records {
+        if let Err(ReaderError::Io(e)) = record {
+            return Err(ReaderError::Io(e));
+        }
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Drops records rejected by filter of config
+/// Columns which are only decoded for filter are removed after
+pub(crate) fn filter_records<'a>(records: RecordIter<'a>, config: &'a Config) -> RecordIter<'a> {
+    let Some(filter) = &config.filter else {
+        return records;
+    };
+
+    let projection = Projection::new(config);
+
+    Box::new(records.filter_map(move |record| match record {
+        Ok(mut record) => {
+            if !filter.matches(&record) {
+                return None;
+            }
+
+            if let Some(projection) = &projection {
+                projection.trim(&mut record);
+            }
+
+            Some(Ok(record))
+        }
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+/// Keeps last n of records
+pub(crate) fn last_records(records: RecordIter<'_>, n: u64) -> RecordIter<'_> {
+    let mut last = VecDeque::new();
+
+    if n > 0 {
+        for record in records {
+            if last.len() as u64 == n {
+                last.pop_front();
+            }
+
+            last.push_back(record);
+        }
+    }
+
+    Box::new(last.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const LINES: &str = "{\"a\": 1}\n{\"a\": 2}\n{\"a\": 3}\n{\"a\": 4}\n";
+
+    fn reader(source: impl Into<Source>, _type: Type) -> Reader {
+        let config: Config = serde_json::from_value(json!({"selected_columns": []})).unwrap();
+
+        Reader::new_with_config(config, source, _type)
+    }
+
+    fn values(records: Vec<Map<String, Value>>) -> Vec<Value> {
+        records
+            .into_iter()
+            .map(|record| record["a"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn iterates_records_one_by_one() {
+        let reader = reader(LINES.as_bytes().to_vec(), Type::JsonLines);
+        let mut records = reader.iter().unwrap();
+
+        assert_eq!(records.next().unwrap().unwrap()["a"], json!(1));
+
+        let rest: Vec<_> = records.collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(values(rest), [json!(2), json!(3), json!(4)]);
+        assert_eq!(
+            values(reader.read(None, None).unwrap()),
+            values(reader.iter().unwrap().collect::<Result<_, _>>().unwrap())
+        );
+    }
+
+    #[test]
+    fn reads_pages_of_records() {
+        let reader = reader(LINES.as_bytes().to_vec(), Type::JsonLines);
+
+        assert_eq!(
+            values(reader.read(Some(1), Some(2)).unwrap()),
+            [json!(2), json!(3)]
+        );
+        assert_eq!(
+            values(
+                reader
+                    .iter_at(Position::FromStart(3))
+                    .unwrap()
+                    .collect::<Result<_, _>>()
+                    .unwrap()
+            ),
+            [json!(4)]
+        );
+        assert!(reader.read(Some(10), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_records_do_not_end_iteration() {
+        let reader = reader(
+            "{\"a\": 1}\n{\"a\": \n{\"a\": 3}\n".as_bytes().to_vec(),
+            Type::JsonLines,
+        );
+
+        let records: Vec<_> = reader.iter().unwrap().collect();
+
+        assert_eq!(records.len(), 3);
+        assert!(records[1].is_err());
+        assert_eq!(records[2].as_ref().unwrap()["a"], json!(3));
+    }
+
+    fn last(reader: &Reader, n: u64) -> Vec<Value> {
+        values(reader.read_at(Position::FromEnd(n), None).unwrap())
+    }
+
+    #[test]
+    fn reads_last_records_of_every_format() {
+        let sources = [
+            (LINES.as_bytes().to_vec(), Type::JsonLines),
+            // Last line without newline
+            (LINES.trim_end().as_bytes().to_vec(), Type::JsonLines),
+            (b"a\n1\n2\n3\n4\n".to_vec(), Type::Csv),
+            (
+                b"[{\"a\": 1}, {\"a\": 2}, {\"a\": 3}, {\"a\": 4}]".to_vec(),
+                Type::Json,
+            ),
+        ];
+
+        for (source, _type) in sources {
+            let reader = reader(source, _type);
+
+            assert_eq!(
+                last(&reader, 2)
+                    .iter()
+                    .map(|a| a.to_string().trim_matches('"').to_string())
+                    .collect::<Vec<_>>(),
+                ["3", "4"],
+                "{:?}",
+                reader._type
+            );
+            assert_eq!(last(&reader, 10).len(), 4);
+            assert!(last(&reader, 0).is_empty());
+        }
+    }
+
+    #[test]
+    fn reads_last_packets_of_native_files_and_streams() {
+        let config = || -> Config {
+            serde_json::from_value(json!({
+                "selected_columns": [],
+                "native_columns": [{"name": "a", "dtype": "u8", "length": 1}],
+            }))
+            .unwrap()
+        };
+
+        let file = Reader::new_with_config(config(), vec![1, 2, 3, 4], Type::Native);
+        let stream = Reader::new_with_config(
+            config(),
+            Source::stream(std::io::Cursor::new(vec![1, 2, 3, 4])),
+            Type::Native,
+        );
+
+        assert_eq!(last(&file, 3), [json!(2), json!(3), json!(4)]);
+        assert_eq!(last(&stream, 3), [json!(2), json!(3), json!(4)]);
+    }
+
+    #[test]
+    fn reads_last_matching_records_with_filter() {
+        let mut reader = reader(LINES.as_bytes().to_vec(), Type::JsonLines);
+        reader.set_filter("a != 4").unwrap();
+
+        assert_eq!(last(&reader, 2), [json!(2), json!(3)]);
+    }
+
+    #[test]
+    fn counts_records_of_every_format() {
+        let sources = [
+            (LINES.as_bytes().to_vec(), Type::JsonLines),
+            (b"a\n1\n2\n3\n4\n".to_vec(), Type::Csv),
+            (b"[{\"a\": 1}, {}, {}, {}]".to_vec(), Type::Json),
+            (b"[[\"a\"], [1], [2], [3], [4]]".to_vec(), Type::JsonArray),
+        ];
+
+        for (source, _type) in sources {
+            let reader = reader(source, _type);
+
+            assert_eq!(reader.count().unwrap(), 4, "{:?}", reader._type);
+        }
+    }
+
+    #[test]
+    fn counts_bad_records_without_decoding_them() {
+        let reader = reader(
+            "{\"a\": 1}\n{\"a\": \n{\"a\": 3}\n".as_bytes().to_vec(),
+            Type::JsonLines,
+        );
+
+        assert_eq!(reader.count().unwrap(), 3);
+    }
+
+    #[test]
+    fn skips_blank_lines_when_counting_and_streaming() {
+        let reader = reader(
+            "{\"a\": 1}\n\n{\"a\": 2}\r\n \n{\"a\": 3}\n\n"
+                .as_bytes()
+                .to_vec(),
+            Type::JsonLines,
+        );
+
+        let records: Vec<_> = reader.iter().unwrap().collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(values(records), [json!(1), json!(2), json!(3)]);
+        assert_eq!(reader.count().unwrap(), 3);
+        assert_eq!(
+            values(reader.read(Some(1), None).unwrap()),
+            [json!(2), json!(3)]
+        );
+        assert_eq!(last(&reader, 2), [json!(2), json!(3)]);
+    }
+
+    #[test]
+    fn counts_only_matching_records_with_filter() {
+        let mut reader = reader(LINES.as_bytes().to_vec(), Type::JsonLines);
+        reader.set_filter("a >= 3").unwrap();
+
+        assert_eq!(reader.count().unwrap(), 2);
+    }
+
+    #[test]
+    fn returns_only_selected_columns() {
+        let sources = [
+            (
+                b"{\"a\": 1, \"b\": 2, \"c\": 3}\n".to_vec(),
+                Type::JsonLines,
+            ),
+            (b"a,b,c\n1,2,3\n".to_vec(), Type::Csv),
+            (b"[{\"a\": 1, \"b\": 2, \"c\": 3}]".to_vec(), Type::Json),
+            (
+                b"[[\"a\", \"b\", \"c\"], [1, 2, 3]]".to_vec(),
+                Type::JsonArray,
+            ),
+        ];
+
+        for (source, _type) in sources {
+            let config: Config =
+                serde_json::from_value(json!({"selected_columns": ["c", "a"]})).unwrap();
+            let mut reader = Reader::new_with_config(config, source, _type);
+
+            // Columns only needed by filter are dropped after it
+            reader.set_filter("b > 1").unwrap();
+
+            let records = reader.read(None, None).unwrap();
+            let columns: Vec<_> = records[0].keys().map(String::as_str).collect();
+
+            assert_eq!(columns.len(), 2, "{:?}", reader._type);
+            assert!(columns.contains(&"a") && columns.contains(&"c"));
+        }
+    }
+
+    #[test]
+    fn bad_records_are_an_error_when_counting_with_filter() {
+        let mut reader = reader(
+            "{\"a\": 1}\n{\"a\": \n{\"a\": 3}\n".as_bytes().to_vec(),
+            Type::JsonLines,
+        );
+        reader.set_filter("a >= 1").unwrap();
+
+        assert!(reader.count().is_err());
+    }
 }
@@ -1,8 +1,122 @@
-use std::{time::Instant, u64};
+use std::{env, fs, process, time::Instant};
 
-use reader::Reader;
+use reader::{generate_config, HeaderOptions, Reader, Type};
+
+const GENERATE_USAGE: &str = "Usage: main generate-config <header.h> [--packet CODE=STRUCT]... \
+[--pack N] [--skip-bytes N] [--flatten] [--base config.json] [--out config.json]";
+
+const QUERY_USAGE: &str =
+    "Usage: main query <config.json> <json|json_array|json_lines|csv|native|multi_native> \
+<file> <sql>";
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("generate-config") => {
+            if let Err(e) = generate(&args[1..]) {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+        Some("query") => {
+            if let Err(e) = query(&args[1..]) {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        }
+        _ => bench(),
+    }
+}
+
+/// Writes config generated from c header to --out or stdout
+fn generate(args: &[String]) -> Result<(), String> {
+    let mut header = None;
+    let mut out = None;
+    let mut options = HeaderOptions::default();
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {arg}\n{GENERATE_USAGE}"))
+        };
+
+        match arg.as_str() {
+            "--packet" => {
+                let packet = value()?;
+                let (code, name) = packet
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected CODE=STRUCT, found {packet}"))?;
+                let code = code
+                    .parse()
+                    .map_err(|_| format!("Invalid packet code {code}"))?;
+
+                options.packets.push((code, name.to_string()));
+            }
+            "--pack" => {
+                let pack = value()?;
+
+                options.packing = Some(pack.parse().map_err(|_| format!("Invalid pack {pack}"))?);
+            }
+            "--skip-bytes" => {
+                let skip = value()?;
+
+                options.skip_bytes = skip
+                    .parse()
+                    .map_err(|_| format!("Invalid skip bytes {skip}"))?;
+            }
+            "--flatten" => options.flatten = true,
+            "--base" => {
+                let path = value()?;
+                let base = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+
+                options.base =
+                    Some(serde_json::from_str(&base).map_err(|e| format!("{path}: {e}"))?);
+            }
+            "--out" => out = Some(value()?.clone()),
+            _ if header.is_none() && !arg.starts_with("--") => header = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument {arg}\n{GENERATE_USAGE}")),
+        }
+    }
+
+    let header = header.ok_or(GENERATE_USAGE)?;
+    let source = fs::read_to_string(&header).map_err(|e| format!("{header}: {e}"))?;
+
+    let config = generate_config(&source, &options).map_err(|e| format!("{header}: {e}"))?;
+    let json = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+
+    match out {
+        Some(path) => fs::write(&path, json).map_err(|e| format!("{path}: {e}")),
+        None => {
+            println!("{json}");
+
+            Ok(())
+        }
+    }
+}
+
+/// Prints rows of sql query over file as json lines
+fn query(args: &[String]) -> Result<(), String> {
+    let [config_path, _type, file_path, sql] = args else {
+        return Err(QUERY_USAGE.to_string());
+    };
+
+    let _type: Type = serde_json::from_value(_type.as_str().into())
+        .map_err(|_| format!("Unknown type {_type}\n{QUERY_USAGE}"))?;
+
+    let reader =
+        Reader::new(config_path.clone(), file_path.clone(), _type).map_err(|e| e.to_string())?;
+
+    for row in reader.query(sql).map_err(|e| e.to_string())? {
+        println!("{}", serde_json::Value::Object(row));
+    }
+
+    Ok(())
+}
+
+fn bench() {
     // Assign reader adapters here
     let reader = Reader::new(
         "/home/appadmin/Work/generic_reader/config_eq.json".to_string(),
@@ -13,7 +127,7 @@ fn main() {
 
     let start = Instant::now();
 
-let data = reader.read(None, None).unwrap();
+    let data = reader.read(None, None).unwrap();
 
     println!(
         "{:?} for {:?} values\n {:?} per iter",
@@ -22,7 +136,7 @@ let data = reader.read(None, None).unwrap();
         start.elapsed() / data.len().max(1) as u32
     );
 
-    if data.len() > 0 {
+    if !data.is_empty() {
         println!(
             "Columns: \n\t{:?}",
             data[0].keys().collect::<Vec<&String>>()
@@ -31,7 +145,7 @@ let data = reader.read(None, None).unwrap();
 
     println!("Data: ");
     for i in data {
-        for (k, v) in i {
+        for (_k, _v) in i {
             // println!("\t{k}: {v}");
         }
     }
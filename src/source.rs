@@ -0,0 +1,131 @@
+use std::{
+    fmt,
+    io::{Read, Seek},
+    sync::Arc,
+};
+
+/// Readers which can also seek, e.g. files or cursors
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Input of a reader
+/// Formats which skip records by seeking read through plain streams instead
+pub enum Source {
+    /// File opened on every read
+    Path(String),
+    /// In memory buffer, can be read any number of times
+    Bytes(Arc<[u8]>),
+    /// Stream which can be read once, e.g. stdin or a socket
+    Stream(Box<dyn Read + Send>),
+    /// Seekable stream which can be read once, e.g. a file inside an archive
+    Seekable(Box<dyn ReadSeek + Send>),
+}
+
+impl Source {
+    pub fn stream(reader: impl Read + Send + 'static) -> Source {
+        Source::Stream(Box::new(reader))
+    }
+
+    pub fn seekable(reader: impl Read + Seek + Send + 'static) -> Source {
+        Source::Seekable(Box::new(reader))
+    }
+
+    /// Copy of source for another read
+    /// None for streams, which can only be read once
+    pub(crate) fn try_clone(&self) -> Option<Source> {
+        match self {
+            Source::Path(path) => Some(Source::Path(path.clone())),
+            Source::Bytes(bytes) => Some(Source::Bytes(bytes.clone())),
+            Source::Stream(_) | Source::Seekable(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            Source::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Source::Stream(_) => f.write_str("Stream"),
+            Source::Seekable(_) => f.write_str("Seekable"),
+        }
+    }
+}
+
+impl From<String> for Source {
+    fn from(path: String) -> Source {
+        Source::Path(path)
+    }
+}
+
+impl From<&str> for Source {
+    fn from(path: &str) -> Source {
+        Source::Path(path.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Source {
+    fn from(bytes: Vec<u8>) -> Source {
+        Source::Bytes(bytes.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io::Cursor, process};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Config, Reader, ReaderError, Type};
+
+    const CSV: &str = "a,b\n1,x\n2,y\n";
+
+    fn reader(source: Source) -> Reader {
+        let config: Config = serde_json::from_value(json!({"selected_columns": []})).unwrap();
+
+        Reader::new_with_config(config, source, Type::Csv)
+    }
+
+    #[test]
+    fn paths_and_bytes_can_be_read_again() {
+        let path = env::temp_dir().join(format!("reader-source-{}.csv", process::id()));
+        fs::write(&path, CSV).unwrap();
+
+        for source in [
+            Source::from(path.to_string_lossy().into_owned()),
+            Source::from(CSV.as_bytes().to_vec()),
+        ] {
+            let reader = reader(source);
+
+            assert_eq!(reader.read(None, None).unwrap().len(), 2);
+            assert_eq!(reader.read(Some(1), None).unwrap()[0]["b"], json!("y"));
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn streams_are_read_once() {
+        for source in [
+            Source::stream(Cursor::new(CSV)),
+            Source::seekable(Cursor::new(CSV)),
+        ] {
+            let reader = reader(source);
+
+            assert_eq!(reader.read(Some(1), None).unwrap()[0]["a"], json!("2"));
+            assert!(matches!(
+                reader.read(None, None),
+                Err(ReaderError::Config(message)) if message == "Stream source was already read"
+            ));
+        }
+    }
+
+    #[test]
+    fn missing_files_are_io_errors() {
+        let reader = reader(Source::from("/nonexistent/capture.csv"));
+
+        assert!(matches!(reader.read(None, None), Err(ReaderError::Io(_))));
+    }
+}
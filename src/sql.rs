@@ -0,0 +1,930 @@
+use std::{cmp::Ordering, str::FromStr};
+
+use serde_json::{Map, Number, Value};
+use sqlparser::{
+    ast::{
+        self, BinaryOperator, Distinct, DuplicateTreatment, FunctionArg, FunctionArgExpr,
+        FunctionArguments, GroupByExpr, SelectItem, SetExpr, Statement, TableFactor, UnaryOperator,
+    },
+    dialect::GenericDialect,
+    parser::Parser,
+};
+
+use crate::{
+    aggregate::order,
+    filter::{Expr, Op},
+    Aggregate, AggregateFunction, Aggregation, Config, Filter, Reader, ReaderError, TimeBucket,
+    Type,
+};
+
+/// Select statement over records of a reader
+/// e.g. SELECT Token, max(Price) FROM capture WHERE TransactionCode = 7208 GROUP BY Token
+///
+/// Supports columns, count, sum, min, max, avg, first, last, count(distinct ..),
+/// time_bucket(width, column), where, group by, having, order by, limit and offset
+pub(crate) struct Query {
+    /// Output names with key of working row they are taken from, records as is for *
+    outputs: Vec<(String, String)>,
+    wildcard: bool,
+    pub(crate) filter: Option<Filter>,
+    /// Records are grouped when query has aggregates, group by or distinct
+    aggregation: Option<Aggregation>,
+    having: Option<Filter>,
+    /// Keys of working row, true when descending
+    order_by: Vec<(String, bool)>,
+    limit: Option<usize>,
+    offset: usize,
+    /// Record columns query reads, besides ones of filter
+    columns: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Clause {
+    Where,
+    Having,
+}
+
+/// Collects groups, aggregates and columns while statement is translated
+/// Working rows of grouped queries have group columns, bucket column and aggregates as #i
+#[derive(Default)]
+struct Builder {
+    /// Table name and alias, which may qualify columns
+    tables: Vec<String>,
+    grouped: bool,
+    group_by: Vec<String>,
+    bucket: Option<TimeBucket>,
+    aggregates: Vec<Aggregate>,
+    outputs: Vec<(String, String)>,
+    columns: Vec<String>,
+}
+
+fn unsupported(message: impl std::fmt::Display) -> ReaderError {
+    ReaderError::Config(format!("Unsupported query: {message}"))
+}
+
+impl Query {
+    pub(crate) fn parse(sql: &str) -> Result<Query, ReaderError> {
+        let statements = Parser::parse_sql(&GenericDialect {}, sql)
+            .map_err(|e| ReaderError::Config(format!("Invalid query: {e}")))?;
+
+        let [Statement::Query(query)] = statements.as_slice() else {
+            return Err(unsupported("expected a single SELECT statement"));
+        };
+
+        if query.with.is_some() {
+            return Err(unsupported("WITH"));
+        }
+
+        let SetExpr::Select(select) = query.body.as_ref() else {
+            return Err(unsupported(
+                "expected SELECT, found set operation or VALUES",
+            ));
+        };
+
+        let mut builder = Builder::default();
+
+        match select.from.as_slice() {
+            [table] if table.joins.is_empty() => match &table.relation {
+                TableFactor::Table { name, alias, .. } => {
+                    builder
+                        .tables
+                        .extend(name.0.last().map(|name| name.value.clone()));
+                    builder
+                        .tables
+                        .extend(alias.as_ref().map(|alias| alias.name.value.clone()));
+                }
+                relation => return Err(unsupported(format!("FROM {relation}"))),
+            },
+            [] => return Err(unsupported("missing FROM")),
+            _ => return Err(unsupported("joins")),
+        }
+
+        let filter = select
+            .selection
+            .as_ref()
+            .map(|selection| builder.condition(selection, Clause::Where))
+            .transpose()?
+            .map(Filter);
+
+        let group_by = match &select.group_by {
+            GroupByExpr::Expressions(exprs, modifiers) if modifiers.is_empty() => exprs,
+            group_by => return Err(unsupported(group_by)),
+        };
+
+        builder.grouped = !group_by.is_empty()
+            || select.distinct.is_some()
+            || select.having.is_some()
+            || select.projection.iter().any(|item| match item {
+                SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                    is_aggregate(expr)
+                }
+                _ => false,
+            });
+
+        for expr in group_by {
+            builder.group(expr)?;
+        }
+
+        match &select.distinct {
+            Some(Distinct::Distinct) => {
+                for item in &select.projection {
+                    match item {
+                        SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. }
+                            if !is_aggregate(expr) =>
+                        {
+                            builder.group(expr)?
+                        }
+                        item => return Err(unsupported(format!("DISTINCT with {item}"))),
+                    }
+                }
+            }
+            Some(distinct) => return Err(unsupported(distinct)),
+            None => {}
+        }
+
+        let mut wildcard = false;
+
+        for item in &select.projection {
+            match item {
+                SelectItem::Wildcard(_) if !builder.grouped && select.projection.len() == 1 => {
+                    wildcard = true
+                }
+                SelectItem::UnnamedExpr(expr) => {
+                    let key = builder.key(expr)?;
+                    let name = match column_name(expr, &builder.tables) {
+                        Some(column) => column,
+                        None => expr.to_string(),
+                    };
+
+                    builder.outputs.push((name, key));
+                }
+                SelectItem::ExprWithAlias { expr, alias } => {
+                    let key = builder.key(expr)?;
+
+                    builder.outputs.push((alias.value.clone(), key));
+                }
+                item => {
+                    return Err(unsupported(format!(
+                        "{item}, * can only be selected alone without grouping"
+                    )))
+                }
+            }
+        }
+
+        let having = select
+            .having
+            .as_ref()
+            .map(|having| builder.condition(having, Clause::Having))
+            .transpose()?
+            .map(Filter);
+
+        let order_by = match &query.order_by {
+            Some(order_by) => order_by
+                .exprs
+                .iter()
+                .map(|order| {
+                    let key = builder.order_key(&order.expr)?;
+
+                    Ok((key, order.asc == Some(false)))
+                })
+                .collect::<Result<_, ReaderError>>()?,
+            None => Vec::new(),
+        };
+
+        let limit = query.limit.as_ref().map(row_count).transpose()?;
+        let offset = query
+            .offset
+            .as_ref()
+            .map(|offset| row_count(&offset.value))
+            .transpose()?
+            .unwrap_or(0);
+
+        let aggregation = builder.grouped.then_some(Aggregation {
+            group_by: builder.group_by,
+            bucket: builder.bucket,
+            aggregates: builder.aggregates,
+        });
+
+        Ok(Query {
+            outputs: builder.outputs,
+            wildcard,
+            filter,
+            aggregation,
+            having,
+            order_by,
+            limit,
+            offset,
+            columns: builder.columns,
+        })
+    }
+
+    /// Columns reader decodes for query, within ones already selected
+    /// Only filter columns are decoded when query reads none, e.g. count(*)
+    pub(crate) fn selected_columns(
+        &self,
+        selected: &[String],
+        filter: Option<&Filter>,
+    ) -> Result<Vec<String>, ReaderError> {
+        if self.wildcard {
+            return Ok(selected.to_vec());
+        }
+
+        if let Some(column) = self
+            .columns
+            .iter()
+            .find(|column| !selected.is_empty() && !selected.contains(column))
+        {
+            return Err(ReaderError::Config(format!(
+                "Queried column {column} is not in selected_columns"
+            )));
+        }
+
+        Ok(match (self.columns.is_empty(), filter) {
+            (false, _) => self.columns.clone(),
+            (true, Some(filter)) => filter.columns().into_iter().map(String::from).collect(),
+            (true, None) => selected.to_vec(),
+        })
+    }
+
+    /// Runs query over records of reader, config must have selected columns and filter of query
+    pub(crate) fn run(
+        &self,
+        reader: &Reader,
+        config: &Config,
+    ) -> Result<Vec<Map<String, Value>>, ReaderError> {
+        let limit = self.limit.unwrap_or(usize::MAX);
+
+        let mut rows = match &self.aggregation {
+            // Rows are streamed when they do not need sorting
+            None if self.order_by.is_empty() => {
+                return reader
+                    .iter_with(config)?
+                    .skip(self.offset)
+                    .take(limit)
+                    .map(|record| record.map(|record| self.output(record)))
+                    .collect();
+            }
+            None => reader.iter_with(config)?.collect::<Result<Vec<_>, _>>()?,
+            // Counts of multi native files include skipped packets, rows do not
+            Some(aggregation)
+                if counts_only(aggregation)
+                    && !(reader._type == Type::MultiNative
+                        && config.native.packet_info.skips_packets()) =>
+            {
+                let count = reader.count_with(config)?;
+
+                vec![aggregation
+                    .aggregates
+                    .iter()
+                    .map(|aggregate| (aggregate.name.clone(), Value::from(count)))
+                    .collect()]
+            }
+            Some(aggregation) => aggregation.run(reader.iter_with(config)?)?,
+        };
+
+        if let Some(having) = &self.having {
+            rows.retain(|row| having.matches(row));
+        }
+
+        if !self.order_by.is_empty() {
+            rows.sort_by(|a, b| {
+                self.order_by
+                    .iter()
+                    .map(|(key, descending)| {
+                        let ordering = sort_order(a.get(key), b.get(key));
+
+                        match descending {
+                            true => ordering.reverse(),
+                            false => ordering,
+                        }
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            });
+        }
+
+        Ok(rows
+            .into_iter()
+            .skip(self.offset)
+            .take(limit)
+            .map(|row| self.output(row))
+            .collect())
+    }
+
+    /// Selected columns of working row under their output names
+    fn output(&self, row: Map<String, Value>) -> Map<String, Value> {
+        if self.wildcard {
+            return row;
+        }
+
+        self.outputs
+            .iter()
+            .map(|(name, key)| (name.clone(), row.get(key).cloned().unwrap_or(Value::Null)))
+            .collect()
+    }
+}
+
+impl Builder {
+    fn use_column(&mut self, column: &str) {
+        if !self.columns.iter().any(|used| used == column) {
+            self.columns.push(column.to_string());
+        }
+    }
+
+    fn group(&mut self, expr: &ast::Expr) -> Result<(), ReaderError> {
+        if let Some(column) = column_name(expr, &self.tables) {
+            self.use_column(&column);
+
+            if !self.group_by.contains(&column) {
+                self.group_by.push(column);
+            }
+
+            return Ok(());
+        }
+
+        match expr {
+            ast::Expr::Function(function) if function_name(function) == "time_bucket" => {
+                let bucket = self.time_bucket(function)?;
+
+                match &self.bucket {
+                    Some(grouped)
+                        if grouped.column != bucket.column || grouped.width != bucket.width =>
+                    {
+                        Err(unsupported("grouping by more than one time_bucket"))
+                    }
+                    _ => {
+                        self.use_column(&bucket.column);
+                        self.bucket = Some(bucket);
+
+                        Ok(())
+                    }
+                }
+            }
+            expr => Err(unsupported(format!("GROUP BY {expr}"))),
+        }
+    }
+
+    /// Key of working row which has value of expression
+    fn key(&mut self, expr: &ast::Expr) -> Result<String, ReaderError> {
+        if let Some(column) = column_name(expr, &self.tables) {
+            if self.grouped && !self.group_by.contains(&column) {
+                return Err(unsupported(format!(
+                    "column {column} must be in GROUP BY or used in an aggregate"
+                )));
+            }
+
+            self.use_column(&column);
+
+            return Ok(column);
+        }
+
+        let ast::Expr::Function(function) = expr else {
+            return Err(unsupported(format!(
+                "{expr}, only columns, aggregates and time_bucket can be selected"
+            )));
+        };
+
+        if function_name(function) == "time_bucket" {
+            let bucket = self.time_bucket(function)?;
+
+            return match &self.bucket {
+                Some(grouped)
+                    if grouped.column == bucket.column && grouped.width == bucket.width =>
+                {
+                    Ok(bucket.column)
+                }
+                _ => Err(unsupported(format!("{expr} must be in GROUP BY"))),
+            };
+        }
+
+        if !self.grouped {
+            return Err(unsupported(format!("{expr}, aggregates need GROUP BY")));
+        }
+
+        let aggregate = self.aggregate(function)?;
+
+        self.aggregates
+            .iter()
+            .position(|existing| {
+                existing.function == aggregate.function && existing.column == aggregate.column
+            })
+            .map(|i| Ok(format!("#{i}")))
+            .unwrap_or_else(|| {
+                if let Some(column) = &aggregate.column {
+                    self.use_column(column);
+                }
+
+                let key = format!("#{}", self.aggregates.len());
+                self.aggregates.push(aggregate.named(&key));
+
+                Ok(key)
+            })
+    }
+
+    /// Key of order by expression, which may be an output name or position
+    fn order_key(&mut self, expr: &ast::Expr) -> Result<String, ReaderError> {
+        if let ast::Expr::Value(ast::Value::Number(position, _)) = expr {
+            return position
+                .parse::<usize>()
+                .ok()
+                .and_then(|position| self.outputs.get(position.checked_sub(1)?))
+                .map(|(_, key)| key.clone())
+                .ok_or_else(|| unsupported(format!("ORDER BY {position}, no such column")));
+        }
+
+        self.alias(expr).map_or_else(|| self.key(expr), Ok)
+    }
+
+    /// Key of output named by identifier
+    fn alias(&self, expr: &ast::Expr) -> Option<String> {
+        let ast::Expr::Identifier(ident) = expr else {
+            return None;
+        };
+
+        self.outputs
+            .iter()
+            .find(|(name, _)| *name == ident.value)
+            .map(|(_, key)| key.clone())
+    }
+
+    fn aggregate(&self, function: &ast::Function) -> Result<Aggregate, ReaderError> {
+        let (args, distinct) = arguments(function)?;
+
+        let function_type = match (function_name(function).as_str(), distinct) {
+            ("count", true) => AggregateFunction::DistinctCount,
+            ("count", false) => AggregateFunction::Count,
+            (_, true) => return Err(unsupported(format!("DISTINCT in {function}"))),
+            ("sum", _) => AggregateFunction::Sum,
+            ("min", _) => AggregateFunction::Min,
+            ("max", _) => AggregateFunction::Max,
+            ("avg" | "mean", _) => AggregateFunction::Mean,
+            ("first" | "first_value", _) => AggregateFunction::First,
+            ("last" | "last_value", _) => AggregateFunction::Last,
+            _ => return Err(unsupported(format!("function {}", function.name))),
+        };
+
+        let column = match args.as_slice() {
+            [FunctionArgExpr::Wildcard] if function_type == AggregateFunction::Count => None,
+            [FunctionArgExpr::Expr(expr)] => match column_name(expr, &self.tables) {
+                Some(column) => Some(column),
+                None => return Err(unsupported(format!("{function}, expected a column"))),
+            },
+            _ => return Err(unsupported(format!("{function}, expected one column"))),
+        };
+
+        Ok(Aggregate::new(function_type, column.as_deref()))
+    }
+
+    /// time_bucket(width, column), width is seconds for datetimes
+    fn time_bucket(&self, function: &ast::Function) -> Result<TimeBucket, ReaderError> {
+        let (args, _) = arguments(function)?;
+
+        let [FunctionArgExpr::Expr(width), FunctionArgExpr::Expr(column)] = args.as_slice() else {
+            return Err(unsupported(format!(
+                "{function}, expected time_bucket(width, column)"
+            )));
+        };
+
+        let width = match literal(width) {
+            Some(Value::Number(width)) => width.as_u64().filter(|width| *width > 0),
+            _ => None,
+        };
+
+        match (width, column_name(column, &self.tables)) {
+            (Some(width), Some(column)) => Ok(TimeBucket { column, width }),
+            _ => Err(unsupported(format!(
+                "{function}, expected positive integer width and a column"
+            ))),
+        }
+    }
+
+    /// Translates condition to filter, having refers to keys of working row
+    fn condition(&mut self, expr: &ast::Expr, clause: Clause) -> Result<Expr, ReaderError> {
+        Ok(match expr {
+            ast::Expr::Nested(expr) => self.condition(expr, clause)?,
+            ast::Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => Expr::And(
+                Box::new(self.condition(left, clause)?),
+                Box::new(self.condition(right, clause)?),
+            ),
+            ast::Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Or,
+                right,
+            } => Expr::Or(
+                Box::new(self.condition(left, clause)?),
+                Box::new(self.condition(right, clause)?),
+            ),
+            ast::Expr::UnaryOp {
+                op: UnaryOperator::Not,
+                expr,
+            } => Expr::Not(Box::new(self.condition(expr, clause)?)),
+            ast::Expr::BinaryOp { left, op, right } => {
+                let op = match op {
+                    BinaryOperator::Eq => Op::Eq,
+                    BinaryOperator::NotEq => Op::Ne,
+                    BinaryOperator::Lt => Op::Lt,
+                    BinaryOperator::LtEq => Op::Le,
+                    BinaryOperator::Gt => Op::Gt,
+                    BinaryOperator::GtEq => Op::Ge,
+                    op => return Err(unsupported(format!("operator {op}"))),
+                };
+
+                // Value may be on either side, e.g. 100 < Price
+                match (literal(right), literal(left)) {
+                    (Some(value), _) => Expr::Compare {
+                        column: self.operand(left, clause)?,
+                        op,
+                        value,
+                    },
+                    (None, Some(value)) => Expr::Compare {
+                        column: self.operand(right, clause)?,
+                        op: flip(op),
+                        value,
+                    },
+                    (None, None) => {
+                        return Err(unsupported(format!("{expr}, expected column and value")))
+                    }
+                }
+            }
+            ast::Expr::IsNull(expr) => Expr::IsNull {
+                column: self.operand(expr, clause)?,
+            },
+            ast::Expr::IsNotNull(expr) => Expr::Not(Box::new(Expr::IsNull {
+                column: self.operand(expr, clause)?,
+            })),
+            ast::Expr::InList {
+                expr,
+                list,
+                negated,
+            } => negate(
+                Expr::In {
+                    column: self.operand(expr, clause)?,
+                    values: list.iter().map(value).collect::<Result<_, _>>()?,
+                },
+                *negated,
+            ),
+            ast::Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => negate(
+                Expr::Between {
+                    column: self.operand(expr, clause)?,
+                    low: value(low)?,
+                    high: value(high)?,
+                },
+                *negated,
+            ),
+            ast::Expr::Like {
+                negated,
+                any: false,
+                expr,
+                pattern,
+                escape_char: None,
+            } => {
+                let column = self.operand(expr, clause)?;
+
+                // Only prefix patterns map to starts_with
+                let like = match literal(pattern) {
+                    Some(Value::String(pattern)) => match pattern.strip_suffix('%') {
+                        Some(prefix) if !prefix.contains(['%', '_']) => Some(Expr::StartsWith {
+                            column,
+                            prefix: prefix.to_string(),
+                        }),
+                        None if !pattern.contains(['%', '_']) => Some(Expr::Compare {
+                            column,
+                            op: Op::Eq,
+                            value: Value::String(pattern),
+                        }),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+
+                match like {
+                    Some(like) => negate(like, *negated),
+                    None => {
+                        return Err(unsupported(format!(
+                            "{expr}, LIKE only supports prefix patterns like 'abc%'"
+                        )))
+                    }
+                }
+            }
+            expr => return Err(unsupported(format!("condition {expr}"))),
+        })
+    }
+
+    /// Column compared by condition
+    fn operand(&mut self, expr: &ast::Expr, clause: Clause) -> Result<String, ReaderError> {
+        match clause {
+            Clause::Where => column_name(expr, &self.tables).ok_or_else(|| match expr {
+                _ if is_aggregate(expr) => unsupported(format!("{expr} in WHERE, use HAVING")),
+                _ => unsupported(format!("{expr}, conditions compare a column to a value")),
+            }),
+            Clause::Having => self.alias(expr).map_or_else(|| self.key(expr), Ok),
+        }
+    }
+}
+
+/// Column named by identifier, without table name or alias qualifying it
+fn column_name(expr: &ast::Expr, tables: &[String]) -> Option<String> {
+    match expr {
+        ast::Expr::Identifier(ident) => Some(ident.value.clone()),
+        ast::Expr::CompoundIdentifier(idents) => {
+            let idents = match idents.split_first() {
+                Some((table, rest)) if tables.contains(&table.value) => rest,
+                _ => idents,
+            };
+
+            // Flattened names have fields, e.g. Levels.Qty
+            let names: Vec<&str> = idents.iter().map(|ident| ident.value.as_str()).collect();
+
+            Some(names.join("."))
+        }
+        ast::Expr::Nested(expr) => column_name(expr, tables),
+        _ => None,
+    }
+}
+
+fn function_name(function: &ast::Function) -> String {
+    function
+        .name
+        .0
+        .last()
+        .map_or_else(String::new, |name| name.value.to_ascii_lowercase())
+}
+
+fn is_aggregate(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Function(function) => matches!(
+            function_name(function).as_str(),
+            "count"
+                | "sum"
+                | "min"
+                | "max"
+                | "avg"
+                | "mean"
+                | "first"
+                | "first_value"
+                | "last"
+                | "last_value"
+        ),
+        ast::Expr::Nested(expr) => is_aggregate(expr),
+        _ => false,
+    }
+}
+
+/// Positional arguments of function, true if they are distinct
+fn arguments(function: &ast::Function) -> Result<(Vec<&FunctionArgExpr>, bool), ReaderError> {
+    if function.over.is_some() || function.filter.is_some() {
+        return Err(unsupported(format!(
+            "{function}, window and filter clauses"
+        )));
+    }
+
+    match &function.args {
+        FunctionArguments::List(list) if list.clauses.is_empty() => {
+            let args = list
+                .args
+                .iter()
+                .map(|arg| match arg {
+                    FunctionArg::Unnamed(arg) => Ok(arg),
+                    arg => Err(unsupported(format!("named argument {arg}"))),
+                })
+                .collect::<Result<_, _>>()?;
+
+            let distinct = list.duplicate_treatment == Some(DuplicateTreatment::Distinct);
+
+            Ok((args, distinct))
+        }
+        FunctionArguments::None => Ok((Vec::new(), false)),
+        _ => Err(unsupported(format!("arguments of {function}"))),
+    }
+}
+
+/// Constant value, None for other expressions
+fn literal(expr: &ast::Expr) -> Option<Value> {
+    match expr {
+        ast::Expr::Value(value) => match value {
+            ast::Value::Number(number, _) => Number::from_str(number).ok().map(Value::Number),
+            ast::Value::SingleQuotedString(text) => Some(Value::String(text.clone())),
+            ast::Value::Boolean(value) => Some(Value::Bool(*value)),
+            ast::Value::Null => Some(Value::Null),
+            _ => None,
+        },
+        ast::Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match literal(expr)? {
+            Value::Number(number) => Number::from_str(&format!("-{number}"))
+                .ok()
+                .map(Value::Number),
+            _ => None,
+        },
+        ast::Expr::Nested(expr) => literal(expr),
+        _ => None,
+    }
+}
+
+fn value(expr: &ast::Expr) -> Result<Value, ReaderError> {
+    literal(expr).ok_or_else(|| unsupported(format!("{expr}, expected a value")))
+}
+
+fn row_count(expr: &ast::Expr) -> Result<usize, ReaderError> {
+    match literal(expr) {
+        Some(Value::Number(number)) => number.as_u64().map(|number| number as usize),
+        _ => None,
+    }
+    .ok_or_else(|| unsupported(format!("{expr}, expected a row count")))
+}
+
+fn negate(expr: Expr, negated: bool) -> Expr {
+    match negated {
+        true => Expr::Not(Box::new(expr)),
+        false => expr,
+    }
+}
+
+/// Same comparison with sides swapped
+fn flip(op: Op) -> Op {
+    match op {
+        Op::Lt => Op::Gt,
+        Op::Le => Op::Ge,
+        Op::Gt => Op::Lt,
+        Op::Ge => Op::Le,
+        op => op,
+    }
+}
+
+/// True when only records are counted, which adapters do without decoding them
+fn counts_only(aggregation: &Aggregation) -> bool {
+    aggregation.group_by.is_empty()
+        && aggregation.bucket.is_none()
+        && aggregation.aggregates.iter().all(|aggregate| {
+            aggregate.function == AggregateFunction::Count && aggregate.column.is_none()
+        })
+}
+
+/// Orders values ascending, nulls and missing values last
+fn sort_order(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    let a = a.filter(|a| !a.is_null());
+    let b = b.filter(|b| !b.is_null());
+
+    match (a, b) {
+        (Some(a), Some(b)) => order(a, b).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::adapters::multi_native_adapter::tests::{config, packet, udp};
+
+    const TRADES: &str = "Token,Price,Qty,Time
+1,10.5,30,2024-01-01T00:00:10Z
+2,20.25,100,2024-01-01T00:00:40Z
+1,11.5,9,2024-01-01T00:01:10Z
+3,7,5,2024-01-01T00:01:20Z
+";
+
+    fn csv(selected: &[&str]) -> Reader {
+        let config: Config =
+            serde_json::from_value(json!({ "selected_columns": selected })).unwrap();
+
+        Reader::new_with_config(config, TRADES.as_bytes().to_vec(), Type::Csv)
+    }
+
+    fn query(reader: &Reader, sql: &str) -> Vec<Value> {
+        reader
+            .query(sql)
+            .unwrap()
+            .into_iter()
+            .map(Value::Object)
+            .collect()
+    }
+
+    #[test]
+    fn selects_filters_and_pages_records() {
+        let reader = csv(&[]);
+
+        assert_eq!(
+            query(
+                &reader,
+                "SELECT Token, Price AS p FROM t WHERE Qty >= 30 OR Token = 3 LIMIT 2 OFFSET 1"
+            ),
+            vec![
+                json!({"Token": "2", "p": "20.25"}),
+                json!({"Token": "3", "p": "7"})
+            ]
+        );
+        assert_eq!(query(&reader, "SELECT * FROM t WHERE Token = 3").len(), 1);
+    }
+
+    #[test]
+    fn aggregates_numeric_strings_by_value() {
+        let reader = csv(&[]);
+
+        assert_eq!(
+            query(
+                &reader,
+                "SELECT max(Qty), min(Qty), sum(Price), avg(Qty), count(*), count(Qty) FROM t"
+            ),
+            vec![json!({
+                "max(Qty)": "100",
+                "min(Qty)": "5",
+                "sum(Price)": "49.25",
+                "avg(Qty)": 36.0,
+                "count(*)": 4,
+                "count(Qty)": 4
+            })]
+        );
+    }
+
+    #[test]
+    fn groups_orders_and_filters_groups() {
+        let reader = csv(&[]);
+
+        assert_eq!(
+            query(
+                &reader,
+                "SELECT Token, count(*) AS n, sum(Qty) FROM t GROUP BY Token \
+                 HAVING n < 2 ORDER BY sum(Qty) DESC"
+            ),
+            vec![
+                json!({"Token": "2", "n": 1, "sum(Qty)": 100}),
+                json!({"Token": "3", "n": 1, "sum(Qty)": 5}),
+            ]
+        );
+
+        assert_eq!(
+            query(
+                &reader,
+                "SELECT time_bucket(60, Time) AS minute, count(*) FROM t GROUP BY time_bucket(60, Time)"
+            ),
+            vec![
+                json!({"minute": "2024-01-01T00:00:00Z", "count(*)": 2}),
+                json!({"minute": "2024-01-01T00:01:00Z", "count(*)": 2}),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_config_of_reader_as_is() {
+        let mut reader = csv(&["Token", "Qty"]);
+        reader.set_filter("Token != 3").unwrap();
+
+        assert_eq!(
+            query(&reader, "SELECT sum(Qty) FROM t WHERE Token = 1"),
+            vec![json!({"sum(Qty)": 39})]
+        );
+        assert_eq!(reader.config.selected_columns, ["Token", "Qty"]);
+        assert_eq!(reader.count().unwrap(), 3);
+    }
+
+    #[test]
+    fn columns_must_be_selected() {
+        let reader = csv(&["Token"]);
+
+        assert!(matches!(
+            reader.query("SELECT Price FROM t"),
+            Err(ReaderError::Config(message)) if message.contains("Price")
+        ));
+        assert!(matches!(
+            reader.query("DELETE FROM t"),
+            Err(ReaderError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn counts_rows_of_multi_native_files_after_packet_lists() {
+        let config = config(
+            json!({}),
+            json!({
+                "exclude": [2],
+                "column_details": {
+                    "1": {"skip_bytes": 4, "columns": [{"name": "Price", "dtype": "u8", "length": 1}]},
+                    "2": {"skip_bytes": 4, "columns": [{"name": "Qty", "dtype": "u8", "length": 1}]},
+                },
+            }),
+        );
+
+        let file = udp(0, &[packet(1, &[5]), packet(2, &[6]), packet(1, &[7])]);
+        let reader = Reader::new_with_config(config, file, Type::MultiNative);
+
+        assert_eq!(query(&reader, "SELECT * FROM t").len(), 2);
+        assert_eq!(
+            query(&reader, "SELECT count(*) FROM t"),
+            vec![json!({"count(*)": 2})]
+        );
+    }
+}